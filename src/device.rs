@@ -3,9 +3,16 @@
 use crate::ffi;
 use bit_vec::BitVec;
 
-use crate::{BaudRate, DepInfo, DepMode, Error, Modulation, Property, Result, Target};
+use crate::util;
+use crate::{
+    BaudRate, DepInfo, DepMode, Error, Mode, Modulation, ModulationType, PollProfile, Property,
+    Result, ScanReport, SelectCriteria, Target,
+};
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
+
+// per modulation, same as libnfc's nfc-list
+const MAX_SCANNED_TARGETS: usize = 16;
 
 pub struct Device<'context> {
     pub(crate) raw_device: *mut ffi::nfc_device,
@@ -26,6 +33,62 @@ impl<'context> Device<'context> {
             res => Err(Error::from(res)),
         }
     }
+
+    pub fn supported_modulations(&mut self, mode: Mode) -> Result<Vec<ModulationType>> {
+        let mut supported: *const ffi::nfc_modulation_type = std::ptr::null();
+        let res = unsafe {
+            ffi::nfc_device_get_supported_modulation(self.raw_device, mode, &mut supported)
+        };
+
+        if res < 0 {
+            Err(Error::from(res))
+        } else {
+            // Safety: libnfc gives us a static list terminated by 0, which isn't a
+            //  valid nfc_modulation_type, so it has to be read as integers.
+            Ok(unsafe {
+                util::read_terminated(supported as *const u32, util::modulation_type_from_raw)
+            })
+        }
+    }
+
+    /// Every legal modulation the device supports in the mode, at every baud
+    /// rate it supports it at.
+    pub fn supported_modulations_with_baud_rates(&mut self, mode: Mode) -> Result<Vec<Modulation>> {
+        let mut modulations = Vec::new();
+        for modulation_type in self.supported_modulations(mode)? {
+            for baud_rate in self.supported_baud_rates(modulation_type)? {
+                let raw = ffi::nfc_modulation {
+                    nmt: modulation_type,
+                    nbr: baud_rate,
+                };
+                if let Ok(modulation) = Modulation::try_from(raw) {
+                    modulations.push(modulation);
+                }
+            }
+        }
+        Ok(modulations)
+    }
+
+    pub fn supported_baud_rates(
+        &mut self,
+        modulation_type: ModulationType,
+    ) -> Result<Vec<BaudRate>> {
+        let mut supported: *const ffi::nfc_baud_rate = std::ptr::null();
+        let res = unsafe {
+            ffi::nfc_device_get_supported_baud_rate(
+                self.raw_device,
+                modulation_type,
+                &mut supported,
+            )
+        };
+
+        if res < 0 {
+            Err(Error::from(res))
+        } else {
+            // Safety: as above, terminated by NBR_UNDEFINED
+            Ok(unsafe { util::read_terminated(supported as *const u32, util::baud_rate_from_raw) })
+        }
+    }
 }
 
 pub struct SecureInitiator<'context>(Initiator<'context>);
@@ -103,6 +166,7 @@ impl<'context> Into<Result<Initiator<'context>>> for Device<'context> {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PollType {
     Limited(u8),
     Forever,
@@ -137,6 +201,10 @@ impl<'context> Initiator<'context> {
         let count;
         // Safety: this is safe because if we don't get a target we return empty
         let mut target: ffi::nfc_target = unsafe { std::mem::zeroed() };
+        let raw_modulations: Vec<ffi::nfc_modulation> = modulations
+            .iter()
+            .map(|modulation| (*modulation).into())
+            .collect();
         let pollnumber = match poll_number {
             PollType::Limited(len) => len,
             PollType::Forever => 0xFF,
@@ -147,8 +215,8 @@ impl<'context> Initiator<'context> {
             //  we are always able to safely make this call.
             count = ffi::nfc_initiator_poll_target(
                 self.device.raw_device,
-                modulations.as_ptr(),
-                modulations.len(),
+                raw_modulations.as_ptr(),
+                raw_modulations.len(),
                 pollnumber,
                 poll_period,
                 &mut target,
@@ -169,19 +237,35 @@ impl<'context> Initiator<'context> {
         }
     }
 
-    // TODO add a better modulations enum that takes care of the pbtInitData?
+    /// Polls for a target as described by the profile, returning `None` if
+    /// none showed up before it timed out.
+    pub fn poll(&mut self, profile: &PollProfile) -> Result<Option<Target>> {
+        profile.check()?;
+        match self.poll_target(
+            &profile.modulations,
+            profile.poll_number(),
+            profile.poll_period(),
+        )? {
+            TargetResultEnum::Found(found) => Ok(Some(found.target)),
+            TargetResultEnum::Empty => Ok(None),
+        }
+    }
+
     pub fn select_passive_target(
         &mut self,
         modulation: Modulation,
-        pbtInitData: &[u8],
+        criteria: &SelectCriteria,
     ) -> TargetResult {
+        criteria.check(modulation)?;
+        let init_data = criteria.init_data();
+
         let mut target: ffi::nfc_target = unsafe { std::mem::zeroed() };
         let count = unsafe {
             ffi::nfc_initiator_select_passive_target(
                 self.device.raw_device,
-                modulation,
-                pbtInitData.as_ptr(),
-                pbtInitData.len(),
+                modulation.into(),
+                init_data.as_ptr(),
+                init_data.len(),
                 &mut target,
             )
         };
@@ -214,7 +298,7 @@ impl<'context> Initiator<'context> {
             //  targets must be the right length as we check for the len and resize it.
             ffi::nfc_initiator_list_passive_targets(
                 self.device.raw_device,
-                modulation,
+                modulation.into(),
                 targets.as_mut_ptr(),
                 targets.len(),
             )
@@ -231,20 +315,41 @@ impl<'context> Initiator<'context> {
         }
     }
 
+    /// Lists the passive targets for every modulation the device supports,
+    /// and merges them into one report without duplicates.
+    ///
+    /// A modulation failing doesn't stop the scan, it's recorded in the
+    /// report instead.
+    pub fn scan_all(&mut self) -> Result<ScanReport> {
+        let mut report = ScanReport::new();
+        for modulation in self.supported_modulations_with_baud_rates(Mode::N_INITIATOR)? {
+            // DEP targets are found with select_dep_target instead
+            if modulation.modulation_type() == ModulationType::NMT_DEP {
+                continue;
+            }
+            match self.list_passive_targets(modulation, MAX_SCANNED_TARGETS) {
+                Ok(targets) => report.add(modulation, targets),
+                Err(error) => report.fail(modulation, error),
+            }
+        }
+        Ok(report)
+    }
+
     pub fn select_dep_target(
         &mut self,
         ndm: DepMode,
         nbr: BaudRate,
-        dep_info: DepInfo,
+        dep_info: &DepInfo,
         timeout: ffi::c_int,
     ) -> TargetResult {
+        let raw_dep_info = ffi::nfc_dep_info::try_from(dep_info)?;
         let mut target: ffi::nfc_target = unsafe { std::mem::zeroed() };
         let count = unsafe {
             ffi::nfc_initiator_select_dep_target(
                 self.device.raw_device,
                 ndm,
                 nbr,
-                &dep_info,
+                &raw_dep_info,
                 &mut target,
                 timeout,
             )
@@ -264,8 +369,14 @@ impl<'context> Initiator<'context> {
         }
     }
 
-    pub fn target_is_present(&mut self, target: Target) -> bool {
-        unsafe { ffi::nfc_initiator_target_is_present(self.device.raw_device, &target.into()) == 0 }
+    pub fn target_is_present(&mut self, target: &Target) -> bool {
+        match ffi::nfc_target::try_from(target) {
+            Ok(raw_target) => unsafe {
+                ffi::nfc_initiator_target_is_present(self.device.raw_device, &raw_target) == 0
+            },
+            // a target libnfc can't represent can't be in the field either
+            Err(_) => false,
+        }
     }
 
     pub fn last_target_is_present(&mut self) -> bool {
@@ -274,6 +385,9 @@ impl<'context> Initiator<'context> {
         }
     }
 
+    /// Sends a frame and returns the answer. `receive_size` is the most
+    /// bytes the answer may hold, but only the bytes actually received are
+    /// returned, not the whole buffer.
     pub fn transceive_bytes(
         &mut self,
         send: &[u8],
@@ -297,18 +411,23 @@ impl<'context> Initiator<'context> {
         if res < 0 {
             Err(Error::from(res))
         } else {
+            received.truncate(res.try_into().unwrap());
             Ok(received)
         }
     }
 
+    /// Sends a raw frame of `send.len()` bits with one parity bit per byte,
+    /// as needed with `NP_HANDLE_PARITY` disabled. At most `receive_size`
+    /// bytes are received, not bits. Returns the bytes received, the last one
+    /// possibly partial, and one parity bit per byte.
     pub fn transceive_bits(
         &mut self,
         send: &BitVec,
         parity_bits: &BitVec,
-        receive_bits: ffi::size_t,
+        receive_size: ffi::size_t,
     ) -> Result<(BitVec, BitVec)> {
-        let mut received: Vec<u8> = vec![0; receive_bits];
-        let mut parity: Vec<u8> = vec![0; receive_bits / 8];
+        let mut received: Vec<u8> = vec![0; receive_size];
+        let mut parity: Vec<u8> = vec![0; receive_size];
         let res = unsafe {
             ffi::nfc_initiator_transceive_bits(
                 self.device.raw_device,
@@ -329,9 +448,12 @@ impl<'context> Initiator<'context> {
         if res < 0 {
             Err(Error::from(res))
         } else {
+            let bits: usize = res.try_into().unwrap();
+            received.truncate(bits.div_ceil(8));
+            parity.truncate(received.len());
             Ok((
-                BitVec::from_bytes(&mut received),
-                BitVec::from_bytes(&mut parity),
+                BitVec::from_bytes(&received),
+                parity.iter().map(|&bit| bit != 0).collect(),
             ))
         }
     }
@@ -0,0 +1,503 @@
+//! Human readable output for targets, mirroring libnfc's `str_nfc_target` so
+//! that our tools print the same thing as `nfc-list`.
+
+use crate::ffi::{nfc_baud_rate, nfc_dep_mode, nfc_modulation_type};
+use crate::target_info::*;
+use crate::{Target, TargetInfo};
+
+use std::fmt;
+use std::time::Duration;
+
+/// Formats a target with the extra breakdown `nfc-list -v` prints. Created
+/// by [`Target::verbose`](struct.Target.html#method.verbose), and also used
+/// when formatting a `Target` with `{:#}`.
+pub struct VerboseTarget<'a>(pub(crate) &'a Target);
+
+impl fmt::Display for nfc_modulation_type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            nfc_modulation_type::NMT_ISO14443A => "ISO/IEC 14443A",
+            nfc_modulation_type::NMT_ISO14443B => "ISO/IEC 14443-4B",
+            nfc_modulation_type::NMT_ISO14443BI => "ISO/IEC 14443-4B'",
+            nfc_modulation_type::NMT_ISO14443BICLASS => "ISO/IEC 14443-2B-3B iClass (Picopass)",
+            nfc_modulation_type::NMT_ISO14443B2CT => "ISO/IEC 14443-2B ASK CTx",
+            nfc_modulation_type::NMT_ISO14443B2SR => "ISO/IEC 14443-2B ST SRx",
+            nfc_modulation_type::NMT_FELICA => "FeliCa",
+            nfc_modulation_type::NMT_JEWEL => "Innovision Jewel",
+            nfc_modulation_type::NMT_BARCODE => "Thinfilm NFC Barcode",
+            nfc_modulation_type::NMT_DEP => "D.E.P.",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for nfc_baud_rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            nfc_baud_rate::NBR_UNDEFINED => "undefined baud rate",
+            nfc_baud_rate::NBR_106 => "106 kbps",
+            nfc_baud_rate::NBR_212 => "212 kbps",
+            nfc_baud_rate::NBR_424 => "424 kbps",
+            nfc_baud_rate::NBR_847 => "847 kbps",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_target(f, self, f.alternate())
+    }
+}
+
+impl<'a> fmt::Display for VerboseTarget<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_target(f, self.0, true)
+    }
+}
+
+fn write_target(f: &mut fmt::Formatter<'_>, target: &Target, verbose: bool) -> fmt::Result {
+    write!(f, "{} ({}", target.info.modulation_type(), target.baud_rate)?;
+    if let TargetInfo::DEP { info } = &target.info {
+        match info.mode {
+            nfc_dep_mode::NDM_ACTIVE => write!(f, ", active mode")?,
+            _ => write!(f, ", passive mode")?,
+        }
+    }
+    writeln!(f, ") target:")?;
+
+    match &target.info {
+        TargetInfo::ISO14443A { info } => write_iso14443a(f, info, verbose),
+        TargetInfo::FELICA { info } => write_felica(f, info),
+        TargetInfo::ISO14443B { info } => write_iso14443b(f, info, verbose),
+        TargetInfo::ISO14443BI { info } => write_iso14443bi(f, info, verbose),
+        TargetInfo::ISO14443BICLASS { info } => write_labeled_hex(f, "UID", &info.uid),
+        TargetInfo::ISO14443B2SR { info } => write_labeled_hex(f, "UID", &info.uid),
+        TargetInfo::ISO14443B2CT { info } => write_iso14443b2ct(f, info),
+        TargetInfo::JEWEL { info } => {
+            write_labeled_hex(f, "ATQA (SENS_RES)", &info.sens_res)?;
+            write_labeled_hex(f, "4-LSB JEWELID", &info.id)
+        }
+        TargetInfo::BARCODE { info } => write_barcode(f, info),
+        TargetInfo::DEP { info } => write_dep(f, info),
+    }
+}
+
+/// Same layout as libnfc's `snprint_hex`: every byte followed by two spaces.
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    for byte in bytes {
+        write!(f, "{:02x}  ", byte)?;
+    }
+    writeln!(f)
+}
+
+fn write_labeled_hex(f: &mut fmt::Formatter<'_>, label: &str, bytes: &[u8]) -> fmt::Result {
+    write!(f, "{:>19}: ", label)?;
+    write_hex(f, bytes)
+}
+
+/// Equivalent of printf's `%.4g`, which libnfc uses for timings.
+fn four_significant_digits(value: f64) -> String {
+    // the logarithm of zero has no magnitude to count digits from
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    let magnitude = value.abs().log10().floor() as i32;
+    let decimals = std::cmp::max(0, 3 - magnitude) as usize;
+    let formatted = format!("{:.*}", decimals, value);
+    if formatted.contains('.') {
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    } else {
+        formatted
+    }
+}
+
+fn milliseconds(duration: Duration) -> String {
+    four_significant_digits(duration.as_secs_f64() * 1000.0)
+}
+
+fn write_iso14443a(f: &mut fmt::Formatter<'_>, info: &Iso14443aInfo, verbose: bool) -> fmt::Result {
+    write_labeled_hex(f, "ATQA (SENS_RES)", &info.atqa)?;
+    if verbose {
+        let uid_size = match (info.atqa[1] & 0xc0) >> 6 {
+            0 => "single",
+            1 => "double",
+            2 => "triple",
+            _ => "RFU",
+        };
+        writeln!(f, "* UID size: {}", uid_size)?;
+        match info.atqa[1] & 0x1f {
+            0x01 | 0x02 | 0x04 | 0x08 | 0x10 => writeln!(f, "* bit frame anticollision supported")?,
+            _ => writeln!(f, "* bit frame anticollision not supported")?,
+        }
+    }
+
+    let random_uid = info.uid.first() == Some(&0x08);
+    write_labeled_hex(
+        f,
+        if random_uid {
+            "UID (NFCID3)"
+        } else {
+            "UID (NFCID1)"
+        },
+        &info.uid,
+    )?;
+    if verbose && random_uid {
+        writeln!(f, "* Random UID")?;
+    }
+
+    write_labeled_hex(f, "SAK (SEL_RES)", &[info.sak])?;
+    if verbose {
+        if info.sak & 0x04 != 0 {
+            writeln!(f, "* Warning! Cascade bit set: UID not complete")?;
+        }
+        if info.sak & 0x20 != 0 {
+            writeln!(f, "* Compliant with ISO/IEC 14443-4")?;
+        } else {
+            writeln!(f, "* Not compliant with ISO/IEC 14443-4")?;
+        }
+        if info.sak & 0x40 != 0 {
+            writeln!(f, "* Compliant with ISO/IEC 18092")?;
+        } else {
+            writeln!(f, "* Not compliant with ISO/IEC 18092")?;
+        }
+    }
+
+    if let Some(ats) = &info.ats {
+        write_labeled_hex(f, "ATS", ats.as_bytes())?;
+        if verbose {
+            write_ats(f, ats)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes the ATS according to ISO14443-4 section 5.2.
+fn write_ats(f: &mut fmt::Formatter<'_>, ats: &Ats) -> fmt::Result {
+    if ats.t0().is_none() {
+        return Ok(());
+    }
+    writeln!(
+        f,
+        "* Max Frame Size accepted by PICC: {} bytes",
+        ats.max_frame_size()
+    )?;
+
+    if ats.ta().is_some() {
+        write_bit_rates(
+            f,
+            ats.bit_rates(),
+            "  ",
+            "DS=2",
+            "DS=4",
+            "DS=8",
+            "DR=2",
+            "DR=4",
+            "DR=8",
+        )?;
+    }
+    if ats.tb().is_some() {
+        writeln!(
+            f,
+            "* Frame Waiting Time: {} ms",
+            milliseconds(ats.frame_waiting_time())
+        )?;
+        match ats.startup_frame_guard_time() {
+            Some(sfgt) => writeln!(f, "* Start-up Frame Guard Time: {} ms", milliseconds(sfgt))?,
+            None => writeln!(f, "* No Start-up Frame Guard Time required")?,
+        }
+    }
+    if ats.tc().is_some() {
+        if ats.nad_supported() {
+            writeln!(f, "* Node Address supported")?;
+        } else {
+            writeln!(f, "* Node Address not supported")?;
+        }
+        if ats.cid_supported() {
+            writeln!(f, "* Card IDentifier supported")?;
+        } else {
+            writeln!(f, "* Card IDentifier not supported")?;
+        }
+    }
+    if !ats.historical_bytes().is_empty() {
+        write!(f, "* Historical bytes Tk: ")?;
+        write_hex(f, ats.historical_bytes())?;
+    }
+
+    Ok(())
+}
+
+// TA(1) and the first protocol info byte of ATQB share a layout, only the
+// wording for the divisors differs between A and B.
+#[allow(clippy::too_many_arguments)]
+fn write_bit_rates(
+    f: &mut fmt::Formatter<'_>,
+    rates: BitRates,
+    indent: &str,
+    to_pcd_212: &str,
+    to_pcd_424: &str,
+    to_pcd_847: &str,
+    to_picc_212: &str,
+    to_picc_424: &str,
+    to_picc_847: &str,
+) -> fmt::Result {
+    let rates = rates.0;
+    writeln!(f, "* Bit Rate Capability:")?;
+    if rates == 0 {
+        writeln!(
+            f,
+            "{}* PICC supports only 106 kbits/s in both directions",
+            indent
+        )?;
+    }
+    if rates & 0x80 != 0 {
+        writeln!(f, "{}* Same bitrate in both directions mandatory", indent)?;
+    }
+    let supported = [
+        (0x10, "PICC to PCD", to_pcd_212, 212),
+        (0x20, "PICC to PCD", to_pcd_424, 424),
+        (0x40, "PICC to PCD", to_pcd_847, 847),
+        (0x01, "PCD to PICC", to_picc_212, 212),
+        (0x02, "PCD to PICC", to_picc_424, 424),
+        (0x04, "PCD to PICC", to_picc_847, 847),
+    ];
+    for (bit, direction, divisor, rate) in supported.iter() {
+        if rates & bit != 0 {
+            writeln!(
+                f,
+                "{}* {}, {}, bitrate {} kbits/s supported",
+                indent, direction, divisor, rate
+            )?;
+        }
+    }
+    if rates & 0x08 != 0 {
+        writeln!(f, "{}* ERROR unknown value", indent)?;
+    }
+    Ok(())
+}
+
+fn write_felica(f: &mut fmt::Formatter<'_>, info: &FelicaInfo) -> fmt::Result {
+    write_labeled_hex(f, "ID (NFCID2)", &info.idm)?;
+    write_labeled_hex(f, "Parameter (PAD)", &info.pmm)?;
+    // libnfc prints zeros when the system code wasn't asked for
+    write_labeled_hex(f, "System Code (SC)", &info.system_code.unwrap_or_default())
+}
+
+fn write_iso14443b(f: &mut fmt::Formatter<'_>, info: &Iso14443bInfo, verbose: bool) -> fmt::Result {
+    write_labeled_hex(f, "PUPI", &info.pupi)?;
+    write_labeled_hex(f, "Application Data", &info.application_data)?;
+    write_labeled_hex(f, "Protocol Info", &info.protocol_info)?;
+    if !verbose {
+        return Ok(());
+    }
+
+    let protocol = info.protocol();
+    write_bit_rates(
+        f,
+        protocol.bit_rates(),
+        " ",
+        "1etu=64/fc",
+        "1etu=32/fc",
+        "1etu=16/fc",
+        "1etu=64/fc",
+        "1etu=32/fc",
+        "1etu=16/fc",
+    )?;
+    writeln!(
+        f,
+        "* Maximum frame sizes: {} bytes",
+        protocol.max_frame_size()
+    )?;
+    if protocol.iso14443_4_compliant() {
+        writeln!(f, "* Protocol types supported: ISO/IEC 14443-4")?;
+    }
+    writeln!(
+        f,
+        "* Frame Waiting Time: {} ms",
+        milliseconds(protocol.frame_waiting_time())
+    )?;
+    if protocol.nad_supported() || protocol.cid_supported() {
+        write!(f, "* Frame options supported: ")?;
+        if protocol.nad_supported() {
+            write!(f, "NAD ")?;
+        }
+        if protocol.cid_supported() {
+            write!(f, "CID ")?;
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+fn write_iso14443bi(
+    f: &mut fmt::Formatter<'_>,
+    info: &Iso14443biInfo,
+    verbose: bool,
+) -> fmt::Result {
+    write_labeled_hex(f, "DIV", &info.div)?;
+    if verbose {
+        let version = (info.ver_log & 0x1e) >> 1;
+        writeln!(f, "{:>19}: {}", "Software Version", version)?;
+        if version == 7 && info.ver_log & 0x80 != 0 {
+            writeln!(f, "{:>19}: yes", "Wait Enable")?;
+        }
+    }
+    if info.ver_log & 0x80 != 0 && info.config & 0x80 != 0 {
+        write_labeled_hex(f, "ATS", &info.atr)?;
+    }
+    Ok(())
+}
+
+fn write_iso14443b2ct(f: &mut fmt::Formatter<'_>, info: &Iso14443b2ctInfo) -> fmt::Result {
+    write_labeled_hex(f, "UID", &info.uid)?;
+    writeln!(
+        f,
+        "{:>19}: {:010}",
+        "UID (decimal)",
+        u32::from_le_bytes(info.uid)
+    )?;
+    writeln!(f, "{:>19}: {:02X}", "Product Code", info.product_code)?;
+    writeln!(f, "{:>19}: {:02X}", "Fab Code", info.fab_code)
+}
+
+fn write_barcode(f: &mut fmt::Formatter<'_>, info: &BarcodeInfo) -> fmt::Result {
+    writeln!(f, "{:>19}: {}", "Size (bits)", info.data.len() * 8)?;
+    write!(f, "{:>19}: ", "Content")?;
+    for (i, byte) in info.data.iter().enumerate() {
+        write!(f, "{:02X}", byte)?;
+        if i % 8 == 7 && i + 1 < info.data.len() {
+            write!(f, "\n{:21}", "")?;
+        }
+    }
+    writeln!(f)
+}
+
+fn write_dep(f: &mut fmt::Formatter<'_>, info: &DepInfo) -> fmt::Result {
+    write!(f, "       NFCID3: ")?;
+    write_hex(f, &info.nfcid3)?;
+    writeln!(f, "           BS: {:02x}", info.bs)?;
+    writeln!(f, "           BR: {:02x}", info.br)?;
+    writeln!(f, "           TO: {:02x}", info.timeout)?;
+    writeln!(f, "           PP: {:02x}", info.pp)?;
+    if !info.general_bytes.is_empty() {
+        write!(f, "General Bytes: ")?;
+        write_hex(f, &info.general_bytes)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BaudRate;
+
+    // `nfc-list` and `nfc-list -v` on a DESFire EV1
+    const DESFIRE: &str = "\
+ISO/IEC 14443A (106 kbps) target:
+    ATQA (SENS_RES): 03  44  
+       UID (NFCID1): 04  65  2d  72  ba  2b  80  
+      SAK (SEL_RES): 20  
+                ATS: 75  77  81  02  80  
+";
+    // libnfc goes on with the fingerprinting that `identify` does here
+    const DESFIRE_VERBOSE: &str = "\
+ISO/IEC 14443A (106 kbps) target:
+    ATQA (SENS_RES): 03  44  
+* UID size: double
+* bit frame anticollision supported
+       UID (NFCID1): 04  65  2d  72  ba  2b  80  
+      SAK (SEL_RES): 20  
+* Compliant with ISO/IEC 14443-4
+* Not compliant with ISO/IEC 18092
+                ATS: 75  77  81  02  80  
+* Max Frame Size accepted by PICC: 64 bytes
+* Bit Rate Capability:
+  * PICC to PCD, DS=2, bitrate 212 kbits/s supported
+  * PICC to PCD, DS=4, bitrate 424 kbits/s supported
+  * PICC to PCD, DS=8, bitrate 847 kbits/s supported
+  * PCD to PICC, DR=2, bitrate 212 kbits/s supported
+  * PCD to PICC, DR=4, bitrate 424 kbits/s supported
+  * PCD to PICC, DR=8, bitrate 847 kbits/s supported
+* Frame Waiting Time: 77.33 ms
+* Start-up Frame Guard Time: 0.6041 ms
+* Node Address not supported
+* Card IDentifier supported
+* Historical bytes Tk: 80  
+";
+
+    fn desfire() -> Target {
+        Target {
+            baud_rate: BaudRate::NBR_106,
+            info: TargetInfo::ISO14443A {
+                info: Iso14443aInfo {
+                    atqa: [0x03, 0x44],
+                    sak: 0x20,
+                    uid: vec![0x04, 0x65, 0x2d, 0x72, 0xba, 0x2b, 0x80],
+                    ats: Some(Ats::new(vec![0x75, 0x77, 0x81, 0x02, 0x80])),
+                },
+            },
+        }
+    }
+
+    fn felica(system_code: Option<[u8; 2]>) -> Target {
+        Target {
+            baud_rate: BaudRate::NBR_212,
+            info: TargetInfo::FELICA {
+                info: FelicaInfo {
+                    response_code: 0x01,
+                    idm: [0x01, 0x2e, 0x4c, 0xcd, 0x7e, 0x83, 0x26, 0x07],
+                    pmm: [0x03, 0x01, 0x4b, 0x02, 0x4f, 0x49, 0x93, 0xff],
+                    system_code,
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn iso14443a() {
+        let target = desfire();
+        assert_eq!(target.to_string(), DESFIRE);
+        assert_eq!(target.verbose().to_string(), DESFIRE_VERBOSE);
+        assert_eq!(format!("{:#}", target), DESFIRE_VERBOSE);
+    }
+
+    #[test]
+    fn felica_target() {
+        let expected = "\
+FeliCa (212 kbps) target:
+        ID (NFCID2): 01  2e  4c  cd  7e  83  26  07  
+    Parameter (PAD): 03  01  4b  02  4f  49  93  ff  
+   System Code (SC): 88  b4  
+";
+        let target = felica(Some([0x88, 0xb4]));
+        assert_eq!(target.to_string(), expected);
+        // there's nothing more to say about FeliCa in verbose mode
+        assert_eq!(target.verbose().to_string(), expected);
+        assert_eq!(
+            felica(None).to_string(),
+            expected.replace("88  b4", "00  00")
+        );
+    }
+
+    #[test]
+    fn significant_digits() {
+        assert_eq!(four_significant_digits(0.0), "0");
+        assert_eq!(four_significant_digits(256.0 * 16.0 / 13560.0), "0.3021");
+        assert_eq!(
+            four_significant_digits(256.0 * 16.0 * 256.0 / 13560.0),
+            "77.33"
+        );
+        assert_eq!(
+            four_significant_digits(256.0 * 16.0 * 16384.0 / 13560.0),
+            "4949"
+        );
+        assert_eq!(four_significant_digits(1.5), "1.5");
+        assert_eq!(four_significant_digits(2.0), "2");
+        assert_eq!(milliseconds(Duration::from_micros(604)), "0.604");
+        assert_eq!(milliseconds(Duration::from_secs(0)), "0");
+    }
+}
@@ -15,7 +15,7 @@ trait Successful {
 }
 
 #[repr(i32)]
-#[derive(Debug, Primitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Primitive)]
 pub enum ErrorKind {
     Success = SUCCESS,
     InputOutput = ffi::NFC_EIO,
@@ -87,6 +87,14 @@ impl NfcError {
             details: message.to_string(),
         }
     }
+
+    /// The libnfc error code, if the error came from libnfc.
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            NfcError::FfiError { error } => Some(error.error),
+            NfcError::UnknownError { .. } => None,
+        }
+    }
 }
 
 impl From<i32> for NfcError {
@@ -0,0 +1,428 @@
+//! Card identification from the anticollision data, following NXP's AN10833
+//! "MIFARE type identification procedure", with optional active probing for
+//! the families that share ATQA and SAK.
+
+use crate::device::{Initiator, TargetResultEnum};
+use crate::ffi;
+use crate::target_info::{Ats, FelicaInfo, Iso14443aInfo};
+use crate::{Error, Modulation, Result, SelectCriteria, Target, TargetInfo};
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CardType {
+    MifareMini,
+    MifareClassic1k,
+    MifareClassic4k,
+    /// ATQA and SAK of the Ultralight family, which needs active probing to
+    /// tell apart.
+    MifareUltralightFamily,
+    MifareUltralight,
+    MifareUltralightC,
+    MifareUltralightEv1,
+    Ntag210,
+    Ntag212,
+    Ntag213,
+    Ntag215,
+    Ntag216,
+    NtagI2c1k,
+    NtagI2c2k,
+    /// ATQA and SAK of a DESFire, which needs active probing to find the
+    /// generation.
+    MifareDesfireFamily,
+    MifareDesfire,
+    MifareDesfireEv1,
+    MifareDesfireEv2,
+    MifareDesfireEv3,
+    MifarePlusSl1,
+    MifarePlusSl2,
+    MifarePlusSl3,
+    SmartMx,
+    Jcop,
+    InfineonMifareClassic1k,
+    InfineonMyD,
+    FelicaStandard,
+    FelicaMobile,
+    FelicaLite,
+    FelicaLiteS,
+    FelicaPlug,
+    FelicaLink,
+    Unknown,
+}
+
+impl fmt::Display for CardType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CardType::MifareMini => "MIFARE Mini 0.3K",
+            CardType::MifareClassic1k => "MIFARE Classic 1K",
+            CardType::MifareClassic4k => "MIFARE Classic 4K",
+            CardType::MifareUltralightFamily => "MIFARE Ultralight family",
+            CardType::MifareUltralight => "MIFARE Ultralight",
+            CardType::MifareUltralightC => "MIFARE Ultralight C",
+            CardType::MifareUltralightEv1 => "MIFARE Ultralight EV1",
+            CardType::Ntag210 => "NTAG210",
+            CardType::Ntag212 => "NTAG212",
+            CardType::Ntag213 => "NTAG213",
+            CardType::Ntag215 => "NTAG215",
+            CardType::Ntag216 => "NTAG216",
+            CardType::NtagI2c1k => "NTAG I2C 1K",
+            CardType::NtagI2c2k => "NTAG I2C 2K",
+            CardType::MifareDesfireFamily => "MIFARE DESFire family",
+            CardType::MifareDesfire => "MIFARE DESFire",
+            CardType::MifareDesfireEv1 => "MIFARE DESFire EV1",
+            CardType::MifareDesfireEv2 => "MIFARE DESFire EV2",
+            CardType::MifareDesfireEv3 => "MIFARE DESFire EV3",
+            CardType::MifarePlusSl1 => "MIFARE Plus, Security level 1",
+            CardType::MifarePlusSl2 => "MIFARE Plus, Security level 2",
+            CardType::MifarePlusSl3 => "MIFARE Plus, Security level 3",
+            CardType::SmartMx => "SmartMX",
+            CardType::Jcop => "JCOP",
+            CardType::InfineonMifareClassic1k => "Infineon MIFARE Classic 1K",
+            CardType::InfineonMyD => "Infineon my-d move",
+            CardType::FelicaStandard => "FeliCa Standard",
+            CardType::FelicaMobile => "Mobile FeliCa",
+            CardType::FelicaLite => "FeliCa Lite",
+            CardType::FelicaLiteS => "FeliCa Lite-S",
+            CardType::FelicaPlug => "FeliCa Plug",
+            CardType::FelicaLink => "FeliCa Link",
+            CardType::Unknown => "Unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+struct Fingerprint {
+    atqa: u16,
+    atqa_mask: u16,
+    sak: u8,
+    card: CardType,
+}
+
+const fn fingerprint(atqa: u16, atqa_mask: u16, sak: u8, card: CardType) -> Fingerprint {
+    Fingerprint {
+        atqa,
+        atqa_mask,
+        sak,
+        card,
+    }
+}
+
+// AN10833 tables 4 and 5. The first match wins, so more specific entries go
+// first.
+const FINGERPRINTS: &[Fingerprint] = &[
+    fingerprint(0x0044, 0xffff, 0x00, CardType::MifareUltralightFamily),
+    fingerprint(0x0004, 0xffbf, 0x09, CardType::MifareMini),
+    fingerprint(0x0004, 0xffbf, 0x08, CardType::MifareClassic1k),
+    fingerprint(0x0002, 0xffbf, 0x18, CardType::MifareClassic4k),
+    fingerprint(0x0004, 0xffbf, 0x10, CardType::MifarePlusSl2),
+    fingerprint(0x0002, 0xffbf, 0x11, CardType::MifarePlusSl2),
+    fingerprint(0x0004, 0xffff, 0x88, CardType::InfineonMifareClassic1k),
+    fingerprint(0x0344, 0xffff, 0x20, CardType::MifareDesfireFamily),
+    // Plus in SL3 shares these with SmartMX, the ATS tells them apart
+    fingerprint(0x0004, 0xf0bf, 0x20, CardType::SmartMx),
+    fingerprint(0x0002, 0xf0bf, 0x20, CardType::SmartMx),
+    fingerprint(0x0048, 0xf0ff, 0x20, CardType::SmartMx),
+    fingerprint(0x0004, 0xf0bf, 0x28, CardType::SmartMx),
+    fingerprint(0x0002, 0xf0bf, 0x38, CardType::SmartMx),
+    fingerprint(0x0048, 0xf0ff, 0x28, CardType::SmartMx),
+    fingerprint(0x0048, 0xf0ff, 0x38, CardType::SmartMx),
+];
+
+// ISO/IEC 7816-6 IC manufacturer code, the first byte of double size UIDs
+const MANUFACTURER_INFINEON: u8 = 0x05;
+
+const GET_VERSION: u8 = 0x60;
+const ADDITIONAL_FRAME: u8 = 0xAF;
+const ULTRALIGHT_C_AUTHENTICATE: u8 = 0x1A;
+
+/// Identifies a target from the data it gave during anticollision only.
+///
+/// For Ultralight-like and DESFire targets the result is the family, use
+/// [`identify_active`](fn.identify_active.html) to narrow it down.
+pub fn identify(target: &Target) -> CardType {
+    match &target.info {
+        TargetInfo::ISO14443A { info } => identify_iso14443a(info),
+        TargetInfo::FELICA { info } => identify_felica(info),
+        _ => CardType::Unknown,
+    }
+}
+
+fn identify_iso14443a(info: &Iso14443aInfo) -> CardType {
+    let atqa = u16::from_be_bytes(info.atqa);
+    let card = FINGERPRINTS
+        .iter()
+        .find(|print| atqa & print.atqa_mask == print.atqa && info.sak == print.sak)
+        .map_or(CardType::Unknown, |print| print.card);
+
+    if card == CardType::MifareUltralightFamily
+        && info.uid.len() == 7
+        && info.uid[0] == MANUFACTURER_INFINEON
+    {
+        return CardType::InfineonMyD;
+    }
+
+    match &info.ats {
+        Some(ats) => refine_by_ats(card, info.sak, ats),
+        None => card,
+    }
+}
+
+fn refine_by_ats(card: CardType, sak: u8, ats: &Ats) -> CardType {
+    let historical = ats.historical_bytes();
+    if historical.windows(4).any(|window| window == b"JCOP") {
+        return CardType::Jcop;
+    }
+
+    // NXP's proprietary historical bytes: tag 0xC1, length, then the chip type
+    // whose high nibble is 1 for DESFire and 2 for Plus
+    match historical {
+        [0xC1, _, chip_type, ..] => match chip_type >> 4 {
+            0x1 => CardType::MifareDesfireFamily,
+            // SAK still advertising MIFARE Classic means it's running in SL1
+            0x2 if sak & 0x08 != 0 => CardType::MifarePlusSl1,
+            0x2 => CardType::MifarePlusSl3,
+            _ => card,
+        },
+        _ => card,
+    }
+}
+
+fn identify_felica(info: &FelicaInfo) -> CardType {
+    // the second byte of PMm is the IC type
+    match info.pmm[1] {
+        0xE0 => CardType::FelicaPlug,
+        0xE1 | 0xF2 => CardType::FelicaLink,
+        0xF0 => CardType::FelicaLite,
+        0xF1 => CardType::FelicaLiteS,
+        0x06..=0x07 | 0x10..=0x1F => CardType::FelicaMobile,
+        _ => CardType::FelicaStandard,
+    }
+}
+
+/// Identifies a target like [`identify`](fn.identify.html), then resolves
+/// the Ultralight and DESFire families by sending GET_VERSION (and for
+/// Ultralight C, the start of an authentication) to the card.
+///
+/// Cards drop out of the active state when they don't understand a command,
+/// so the target is reselected by UID after a failed probe. It's left
+/// selected when this returns successfully.
+pub fn identify_active(initiator: &mut Initiator, target: &Target) -> Result<CardType> {
+    let card = identify(target);
+    match card {
+        CardType::MifareUltralightFamily => probe_ultralight(initiator, target),
+        CardType::MifareDesfireFamily => {
+            probe_desfire(initiator, target).map(|found| found.unwrap_or(card))
+        }
+        _ => Ok(card),
+    }
+}
+
+fn probe_ultralight(initiator: &mut Initiator, target: &Target) -> Result<CardType> {
+    if let Ok(version) = initiator.transceive_bytes(&[GET_VERSION], 8, -1) {
+        return Ok(ultralight_from_version(&version));
+    }
+    reselect(initiator, target)?;
+
+    // only the C answers the first step of its 3DES authentication
+    match initiator.transceive_bytes(&[ULTRALIGHT_C_AUTHENTICATE, 0x00], 9, -1) {
+        Ok(ref response) if response.first() == Some(&ADDITIONAL_FRAME) => {
+            reselect(initiator, target)?;
+            Ok(CardType::MifareUltralightC)
+        }
+        _ => {
+            reselect(initiator, target)?;
+            Ok(CardType::MifareUltralight)
+        }
+    }
+}
+
+/// Decodes the vendor, product type, subtype, version and storage size
+/// returned by GET_VERSION on Ultralight EV1 and NTAG.
+fn ultralight_from_version(version: &[u8]) -> CardType {
+    match version {
+        [_, 0x04, 0x03, _, _, _, 0x0B, _] | [_, 0x04, 0x03, _, _, _, 0x0E, _] => {
+            CardType::MifareUltralightEv1
+        }
+        [_, 0x04, 0x04, 0x05, _, _, 0x13, _] => CardType::NtagI2c1k,
+        [_, 0x04, 0x04, 0x05, _, _, 0x15, _] => CardType::NtagI2c2k,
+        [_, 0x04, 0x04, _, _, _, 0x0B, _] => CardType::Ntag210,
+        [_, 0x04, 0x04, _, _, _, 0x0E, _] => CardType::Ntag212,
+        [_, 0x04, 0x04, _, _, _, 0x0F, _] => CardType::Ntag213,
+        [_, 0x04, 0x04, _, _, _, 0x11, _] => CardType::Ntag215,
+        [_, 0x04, 0x04, _, _, _, 0x13, _] => CardType::Ntag216,
+        _ => CardType::MifareUltralightFamily,
+    }
+}
+
+fn probe_desfire(initiator: &mut Initiator, target: &Target) -> Result<Option<CardType>> {
+    let hardware = match initiator.transceive_bytes(&[GET_VERSION], 8, -1) {
+        Ok(hardware) => hardware,
+        Err(_) => {
+            reselect(initiator, target)?;
+            return Ok(None);
+        }
+    };
+    let card = match hardware.as_slice() {
+        // status, vendor, type, subtype, major version
+        [ADDITIONAL_FRAME, 0x04, 0x01, _, major, ..] => match major >> 4 {
+            0x0 if *major == 0 => Some(CardType::MifareDesfire),
+            0x0 => Some(CardType::MifareDesfireEv1),
+            0x1 | 0x2 => Some(CardType::MifareDesfireEv2),
+            0x3 => Some(CardType::MifareDesfireEv3),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    // fetch the software and production frames so the card is left ready
+    // for the next command
+    if hardware.first() == Some(&ADDITIONAL_FRAME) {
+        initiator.transceive_bytes(&[ADDITIONAL_FRAME], 8, -1)?;
+        initiator.transceive_bytes(&[ADDITIONAL_FRAME], 15, -1)?;
+    }
+
+    Ok(card)
+}
+
+fn reselect(initiator: &mut Initiator, target: &Target) -> Result<()> {
+    let uid = match &target.info {
+        TargetInfo::ISO14443A { info } => &info.uid,
+        _ => return Err(Error::new("Only ISO14443A targets can be reselected")),
+    };
+    let criteria = SelectCriteria::Iso14443a { uid: uid.clone() };
+    match initiator.select_passive_target(Modulation::iso14443a(), &criteria)? {
+        TargetResultEnum::Found(_) => Ok(()),
+        TargetResultEnum::Empty => Err(Error::from(ffi::NFC_ETGRELEASED)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::decode_hex;
+
+    fn info(atqa: u16, sak: u8, uid: &str, ats: Option<&str>) -> Iso14443aInfo {
+        Iso14443aInfo {
+            atqa: atqa.to_be_bytes(),
+            sak,
+            uid: decode_hex(uid).unwrap(),
+            ats: ats.map(|ats| Ats::new(decode_hex(ats).unwrap())),
+        }
+    }
+
+    #[test]
+    fn fingerprints() {
+        // AN10833 table 4, with single and double size UIDs
+        let cases = [
+            (0x0004, 0x09, CardType::MifareMini),
+            (0x0004, 0x08, CardType::MifareClassic1k),
+            (0x0044, 0x08, CardType::MifareClassic1k),
+            (0x0002, 0x18, CardType::MifareClassic4k),
+            (0x0042, 0x18, CardType::MifareClassic4k),
+            (0x0004, 0x10, CardType::MifarePlusSl2),
+            (0x0044, 0x10, CardType::MifarePlusSl2),
+            (0x0002, 0x11, CardType::MifarePlusSl2),
+            (0x0042, 0x11, CardType::MifarePlusSl2),
+            (0x0044, 0x00, CardType::MifareUltralightFamily),
+            (0x0344, 0x20, CardType::MifareDesfireFamily),
+            (0x0004, 0x88, CardType::InfineonMifareClassic1k),
+            (0x0004, 0x20, CardType::SmartMx),
+            (0x0344, 0x28, CardType::SmartMx),
+            (0x0002, 0x38, CardType::SmartMx),
+            (0x0048, 0x20, CardType::SmartMx),
+            (0x0004, 0x00, CardType::Unknown),
+            (0x0044, 0x88, CardType::Unknown),
+            (0x0000, 0x08, CardType::Unknown),
+        ];
+        for &(atqa, sak, card) in cases.iter() {
+            let uid = if atqa & 0x0040 == 0 {
+                "B4F4E9C2"
+            } else {
+                "044B2A125C5E80"
+            };
+            assert_eq!(
+                identify_iso14443a(&info(atqa, sak, uid, None)),
+                card,
+                "ATQA {:04X}, SAK {:02X}",
+                atqa,
+                sak
+            );
+        }
+    }
+
+    #[test]
+    fn infineon_my_d() {
+        let my_d = info(0x0044, 0x00, "05C4A1B2C3D4E5", None);
+        assert_eq!(identify_iso14443a(&my_d), CardType::InfineonMyD);
+
+        // only double size UIDs carry the manufacturer code
+        let single = info(0x0044, 0x00, "05C4A1B2", None);
+        assert_eq!(
+            identify_iso14443a(&single),
+            CardType::MifareUltralightFamily
+        );
+        let nxp = info(0x0044, 0x00, "04C4A1B2C3D4E5", None);
+        assert_eq!(identify_iso14443a(&nxp), CardType::MifareUltralightFamily);
+    }
+
+    #[test]
+    fn refined_by_ats() {
+        // JCOP41 v2.4.1, "JCOPv241" in the historical bytes
+        let jcop = info(0x0004, 0x28, "B4F4E9C2", Some("787780024A434F5076323431"));
+        assert_eq!(identify_iso14443a(&jcop), CardType::Jcop);
+
+        // MIFARE Plus S, chip type 0x2F; SAK 0x28 still offers MIFARE Classic
+        let plus = "75778002C1052F2F01BCD6";
+        let sl1 = info(0x0044, 0x28, "044B2A125C5E80", Some(plus));
+        assert_eq!(identify_iso14443a(&sl1), CardType::MifarePlusSl1);
+        let sl3 = info(0x0044, 0x20, "044B2A125C5E80", Some(plus));
+        assert_eq!(identify_iso14443a(&sl3), CardType::MifarePlusSl3);
+
+        // DESFire EV1, whose historical bytes say nothing more
+        let desfire = info(0x0344, 0x20, "04448BD2DB6B80", Some("7577810280"));
+        assert_eq!(identify_iso14443a(&desfire), CardType::MifareDesfireFamily);
+        // a DESFire chip type behind the SmartMX fingerprint
+        let chip_type = info(0x0004, 0x20, "B4F4E9C2", Some("75778102C10510"));
+        assert_eq!(
+            identify_iso14443a(&chip_type),
+            CardType::MifareDesfireFamily
+        );
+
+        let other = Ats::new(decode_hex("7577810280").unwrap());
+        assert_eq!(
+            refine_by_ats(CardType::SmartMx, 0x20, &other),
+            CardType::SmartMx
+        );
+        let unknown_chip = Ats::new(decode_hex("75778102C1053F").unwrap());
+        assert_eq!(
+            refine_by_ats(CardType::SmartMx, 0x20, &unknown_chip),
+            CardType::SmartMx
+        );
+    }
+
+    #[test]
+    fn ultralight_versions() {
+        let cases = [
+            ("0004030101000B03", CardType::MifareUltralightEv1),
+            ("0004030101000E03", CardType::MifareUltralightEv1),
+            ("0004040101000B03", CardType::Ntag210),
+            ("0004040101000E03", CardType::Ntag212),
+            ("0004040201000F03", CardType::Ntag213),
+            ("0004040201001103", CardType::Ntag215),
+            ("0004040201001303", CardType::Ntag216),
+            ("0004040502011303", CardType::NtagI2c1k),
+            ("0004040502011503", CardType::NtagI2c2k),
+            ("0004040201001203", CardType::MifareUltralightFamily),
+            ("0004030101000F03", CardType::MifareUltralightFamily),
+            ("00040402010011", CardType::MifareUltralightFamily),
+        ];
+        for &(version, card) in cases.iter() {
+            assert_eq!(
+                ultralight_from_version(&decode_hex(version).unwrap()),
+                card,
+                "{}",
+                version
+            );
+        }
+    }
+}
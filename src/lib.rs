@@ -8,23 +8,42 @@
 
 mod context;
 mod device;
+mod display;
 mod error;
 mod ffi;
+mod identify;
+pub mod mifare;
+mod modulation;
+pub mod ntag424;
+mod poll;
+mod scan;
+mod select;
+mod session;
+#[cfg(feature = "serde")]
+mod serialize;
 mod target;
+pub mod target_info;
 mod util;
 
 pub use ffi::{
-    nfc_baud_rate as BaudRate, nfc_dep_info as DepInfo, nfc_dep_mode as DepMode,
-    nfc_modulation as Modulation, nfc_modulation_type as ModulationType, nfc_property as Property,
+    nfc_baud_rate as BaudRate, nfc_dep_mode as DepMode, nfc_mode as Mode,
+    nfc_modulation_type as ModulationType, nfc_property as Property,
 };
 
 pub use context::Context;
 pub use device::{Device, Initiator, PollType, TargetAndCount, TargetResultEnum};
+pub use display::VerboseTarget;
+pub use identify::{identify, identify_active, CardType};
+pub use modulation::{Baud, DepBaud, FelicaBaud, Modulation};
+pub use poll::PollProfile;
+pub use scan::ScanReport;
+pub use select::{FelicaRequestCode, FelicaTimeSlots, SelectCriteria};
+pub use session::{ResyncEvent, RetryPolicy, Session};
 pub use target::{Target, TargetInfo};
 
-pub use target::target_info;
+pub use target_info::DepInfo;
 
-pub use error::{NfcError as Error, NfcResult as Result};
+pub use error::{ErrorKind, NfcError as Error, NfcResult as Result};
 
 /// Retrieves the version of the linked NFC library.
 pub fn version() -> &'static str {
@@ -0,0 +1,10 @@
+//! Command sets for NXP's MIFARE family, built on the raw exchange functions
+//! of [`Initiator`](../struct.Initiator.html).
+
+pub mod classic;
+pub mod crypto1;
+pub mod darkside;
+pub mod magic;
+pub mod nested;
+pub mod originality;
+pub mod ultralight;
@@ -0,0 +1,319 @@
+//! MIFARE Classic (and compatible) cards.
+//!
+//! The reader does Crypto1 for us: with `NP_EASY_FRAMING` enabled, as it is
+//! after the device was turned into an initiator, authentication and block
+//! commands are plain `transceive_bytes` calls and everything after a
+//! successful authentication is encrypted transparently.
+//!
+//! Cards emulating MIFARE Classic on an ISO14443-4 chip (SmartMX, Plus in
+//! SL1) only answer to these commands when `NP_AUTO_ISO14443_4` was disabled
+//! before selecting them.
+
+use crate::device::{Initiator, TargetResultEnum};
+use crate::ffi;
+use crate::{
+    identify, CardType, Error, ErrorKind, Modulation, Result, SelectCriteria, Target, TargetInfo,
+};
+
+use std::convert::TryInto;
+
+mod dictionary;
+mod dump;
+mod mad;
+mod trailer;
+mod value;
+
+pub use dictionary::{KeyDictionary, KeyProgress};
+pub use dump::{Dump, RestoreOptions};
+pub use mad::{crc8, Aid, Mad, MAD2_SECTOR, MAD_KEY};
+pub use trailer::{AccessConditions, DataAccess, SectorTrailer, TrailerAccess};
+pub use value::{BackedValue, ValueBlock, ValueState};
+
+pub const BLOCK_SIZE: usize = 16;
+pub const KEY_SIZE: usize = 6;
+
+pub type Block = [u8; BLOCK_SIZE];
+pub type Key = [u8; KEY_SIZE];
+
+const AUTH_A: u8 = 0x60;
+const AUTH_B: u8 = 0x61;
+const READ: u8 = 0x30;
+const WRITE: u8 = 0xA0;
+
+// the 4K has 32 small sectors followed by 8 big ones
+const SMALL_SECTOR_BLOCKS: u8 = 4;
+const BIG_SECTOR_BLOCKS: u8 = 16;
+const SMALL_SECTORS: u8 = 32;
+const FIRST_BIG_BLOCK: u16 = SMALL_SECTORS as u16 * SMALL_SECTOR_BLOCKS as u16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KeyType {
+    A,
+    B,
+}
+
+/// The keys of a sector, where known.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SectorKeys {
+    pub a: Option<Key>,
+    pub b: Option<Key>,
+}
+
+impl SectorKeys {
+    pub fn new(a: Option<Key>, b: Option<Key>) -> Self {
+        SectorKeys { a, b }
+    }
+
+    pub fn get(&self, key_type: KeyType) -> Option<Key> {
+        match key_type {
+            KeyType::A => self.a,
+            KeyType::B => self.b,
+        }
+    }
+
+    pub fn set(&mut self, key_type: KeyType, key: Key) {
+        match key_type {
+            KeyType::A => self.a = Some(key),
+            KeyType::B => self.b = Some(key),
+        }
+    }
+}
+
+/// The memory layout of a card: how many sectors it has and how blocks map
+/// onto them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Layout {
+    /// 5 sectors of 4 blocks
+    Mini,
+    /// 16 sectors of 4 blocks
+    Classic1k,
+    /// 32 sectors of 4 blocks, then 8 sectors of 16 blocks
+    Classic4k,
+}
+
+impl Layout {
+    /// The layout of an identified card, if it's a MIFARE Classic one.
+    pub fn of(card: CardType) -> Option<Self> {
+        match card {
+            CardType::MifareMini => Some(Layout::Mini),
+            CardType::MifareClassic1k | CardType::InfineonMifareClassic1k => {
+                Some(Layout::Classic1k)
+            }
+            CardType::MifareClassic4k => Some(Layout::Classic4k),
+            _ => None,
+        }
+    }
+
+    pub fn sector_count(self) -> u8 {
+        match self {
+            Layout::Mini => 5,
+            Layout::Classic1k => 16,
+            Layout::Classic4k => 40,
+        }
+    }
+
+    pub fn block_count(self) -> u16 {
+        match self {
+            Layout::Mini => 20,
+            Layout::Classic1k => 64,
+            Layout::Classic4k => 256,
+        }
+    }
+
+    /// The size of the card's memory in bytes.
+    pub fn size(self) -> usize {
+        usize::from(self.block_count()) * BLOCK_SIZE
+    }
+
+    pub fn contains_block(self, block: u8) -> bool {
+        u16::from(block) < self.block_count()
+    }
+
+    pub fn contains_sector(self, sector: u8) -> bool {
+        sector < self.sector_count()
+    }
+
+    /// The sectors of the card, in order.
+    pub fn sectors(self) -> impl Iterator<Item = u8> {
+        0..self.sector_count()
+    }
+}
+
+/// The sector a block belongs to.
+pub fn sector_of(block: u8) -> u8 {
+    if u16::from(block) < FIRST_BIG_BLOCK {
+        block / SMALL_SECTOR_BLOCKS
+    } else {
+        SMALL_SECTORS + (block - FIRST_BIG_BLOCK as u8) / BIG_SECTOR_BLOCKS
+    }
+}
+
+/// How many blocks a sector has, including its trailer.
+pub fn blocks_in_sector(sector: u8) -> u8 {
+    if sector < SMALL_SECTORS {
+        SMALL_SECTOR_BLOCKS
+    } else {
+        BIG_SECTOR_BLOCKS
+    }
+}
+
+pub fn first_block(sector: u8) -> u8 {
+    if sector < SMALL_SECTORS {
+        sector * SMALL_SECTOR_BLOCKS
+    } else {
+        FIRST_BIG_BLOCK as u8 + (sector - SMALL_SECTORS) * BIG_SECTOR_BLOCKS
+    }
+}
+
+/// The block holding the sector's keys and access conditions.
+pub fn trailer_block(sector: u8) -> u8 {
+    first_block(sector) + blocks_in_sector(sector) - 1
+}
+
+pub fn is_trailer(block: u8) -> bool {
+    trailer_block(sector_of(block)) == block
+}
+
+/// Whether an authentication went through, `false` meaning the card refused
+/// the key. Any other error is passed on, so that trying keys doesn't take
+/// a card leaving the field for a wrong key.
+pub(crate) fn key_accepted<T>(result: Result<T>) -> Result<bool> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == Some(ErrorKind::MifareClassicAuth) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// A MIFARE Classic target selected on an initiator.
+pub struct MifareClassic<'a, 'context> {
+    initiator: &'a mut Initiator<'context>,
+    target: Target,
+    layout: Layout,
+}
+
+impl<'a, 'context> MifareClassic<'a, 'context> {
+    /// Wraps a selected target, working out the layout from its ATQA and SAK.
+    pub fn new(initiator: &'a mut Initiator<'context>, target: Target) -> Result<Self> {
+        match Layout::of(identify(&target)) {
+            Some(layout) => MifareClassic::with_layout(initiator, target, layout),
+            None => Err(Error::new("Target isn't a MIFARE Classic card")),
+        }
+    }
+
+    /// Wraps a selected target with a known layout, for cards whose ATQA and
+    /// SAK don't give it away, like clones and emulations.
+    pub fn with_layout(
+        initiator: &'a mut Initiator<'context>,
+        target: Target,
+        layout: Layout,
+    ) -> Result<Self> {
+        match &target.info {
+            TargetInfo::ISO14443A { info } if info.uid.len() >= 4 => Ok(MifareClassic {
+                initiator,
+                target,
+                layout,
+            }),
+            _ => Err(Error::new("MIFARE Classic targets have to be ISO14443A")),
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    pub fn initiator(&mut self) -> &mut Initiator<'context> {
+        self.initiator
+    }
+
+    pub fn uid(&self) -> &[u8] {
+        match &self.target.info {
+            TargetInfo::ISO14443A { info } => &info.uid,
+            // checked when constructed
+            _ => unreachable!(),
+        }
+    }
+
+    /// Selects the card again, as needed after a failed authentication or a
+    /// denied command.
+    pub fn reselect(&mut self) -> Result<()> {
+        let criteria = SelectCriteria::for_target(&self.target);
+        match self
+            .initiator
+            .select_passive_target(Modulation::iso14443a(), &criteria)?
+        {
+            TargetResultEnum::Found(_) => Ok(()),
+            TargetResultEnum::Empty => Err(Error::from(ffi::NFC_ETGRELEASED)),
+        }
+    }
+
+    /// Authenticates for the sector containing `block`. Fails with
+    /// `ErrorKind::MifareClassicAuth` if the key is wrong, after which the
+    /// card has to be selected again. Other errors, such as the card leaving
+    /// the field, are returned as they are.
+    pub fn authenticate(&mut self, block: u8, key_type: KeyType, key: &Key) -> Result<()> {
+        self.check_block(block)?;
+
+        let command = match key_type {
+            KeyType::A => AUTH_A,
+            KeyType::B => AUTH_B,
+        };
+        // the card wants the last 4 bytes of the UID, no matter its length
+        let uid = self.uid();
+        let mut frame = vec![command, block];
+        frame.extend_from_slice(key);
+        frame.extend_from_slice(&uid[uid.len() - 4..]);
+
+        self.initiator
+            .transceive_bytes(&frame, BLOCK_SIZE, -1)
+            .map(|_| ())
+    }
+
+    /// Reads a block of an authenticated sector.
+    pub fn read_block(&mut self, block: u8) -> Result<Block> {
+        self.check_block(block)?;
+
+        let data = self
+            .initiator
+            .transceive_bytes(&[READ, block], BLOCK_SIZE, -1)?;
+        data.as_slice().try_into().map_err(|_| {
+            Error::new(&format!(
+                "Read of block {} returned {} bytes",
+                block,
+                data.len()
+            ))
+        })
+    }
+
+    /// Writes a block of an authenticated sector.
+    pub fn write_block(&mut self, block: u8, data: &Block) -> Result<()> {
+        self.check_block(block)?;
+
+        let mut frame = vec![WRITE, block];
+        frame.extend_from_slice(data);
+        self.initiator
+            .transceive_bytes(&frame, BLOCK_SIZE, -1)
+            .map(|_| ())
+    }
+
+    fn check_block(&self, block: u8) -> Result<()> {
+        if self.layout.contains_block(block) {
+            Ok(())
+        } else {
+            Err(Error::from(ffi::NFC_EINVARG))
+        }
+    }
+
+    fn check_sector(&self, sector: u8) -> Result<()> {
+        if self.layout.contains_sector(sector) {
+            Ok(())
+        } else {
+            Err(Error::from(ffi::NFC_EINVARG))
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Finding the keys of a card by trying a list of candidates.
+
+use super::{key_accepted, trailer_block, Key, KeyType, MifareClassic, SectorKeys, KEY_SIZE};
+use crate::{Error, Result};
+
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+/// Keys cards commonly ship or get personalized with, the same list `mfoc`
+/// starts from.
+const DEFAULT_KEYS: [Key; 13] = [
+    [0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+    [0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5],
+    [0xd3, 0xf7, 0xd3, 0xf7, 0xd3, 0xf7],
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0xb0, 0xb1, 0xb2, 0xb3, 0xb4, 0xb5],
+    [0x4d, 0x3a, 0x99, 0xc3, 0x51, 0xdd],
+    [0x1a, 0x98, 0x2c, 0x7e, 0x45, 0x9a],
+    [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff],
+    [0x71, 0x4c, 0x5c, 0x88, 0x6e, 0x97],
+    [0x58, 0x7e, 0xe5, 0xf9, 0x35, 0x0f],
+    [0xa0, 0x47, 0x8c, 0xc3, 0x90, 0x91],
+    [0x53, 0x3c, 0xb6, 0xc7, 0x23, 0xf6],
+    [0x8f, 0xd0, 0xa4, 0xf2, 0x56, 0xe9],
+];
+
+/// Where [`KeyDictionary::find_keys`](struct.KeyDictionary.html#method.find_keys)
+/// is at, passed to its callback before each attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyProgress {
+    pub sector: u8,
+    pub key_type: KeyType,
+    /// How many keys were tried for this sector and key type, counting this
+    /// one.
+    pub attempt: usize,
+    /// How many keys there are to try.
+    pub total: usize,
+}
+
+/// An ordered list of candidate keys without duplicates.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct KeyDictionary {
+    keys: Vec<Key>,
+}
+
+impl KeyDictionary {
+    pub fn new() -> Self {
+        KeyDictionary::default()
+    }
+
+    /// The built in list of well known keys.
+    pub fn defaults() -> Self {
+        let mut dictionary = KeyDictionary::new();
+        dictionary.extend(DEFAULT_KEYS.iter().copied());
+        dictionary
+    }
+
+    /// Parses the `.dic` format used by the Proxmark3 and mfoc: one key of 12
+    /// hex digits per line, with `#` starting a comment.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut dictionary = KeyDictionary::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let word = match line.split_whitespace().next() {
+                Some(word) => word,
+                None => continue,
+            };
+            dictionary.push(parse_key(word).ok_or_else(|| {
+                Error::new(&format!("Invalid key {:?} on line {}", word, number + 1))
+            })?);
+        }
+        Ok(dictionary)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| Error::new(&format!("Couldn't read {}: {}", path.display(), e)))?;
+        KeyDictionary::parse(&text)
+    }
+
+    /// Adds a key at the end, unless it's already in the dictionary.
+    pub fn push(&mut self, key: Key) {
+        if !self.keys.contains(&key) {
+            self.keys.push(key);
+        }
+    }
+
+    pub fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Tries the keys on every sector, as both key A and key B, returning
+    /// what was found indexed by sector.
+    ///
+    /// Keys that worked on a sector are tried first on the following ones,
+    /// since cards tend to reuse them. A failed authentication makes the
+    /// card drop out, so it's selected again after each one.
+    pub fn find_keys<F>(&self, card: &mut MifareClassic, mut progress: F) -> Result<Vec<SectorKeys>>
+    where
+        F: FnMut(KeyProgress),
+    {
+        // most recently successful first
+        let mut found: Vec<Key> = Vec::new();
+        let mut sectors = Vec::new();
+
+        for sector in card.layout().sectors() {
+            let mut keys = SectorKeys::default();
+            for &key_type in &[KeyType::A, KeyType::B] {
+                let candidates: Vec<Key> = found
+                    .iter()
+                    .chain(self.keys.iter().filter(|key| !found.contains(key)))
+                    .copied()
+                    .collect();
+
+                for (i, key) in candidates.iter().enumerate() {
+                    progress(KeyProgress {
+                        sector,
+                        key_type,
+                        attempt: i + 1,
+                        total: candidates.len(),
+                    });
+                    if key_accepted(card.authenticate(trailer_block(sector), key_type, key))? {
+                        keys.set(key_type, *key);
+                        found.retain(|known| known != key);
+                        found.insert(0, *key);
+                        break;
+                    }
+                    card.reselect()?;
+                }
+            }
+            sectors.push(keys);
+        }
+        Ok(sectors)
+    }
+}
+
+impl Extend<Key> for KeyDictionary {
+    fn extend<I: IntoIterator<Item = Key>>(&mut self, keys: I) {
+        for key in keys {
+            self.push(key);
+        }
+    }
+}
+
+fn parse_key(word: &str) -> Option<Key> {
+    // from_str_radix would also take a sign
+    if word.len() != KEY_SIZE * 2 || !word.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..KEY_SIZE)
+        .map(|i| u8::from_str_radix(&word[i * 2..i * 2 + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?
+        .as_slice()
+        .try_into()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let text = "\
+# Proxmark3 style dictionary
+FFFFFFFFFFFF
+a0a1a2a3a4a5 # MAD key
+
+  D3F7D3F7D3F7\t  trailing text
+# 000000000000
+A0A1A2A3A4A5
+";
+        let dictionary = KeyDictionary::parse(text).unwrap();
+        assert_eq!(
+            dictionary.keys(),
+            [
+                [0xff; KEY_SIZE],
+                [0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5],
+                [0xd3, 0xf7, 0xd3, 0xf7, 0xd3, 0xf7],
+            ]
+        );
+        assert!(KeyDictionary::parse("\n# nothing\n\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = KeyDictionary::parse("FFFFFFFFFFFF\n\n# comment\nFFFFFFFFFFF\n").unwrap_err();
+        assert!(error.to_string().contains("line 4"), "{}", error);
+
+        for word in &[
+            "+FFFFFFFFFFF",
+            "FFFFFFFFFF+F",
+            "FFFFFFFFFFFG",
+            "FFFFFFFFFFFFFF",
+            "FFFFFFFFFF\u{e9}",
+        ] {
+            assert!(KeyDictionary::parse(word).is_err(), "{:?}", word);
+        }
+    }
+
+    #[test]
+    fn deduplication() {
+        let mut dictionary = KeyDictionary::defaults();
+        assert_eq!(dictionary.len(), DEFAULT_KEYS.len());
+        dictionary.extend(
+            KeyDictionary::parse("000000000000\n112233445566\nFFFFFFFFFFFF")
+                .unwrap()
+                .keys()
+                .iter()
+                .copied(),
+        );
+        assert_eq!(dictionary.len(), DEFAULT_KEYS.len() + 1);
+        assert_eq!(dictionary.keys()[..DEFAULT_KEYS.len()], DEFAULT_KEYS[..]);
+        assert_eq!(
+            dictionary.keys().last(),
+            Some(&[0x11, 0x22, 0x33, 0x44, 0x55, 0x66])
+        );
+    }
+}
@@ -0,0 +1,689 @@
+//! Reading a whole card into an image and writing it back, and the image
+//! formats of `nfc-mfclassic`, the Proxmark3 and the Flipper Zero.
+
+use super::{
+    blocks_in_sector, first_block, is_trailer, key_accepted, sector_of, trailer_block, Block,
+    KeyType, Layout, MifareClassic, SectorKeys, SectorTrailer, BLOCK_SIZE, KEY_SIZE,
+};
+use crate::mifare::magic::bcc;
+#[cfg(feature = "serde_json")]
+use crate::util::{decode_hex, encode_hex};
+use crate::{Error, Result, TargetInfo};
+
+use std::convert::TryInto;
+use std::fmt::Write;
+
+const KEY_B_OFFSET: usize = 10;
+
+/// An image of a card.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Dump {
+    pub layout: Layout,
+    pub uid: Vec<u8>,
+    pub atqa: [u8; 2],
+    pub sak: u8,
+    /// Every block of the card, `None` where it couldn't be read.
+    pub blocks: Vec<Option<Block>>,
+    /// The keys that worked, per sector. The trailers hold these keys in
+    /// place of the zeros the card returns for them. Only formats that
+    /// record which keys were found fill these in when read.
+    pub keys: Vec<SectorKeys>,
+}
+
+/// What [`MifareClassic::restore_dump`](struct.MifareClassic.html#method.restore_dump)
+/// leaves alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RestoreOptions {
+    pub skip_trailers: bool,
+    /// Block 0 can only be written on magic cards, so it's skipped unless
+    /// this is cleared.
+    pub skip_block_0: bool,
+}
+
+impl Default for RestoreOptions {
+    fn default() -> Self {
+        RestoreOptions {
+            skip_trailers: false,
+            skip_block_0: true,
+        }
+    }
+}
+
+impl<'a, 'context> MifareClassic<'a, 'context> {
+    /// Reads every block the keys give access to. `keys` is indexed by
+    /// sector, missing entries meaning no key is known.
+    pub fn dump(&mut self, keys: &[SectorKeys]) -> Result<Dump> {
+        let mut blocks = vec![None; usize::from(self.layout.block_count())];
+        let mut worked = Vec::new();
+        for sector in self.layout.sectors() {
+            let candidates = keys.get(usize::from(sector)).copied().unwrap_or_default();
+            worked.push(self.dump_sector(sector, &candidates, &mut blocks)?);
+        }
+
+        let (atqa, sak) = match &self.target.info {
+            TargetInfo::ISO14443A { info } => (info.atqa, info.sak),
+            // checked when constructed
+            _ => unreachable!(),
+        };
+        Ok(Dump {
+            layout: self.layout,
+            uid: self.uid().to_vec(),
+            atqa,
+            sak,
+            blocks,
+            keys: worked,
+        })
+    }
+
+    fn dump_sector(
+        &mut self,
+        sector: u8,
+        keys: &SectorKeys,
+        blocks: &mut [Option<Block>],
+    ) -> Result<SectorKeys> {
+        let first = first_block(sector);
+        let mut worked = SectorKeys::default();
+
+        for &key_type in &[KeyType::A, KeyType::B] {
+            let key = match keys.get(key_type) {
+                Some(key) => key,
+                None => continue,
+            };
+            if !key_accepted(self.authenticate(first, key_type, &key))? {
+                self.reselect()?;
+                continue;
+            }
+            worked.set(key_type, key);
+
+            for offset in 0..blocks_in_sector(sector) {
+                let block = first + offset;
+                let slot = &mut blocks[usize::from(block)];
+                if slot.is_some() {
+                    continue;
+                }
+                match self.read_block(block) {
+                    Ok(data) => *slot = Some(data),
+                    // the card halts when a read is denied
+                    Err(_) => {
+                        self.reselect()?;
+                        if !key_accepted(self.authenticate(first, key_type, &key))? {
+                            self.reselect()?;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(trailer) = &mut blocks[usize::from(trailer_block(sector))] {
+            if let Some(key) = worked.a {
+                trailer[..KEY_SIZE].copy_from_slice(&key);
+            }
+            if let Some(key) = worked.b {
+                trailer[KEY_B_OFFSET..].copy_from_slice(&key);
+            }
+        }
+        Ok(worked)
+    }
+
+    /// Writes an image back, authenticating with `keys`, indexed by sector.
+    /// Blocks missing from the image are skipped.
+    ///
+    /// Every trailer to be written is checked before anything is written: it
+    /// has to pass [`SectorTrailer::validate`](struct.SectorTrailer.html#method.validate)
+    /// and both its keys have to be known in `image.keys`, since a trailer
+    /// read from a card holds zeros in place of the keys that couldn't be
+    /// found.
+    pub fn restore_dump(
+        &mut self,
+        image: &Dump,
+        keys: &[SectorKeys],
+        options: RestoreOptions,
+    ) -> Result<()> {
+        if image.layout != self.layout
+            || image.blocks.len() != usize::from(image.layout.block_count())
+        {
+            return Err(Error::new("Image doesn't match the card's layout"));
+        }
+
+        let skipped = |block: u8| {
+            (block == 0 && options.skip_block_0) || (is_trailer(block) && options.skip_trailers)
+        };
+
+        if !options.skip_trailers {
+            for sector in self.layout.sectors() {
+                let data = match &image.blocks[usize::from(trailer_block(sector))] {
+                    Some(data) => data,
+                    None => continue,
+                };
+                SectorTrailer::decode(data)?.validate()?;
+                match image.keys.get(usize::from(sector)) {
+                    Some(SectorKeys {
+                        a: Some(_),
+                        b: Some(_),
+                    }) => {}
+                    _ => {
+                        return Err(Error::new(&format!(
+                            "The keys of sector {} aren't known in the image, writing its \
+                             trailer would overwrite them",
+                            sector
+                        )))
+                    }
+                }
+            }
+        }
+
+        for (block, data) in image.blocks.iter().enumerate() {
+            let block = block as u8;
+            let data = match data {
+                Some(data) if !skipped(block) => data,
+                _ => continue,
+            };
+            let sector_keys = keys
+                .get(usize::from(sector_of(block)))
+                .copied()
+                .unwrap_or_default();
+            self.write_with_keys(block, data, &sector_keys)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a block with the first key the card accepts. A write refused
+    /// after authenticating, or any other failure, is returned as it is
+    /// rather than trying the other key.
+    fn write_with_keys(&mut self, block: u8, data: &Block, keys: &SectorKeys) -> Result<()> {
+        // writing usually takes key B
+        for &key_type in &[KeyType::B, KeyType::A] {
+            let key = match keys.get(key_type) {
+                Some(key) => key,
+                None => continue,
+            };
+            if key_accepted(self.authenticate(block, key_type, &key))? {
+                return self.write_block(block, data);
+            }
+            self.reselect()?;
+        }
+        Err(Error::new(&format!(
+            "No known key is accepted for block {}",
+            block
+        )))
+    }
+}
+
+impl Dump {
+    /// Builds an image from its blocks, taking the UID, ATQA and SAK from
+    /// block 0. A 4 byte UID is followed by its BCC, the SAK and the ATQA, a
+    /// 7 byte one directly by the SAK and the ATQA; without the UID size the
+    /// layout is guessed from whether the BCC matches. The keys are left
+    /// unknown: the trailers of an image may just as well hold the zeros a
+    /// card returns for keys that weren't found.
+    fn from_blocks(blocks: Vec<Option<Block>>, uid_size: Option<usize>) -> Result<Self> {
+        let layout = [Layout::Mini, Layout::Classic1k, Layout::Classic4k]
+            .iter()
+            .copied()
+            .find(|layout| usize::from(layout.block_count()) == blocks.len())
+            .ok_or_else(|| Error::new(&format!("No card has {} blocks", blocks.len())))?;
+
+        let block_0 = blocks[0].unwrap_or_default();
+        let uid_size = uid_size.unwrap_or(if block_0[4] == bcc(&block_0[..4]) {
+            4
+        } else {
+            7
+        });
+        let (uid, sak, atqa) = match uid_size {
+            4 => (&block_0[..4], block_0[5], [block_0[7], block_0[6]]),
+            7 => (&block_0[..7], block_0[7], [block_0[9], block_0[8]]),
+            size => {
+                return Err(Error::new(&format!(
+                    "Block 0 can't hold a UID of {} bytes",
+                    size
+                )))
+            }
+        };
+        Ok(Dump {
+            layout,
+            uid: uid.to_vec(),
+            atqa,
+            sak,
+            blocks,
+            keys: vec![SectorKeys::default(); usize::from(layout.sector_count())],
+        })
+    }
+
+    /// The raw image `nfc-mfclassic` reads and writes, with zeros for the
+    /// blocks that couldn't be read.
+    pub fn to_mfd(&self) -> Vec<u8> {
+        self.blocks
+            .iter()
+            .flat_map(|block| block.unwrap_or_default().to_vec())
+            .collect()
+    }
+
+    /// Reads a raw image. It doesn't say which keys are known, so they're
+    /// left unknown; set [`keys`](#structfield.keys) if they are.
+    ///
+    /// Nor does it record the size of the UID: a 4 byte UID is assumed when
+    /// block 0 holds its BCC, a 7 byte one otherwise. Set
+    /// [`uid`](#structfield.uid), [`atqa`](#structfield.atqa) and
+    /// [`sak`](#structfield.sak) if the guess is wrong.
+    pub fn from_mfd(data: &[u8]) -> Result<Self> {
+        let blocks = data.chunks_exact(BLOCK_SIZE);
+        if !blocks.remainder().is_empty() {
+            return Err(Error::new("Image isn't made of whole blocks"));
+        }
+        Dump::from_blocks(blocks.map(|block| block.try_into().ok()).collect(), None)
+    }
+
+    /// The JSON dump format of the Proxmark3 client. Only the known keys are
+    /// listed in its `SectorKeys`.
+    #[cfg(feature = "serde_json")]
+    pub fn to_proxmark_json(&self) -> String {
+        use serde_json::{json, Map, Value};
+
+        let hex = |bytes: &[u8]| Value::String(encode_hex(bytes).to_uppercase());
+        let blocks: Map<String, Value> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| (i.to_string(), hex(&block.unwrap_or_default())))
+            .collect();
+        let sectors: Map<String, Value> = self
+            .layout
+            .sectors()
+            .map(|sector| {
+                let trailer = self.blocks[usize::from(trailer_block(sector))].unwrap_or_default();
+                let known = self
+                    .keys
+                    .get(usize::from(sector))
+                    .copied()
+                    .unwrap_or_default();
+                let mut keys = Map::new();
+                if let Some(key) = known.a {
+                    keys.insert("KeyA".to_string(), hex(&key));
+                }
+                if let Some(key) = known.b {
+                    keys.insert("KeyB".to_string(), hex(&key));
+                }
+                keys.insert(
+                    "AccessConditions".to_string(),
+                    hex(&trailer[KEY_SIZE..KEY_B_OFFSET]),
+                );
+                (sector.to_string(), Value::Object(keys))
+            })
+            .collect();
+
+        let dump = json!({
+            "Created": "nfcrs",
+            "FileType": "mfcard",
+            "Card": {
+                "UID": hex(&self.uid),
+                // the Proxmark3 writes the ATQA least significant byte first
+                "ATQA": hex(&[self.atqa[1], self.atqa[0]]),
+                "SAK": hex(&[self.sak]),
+            },
+            "blocks": blocks,
+            "SectorKeys": sectors,
+        });
+        serde_json::to_string_pretty(&dump).unwrap()
+    }
+
+    /// Reads a Proxmark3 JSON dump, taking the keys from its `SectorKeys`.
+    #[cfg(feature = "serde_json")]
+    pub fn from_proxmark_json(json: &str) -> Result<Self> {
+        use serde_json::Value;
+
+        let dump: Value = serde_json::from_str(json).map_err(|e| Error::new(&e.to_string()))?;
+        let blocks = dump["blocks"]
+            .as_object()
+            .ok_or_else(|| Error::new("Proxmark3 dump has no blocks"))?;
+
+        let blocks = (0..blocks.len())
+            .map(|i| {
+                blocks
+                    .get(&i.to_string())
+                    .and_then(Value::as_str)
+                    .and_then(|hex| decode_hex(hex).ok())
+                    .and_then(|block| block.as_slice().try_into().ok())
+                    .map(Some)
+                    .ok_or_else(|| Error::new(&format!("Block {} is missing or malformed", i)))
+            })
+            .collect::<Result<_>>()?;
+        let card = &dump["Card"];
+        let field = |name: &str| card[name].as_str().and_then(|hex| decode_hex(hex).ok());
+        let uid = field("UID");
+        let mut image = Dump::from_blocks(blocks, uid.as_ref().map(Vec::len))?;
+
+        let key = |sector: &Value, name: &str| {
+            sector[name]
+                .as_str()
+                .and_then(|hex| decode_hex(hex).ok())
+                .and_then(|key| key.as_slice().try_into().ok())
+        };
+        for (sector, keys) in image.keys.iter_mut().enumerate() {
+            let recorded = &dump["SectorKeys"][sector.to_string()];
+            *keys = SectorKeys::new(key(recorded, "KeyA"), key(recorded, "KeyB"));
+        }
+
+        if let Some(uid) = uid {
+            image.uid = uid;
+        }
+        if let Some(&[low, high]) = field("ATQA").as_deref() {
+            image.atqa = [high, low];
+        }
+        if let Some(&[sak]) = field("SAK").as_deref() {
+            image.sak = sak;
+        }
+        Ok(image)
+    }
+
+    /// The `.nfc` format of the Flipper Zero. Bytes that weren't read, such
+    /// as keys that weren't found, are written as `??`.
+    pub fn to_flipper(&self) -> String {
+        let mut out = String::new();
+        out.push_str("Filetype: Flipper NFC device\n");
+        out.push_str("Version: 4\n");
+        out.push_str("Device type: Mifare Classic\n");
+        writeln!(
+            out,
+            "UID: {}",
+            flipper_hex(self.uid.iter().copied().map(Some))
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "ATQA: {}",
+            flipper_hex(self.atqa.iter().copied().map(Some))
+        )
+        .unwrap();
+        writeln!(out, "SAK: {:02X}", self.sak).unwrap();
+        let card_type = match self.layout {
+            Layout::Mini => "MINI",
+            Layout::Classic1k => "1K",
+            Layout::Classic4k => "4K",
+        };
+        writeln!(out, "Mifare Classic type: {}", card_type).unwrap();
+        out.push_str("Data format version: 2\n");
+        out.push_str("# Mifare Classic blocks, '??' means unknown data\n");
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            let keys = self
+                .keys
+                .get(usize::from(sector_of(i as u8)))
+                .copied()
+                .unwrap_or_default();
+            let trailer = is_trailer(i as u8);
+            let bytes = (0..BLOCK_SIZE).map(|offset| {
+                let unknown_key = trailer
+                    && ((offset < KEY_SIZE && keys.a.is_none())
+                        || (offset >= KEY_B_OFFSET && keys.b.is_none()));
+                block.filter(|_| !unknown_key).map(|block| block[offset])
+            });
+            writeln!(out, "Block {}: {}", i, flipper_hex(bytes)).unwrap();
+        }
+        out
+    }
+
+    /// Reads a Flipper Zero dump, the keys spelled out in its trailers being
+    /// the known ones.
+    pub fn from_flipper(text: &str) -> Result<Self> {
+        let mut uid = None;
+        let mut atqa = None;
+        let mut sak = None;
+        let mut blocks = Vec::new();
+
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let (name, value) = match line.find(':') {
+                Some(colon) => (line[..colon].trim(), line[colon + 1..].trim()),
+                None => continue,
+            };
+            let bytes = || -> Result<Vec<Option<u8>>> {
+                value
+                    .split_whitespace()
+                    .map(|byte| match byte {
+                        "??" => Ok(None),
+                        byte => u8::from_str_radix(byte, 16).map(Some).map_err(|_| {
+                            Error::new(&format!("Invalid byte {:?} in {}", byte, name))
+                        }),
+                    })
+                    .collect()
+            };
+            let known = || -> Result<Vec<u8>> {
+                bytes()?
+                    .into_iter()
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| Error::new(&format!("{} is unknown", name)))
+            };
+
+            match name {
+                "Device type" if value != "Mifare Classic" => {
+                    return Err(Error::new(&format!("Not a MIFARE Classic dump: {}", value)))
+                }
+                "UID" => uid = Some(known()?),
+                "ATQA" => atqa = known()?.as_slice().try_into().ok(),
+                "SAK" => sak = known()?.first().copied(),
+                name if name.starts_with("Block ") => {
+                    let index: usize = name["Block ".len()..]
+                        .parse()
+                        .map_err(|_| Error::new(&format!("Invalid block name {:?}", name)))?;
+                    let bytes = bytes()?;
+                    if index != blocks.len() || bytes.len() != BLOCK_SIZE {
+                        return Err(Error::new(&format!(
+                            "{} is out of place or malformed",
+                            name
+                        )));
+                    }
+                    blocks.push(bytes);
+                }
+                _ => {}
+            }
+        }
+
+        let data = blocks
+            .iter()
+            .map(|bytes| {
+                if bytes.iter().all(Option::is_none) {
+                    None
+                } else {
+                    let mut block = [0; BLOCK_SIZE];
+                    for (byte, value) in block.iter_mut().zip(bytes) {
+                        *byte = value.unwrap_or_default();
+                    }
+                    Some(block)
+                }
+            })
+            .collect();
+        let mut image = Dump::from_blocks(data, uid.as_ref().map(Vec::len))?;
+
+        // the Flipper writes the keys it didn't find as `??`, so the ones
+        // spelled out are known
+        let key = |bytes: &[Option<u8>]| {
+            bytes
+                .iter()
+                .copied()
+                .collect::<Option<Vec<_>>>()
+                .and_then(|key| key.as_slice().try_into().ok())
+        };
+        for sector in image.layout.sectors() {
+            let trailer = &blocks[usize::from(trailer_block(sector))];
+            image.keys[usize::from(sector)] =
+                SectorKeys::new(key(&trailer[..KEY_SIZE]), key(&trailer[KEY_B_OFFSET..]));
+        }
+        if let Some(uid) = uid {
+            image.uid = uid;
+        }
+        if let Some(atqa) = atqa {
+            image.atqa = atqa;
+        }
+        if let Some(sak) = sak {
+            image.sak = sak;
+        }
+        Ok(image)
+    }
+}
+
+fn flipper_hex(bytes: impl Iterator<Item = Option<u8>>) -> String {
+    bytes
+        .map(|byte| match byte {
+            Some(byte) => format!("{:02X}", byte),
+            None => "??".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::decode_hex;
+
+    const FLIPPER: &str = include_str!("testdata/flipper.nfc");
+    #[cfg(feature = "serde_json")]
+    const PROXMARK: &str = include_str!("testdata/proxmark3.json");
+
+    fn image(block_0: &str) -> Vec<u8> {
+        let mut data = vec![0; 64 * BLOCK_SIZE];
+        data[..BLOCK_SIZE].copy_from_slice(&decode_hex(block_0).unwrap());
+        for trailer in (3..64).step_by(4) {
+            data[trailer * BLOCK_SIZE..][..BLOCK_SIZE]
+                .copy_from_slice(&decode_hex("FFFFFFFFFFFFFF078069FFFFFFFFFFFF").unwrap());
+        }
+        data
+    }
+
+    #[test]
+    fn mfd_round_trip() {
+        let data = image("B4F4E9C26B0804006263646566676869");
+        let dump = Dump::from_mfd(&data).unwrap();
+        assert_eq!(dump.layout, Layout::Classic1k);
+        assert_eq!(dump.uid, [0xb4, 0xf4, 0xe9, 0xc2]);
+        assert_eq!(dump.atqa, [0x00, 0x04]);
+        assert_eq!(dump.sak, 0x08);
+        assert!(dump.keys.iter().all(|keys| *keys == SectorKeys::default()));
+        assert_eq!(dump.to_mfd(), data);
+    }
+
+    #[test]
+    fn mfd_seven_byte_uid() {
+        let dump = Dump::from_mfd(&image("044B2A125C5E80084400120000000000")).unwrap();
+        assert_eq!(dump.uid, [0x04, 0x4b, 0x2a, 0x12, 0x5c, 0x5e, 0x80]);
+        assert_eq!(dump.atqa, [0x00, 0x44]);
+        assert_eq!(dump.sak, 0x08);
+    }
+
+    #[test]
+    fn mfd_wrong_size() {
+        let data = image("B4F4E9C26B0804006263646566676869");
+        assert!(Dump::from_mfd(&data[..63 * BLOCK_SIZE]).is_err());
+        assert!(Dump::from_mfd(&data[..data.len() - 1]).is_err());
+        assert!(Dump::from_mfd(&[]).is_err());
+    }
+
+    #[test]
+    fn flipper_fixture() {
+        let dump = Dump::from_flipper(FLIPPER).unwrap();
+        assert_eq!(dump.layout, Layout::Classic1k);
+        assert_eq!(dump.uid, [0x04, 0x4b, 0x2a, 0x12, 0x5c, 0x5e, 0x80]);
+        assert_eq!(dump.atqa, [0x00, 0x44]);
+        assert_eq!(dump.sak, 0x08);
+
+        let block_1 = decode_hex("140103E103E103E103E103E103E103E1").unwrap();
+        assert_eq!(dump.blocks[1].unwrap()[..], block_1[..]);
+        assert_eq!(
+            dump.keys[0],
+            SectorKeys::new(Some([0xff; 6]), Some([0xff; 6]))
+        );
+
+        // sector 14 had only its key A found, sector 15 no key at all
+        assert_eq!(dump.keys[14], SectorKeys::new(Some([0xff; 6]), None));
+        assert_eq!(
+            dump.blocks[59].unwrap()[..KEY_B_OFFSET],
+            decode_hex("FFFFFFFFFFFFFF078069").unwrap()[..]
+        );
+        assert_eq!(dump.keys[15], SectorKeys::default());
+        assert!(dump.blocks[60..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn flipper_round_trip() {
+        let dump = Dump::from_flipper(FLIPPER).unwrap();
+        let text = dump.to_flipper();
+        assert!(text.contains("Block 59: FF FF FF FF FF FF FF 07 80 69 ?? ?? ?? ?? ?? ??\n"));
+        assert!(text.contains("Block 60: ?? ?? ?? ?? ?? ?? ?? ?? ?? ?? ?? ?? ?? ?? ?? ??\n"));
+        assert_eq!(Dump::from_flipper(&text).unwrap(), dump);
+
+        let mut mfd = Dump::from_mfd(&image("B4F4E9C26B0804006263646566676869")).unwrap();
+        mfd.keys = vec![SectorKeys::new(Some([0xff; 6]), Some([0xff; 6])); 16];
+        assert_eq!(Dump::from_flipper(&mfd.to_flipper()).unwrap(), mfd);
+    }
+
+    #[test]
+    fn flipper_rejects() {
+        let truncated: String = FLIPPER
+            .lines()
+            .filter(|line| !line.starts_with("Block 63:"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert!(Dump::from_flipper(&truncated).is_err());
+
+        let ultralight = FLIPPER.replace(
+            "Device type: Mifare Classic",
+            "Device type: NTAG/Ultralight",
+        );
+        assert!(Dump::from_flipper(&ultralight).is_err());
+        assert!(Dump::from_flipper(&FLIPPER.replace("Block 5:", "Block 6:")).is_err());
+        assert!(Dump::from_flipper(&FLIPPER.replace("UID: 04", "UID: ??")).is_err());
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn proxmark_fixture() {
+        let dump = Dump::from_proxmark_json(PROXMARK).unwrap();
+        assert_eq!(dump.layout, Layout::Classic1k);
+        assert_eq!(dump.uid, [0xb4, 0xf4, 0xe9, 0xc2]);
+        assert_eq!(dump.atqa, [0x00, 0x04]);
+        assert_eq!(dump.sak, 0x08);
+        assert_eq!(&dump.blocks[4].unwrap()[..13], b"Hello, world!");
+        assert_eq!(
+            dump.keys[1],
+            SectorKeys::new(
+                Some([0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5]),
+                Some([0xb0, 0xb1, 0xb2, 0xb3, 0xb4, 0xb5])
+            )
+        );
+        assert_eq!(
+            dump.keys[2],
+            SectorKeys::new(Some([0xff; 6]), Some([0xff; 6]))
+        );
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn proxmark_round_trip() {
+        let dump = Dump::from_proxmark_json(PROXMARK).unwrap();
+        assert_eq!(
+            Dump::from_proxmark_json(&dump.to_proxmark_json()).unwrap(),
+            dump
+        );
+
+        // keys that aren't known aren't written
+        let mut partial = dump.clone();
+        partial.keys[3] = SectorKeys::new(None, Some([0xff; 6]));
+        let json = partial.to_proxmark_json();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(value["SectorKeys"]["3"].get("KeyA").is_none());
+        assert_eq!(Dump::from_proxmark_json(&json).unwrap(), partial);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn proxmark_rejects() {
+        let mut value: serde_json::Value = serde_json::from_str(PROXMARK).unwrap();
+        value["blocks"].as_object_mut().unwrap().remove("63");
+        assert!(Dump::from_proxmark_json(&value.to_string()).is_err());
+
+        let mut value: serde_json::Value = serde_json::from_str(PROXMARK).unwrap();
+        value["blocks"]["5"] = "00".into();
+        assert!(Dump::from_proxmark_json(&value.to_string()).is_err());
+        assert!(Dump::from_proxmark_json("{}").is_err());
+    }
+}
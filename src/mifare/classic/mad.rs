@@ -0,0 +1,482 @@
+//! The MIFARE Application Directory, which records which application owns
+//! each sector of a card shared between several of them.
+//!
+//! MAD v1 lives in blocks 1 and 2 of sector 0 and covers sectors 1 to 15.
+//! MAD v2, on 4K cards, adds blocks 64 to 66 of sector 16 for sectors 17 to
+//! 39. Each starts with a CRC8 and an info byte pointing to the card
+//! publisher sector, followed by the 2 byte ID of the application in each
+//! sector.
+
+use super::{first_block, Block, Key, KeyType, Layout, MifareClassic, BLOCK_SIZE};
+use crate::{Error, Result};
+
+use std::convert::TryInto;
+
+/// Key A of the MAD sectors, which anyone may use to read them.
+pub const MAD_KEY: Key = [0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5];
+/// The sector holding MAD v2.
+pub const MAD2_SECTOR: u8 = 16;
+
+const MAD1_SECTORS: u8 = 15;
+const MAD2_SECTORS: u8 = 23;
+const CRC_PRESET: u8 = 0xc7;
+const CRC_POLYNOMIAL: u8 = 0x1d;
+/// Bits of the general purpose byte of sector 0.
+const GPB_MAD_AVAILABLE: u8 = 0x80;
+const GPB_MULTI_APPLICATION: u8 = 0x40;
+const GPB_VERSION: u8 = 0x03;
+const INFO_PUBLISHER_SECTOR: u8 = 0x3f;
+
+/// An application ID: the function cluster code in the high byte and the
+/// application code in the low one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Aid(pub u16);
+
+impl Aid {
+    pub const FREE: Aid = Aid(0x0000);
+    pub const DEFECT: Aid = Aid(0x0001);
+    pub const RESERVED: Aid = Aid(0x0002);
+    pub const ADDITIONAL_DIRECTORY_INFO: Aid = Aid(0x0003);
+    pub const CARDHOLDER_INFO: Aid = Aid(0x0004);
+    /// Marks sectors the card doesn't have.
+    pub const NOT_APPLICABLE: Aid = Aid(0x0005);
+    pub const NDEF: Aid = Aid(0xe103);
+
+    pub fn function_cluster(self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    pub fn application_code(self) -> u8 {
+        self.0 as u8
+    }
+
+    /// Whether this is one of the IDs of the administration cluster, like
+    /// [`FREE`](#associatedconstant.FREE) or
+    /// [`DEFECT`](#associatedconstant.DEFECT), rather than an application.
+    pub fn is_administrative(self) -> bool {
+        self.function_cluster() == 0
+    }
+}
+
+/// A MIFARE Application Directory.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Mad {
+    layout: Layout,
+    publisher_sector: Option<u8>,
+    /// Indexed by sector, skipping the sectors holding the MAD.
+    aids: Vec<Aid>,
+}
+
+impl Mad {
+    /// An empty directory for a card, version 2 on 4K cards and version 1
+    /// on the others. Sectors a Mini doesn't have are marked as such.
+    pub fn new(layout: Layout) -> Self {
+        let count = match layout {
+            Layout::Classic4k => MAD1_SECTORS + MAD2_SECTORS,
+            Layout::Mini | Layout::Classic1k => MAD1_SECTORS,
+        };
+        let mut mad = Mad {
+            layout,
+            publisher_sector: None,
+            aids: vec![Aid::FREE; usize::from(count)],
+        };
+        for sector in layout.sector_count()..=MAD1_SECTORS {
+            mad.aids[usize::from(sector - 1)] = Aid::NOT_APPLICABLE;
+        }
+        mad
+    }
+
+    /// Decodes blocks 1 and 2 and, for version 2, blocks 64 to 66, checking
+    /// their CRCs.
+    pub fn decode(layout: Layout, mad1: &[Block; 2], mad2: Option<&[Block; 3]>) -> Result<Self> {
+        if mad2.is_some() && layout != Layout::Classic4k {
+            return Err(Error::new("MAD v2 is only found on 4K cards"));
+        }
+
+        let mut mad = Mad::new(layout);
+        mad.aids.clear();
+        let mut info = decode_section(&mad1.concat(), &mut mad.aids)?;
+        if let Some(mad2) = mad2 {
+            // MAD v2's info byte can point past sector 15, and wins
+            let info2 = decode_section(&mad2.concat(), &mut mad.aids)?;
+            if publisher_sector(info2).is_some() {
+                info = info2;
+            }
+        }
+        mad.set_publisher_sector(publisher_sector(info))?;
+        Ok(mad)
+    }
+
+    /// Encodes blocks 1 and 2 and, for version 2, blocks 64 to 66.
+    pub fn encode(&self) -> ([Block; 2], Option<[Block; 3]>) {
+        let info = self.publisher_sector.unwrap_or_default();
+        let (mad1, mad2) = self.aids.split_at(usize::from(MAD1_SECTORS));
+
+        let mad1_info = if info <= MAD1_SECTORS { info } else { 0 };
+        let mad1 = encode_section(mad1_info, mad1);
+        let blocks = [
+            mad1[..BLOCK_SIZE].try_into().unwrap(),
+            mad1[BLOCK_SIZE..].try_into().unwrap(),
+        ];
+        if self.version() == 1 {
+            return (blocks, None);
+        }
+
+        let mad2 = encode_section(info, mad2);
+        let mut mad2_blocks = [[0; BLOCK_SIZE]; 3];
+        for (block, chunk) in mad2_blocks.iter_mut().zip(mad2.chunks(BLOCK_SIZE)) {
+            block.copy_from_slice(chunk);
+        }
+        (blocks, Some(mad2_blocks))
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn version(&self) -> u8 {
+        if self.aids.len() > usize::from(MAD1_SECTORS) {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// The general purpose byte sector 0's trailer should have for this
+    /// directory to be found.
+    pub fn gpb(&self) -> u8 {
+        GPB_MAD_AVAILABLE | GPB_MULTI_APPLICATION | self.version()
+    }
+
+    /// The sector holding information about who issued the card.
+    pub fn publisher_sector(&self) -> Option<u8> {
+        self.publisher_sector
+    }
+
+    pub fn set_publisher_sector(&mut self, sector: Option<u8>) -> Result<()> {
+        if let Some(sector) = sector {
+            self.index(sector)?;
+        }
+        self.publisher_sector = sector;
+        Ok(())
+    }
+
+    /// The application owning a sector, or `None` for the sectors holding
+    /// the MAD and those it doesn't cover.
+    pub fn aid(&self, sector: u8) -> Option<Aid> {
+        self.index(sector).ok().map(|index| self.aids[index])
+    }
+
+    pub fn set_aid(&mut self, sector: u8, aid: Aid) -> Result<()> {
+        let index = self.index(sector)?;
+        self.aids[index] = aid;
+        Ok(())
+    }
+
+    /// Every sector the directory covers, with its application.
+    pub fn entries(&self) -> impl Iterator<Item = (u8, Aid)> + '_ {
+        self.sectors().zip(self.aids.iter().copied())
+    }
+
+    /// The sectors an application owns, in order.
+    pub fn sectors_of(&self, aid: Aid) -> Vec<u8> {
+        self.entries()
+            .filter(|&(_, owner)| owner == aid)
+            .map(|(sector, _)| sector)
+            .collect()
+    }
+
+    /// The sectors marked free that the card has.
+    pub fn free_sectors(&self) -> Vec<u8> {
+        self.sectors_of(Aid::FREE)
+            .into_iter()
+            .filter(|&sector| self.layout.contains_sector(sector))
+            .collect()
+    }
+
+    /// Gives `count` free sectors to an application, returning them. Sectors
+    /// owned by anything else are never touched, and nothing changes if
+    /// there aren't enough free ones.
+    pub fn allocate(&mut self, aid: Aid, count: usize) -> Result<Vec<u8>> {
+        if aid.is_administrative() {
+            return Err(Error::new(
+                "Sectors can't be allocated to an administrative AID",
+            ));
+        }
+
+        let free = self.free_sectors();
+        if free.len() < count {
+            return Err(Error::new(&format!(
+                "{} free sectors are needed but only {} are left",
+                count,
+                free.len()
+            )));
+        }
+        let sectors = free[..count].to_vec();
+        for &sector in &sectors {
+            self.set_aid(sector, aid)?;
+        }
+        Ok(sectors)
+    }
+
+    /// Marks every sector of an application free, returning them.
+    pub fn release(&mut self, aid: Aid) -> Vec<u8> {
+        let sectors = self.sectors_of(aid);
+        for owner in self.aids.iter_mut().filter(|owner| **owner == aid) {
+            *owner = Aid::FREE;
+        }
+        sectors
+    }
+
+    fn sectors(&self) -> impl Iterator<Item = u8> {
+        (1..=MAD1_SECTORS).chain(MAD2_SECTOR + 1..MAD2_SECTOR + 1 + MAD2_SECTORS)
+    }
+
+    fn index(&self, sector: u8) -> Result<usize> {
+        self.sectors()
+            .take(self.aids.len())
+            .position(|covered| covered == sector)
+            .ok_or_else(|| {
+                Error::new(&format!(
+                    "Sector {} isn't covered by MAD v{}",
+                    sector,
+                    self.version()
+                ))
+            })
+    }
+}
+
+impl<'a, 'context> MifareClassic<'a, 'context> {
+    /// Reads the card's directory with the public [`MAD_KEY`](constant.MAD_KEY.html).
+    pub fn read_mad(&mut self) -> Result<Mad> {
+        self.authenticate(0, KeyType::A, &MAD_KEY)?;
+        let gpb = self.read_trailer(0)?.gpb;
+        if gpb & GPB_MAD_AVAILABLE == 0 {
+            return Err(Error::new("The card has no MAD"));
+        }
+
+        let mad1 = [self.read_block(1)?, self.read_block(2)?];
+        match gpb & GPB_VERSION {
+            1 => Mad::decode(self.layout(), &mad1, None),
+            2 => {
+                let first = first_block(MAD2_SECTOR);
+                self.authenticate(first, KeyType::A, &MAD_KEY)?;
+                let mad2 = [
+                    self.read_block(first)?,
+                    self.read_block(first + 1)?,
+                    self.read_block(first + 2)?,
+                ];
+                Mad::decode(self.layout(), &mad1, Some(&mad2))
+            }
+            version => Err(Error::new(&format!("Unknown MAD version {}", version))),
+        }
+    }
+
+    /// Writes a directory with key B of the MAD sectors. Sector 0's general
+    /// purpose byte is left alone, see [`Mad::gpb`](struct.Mad.html#method.gpb).
+    pub fn write_mad(&mut self, mad: &Mad, key_b: &Key) -> Result<()> {
+        if mad.layout() != self.layout() {
+            return Err(Error::new("The MAD was made for a different layout"));
+        }
+
+        let (mad1, mad2) = mad.encode();
+        self.authenticate(0, KeyType::B, key_b)?;
+        self.write_block(1, &mad1[0])?;
+        self.write_block(2, &mad1[1])?;
+        if let Some(mad2) = mad2 {
+            let first = first_block(MAD2_SECTOR);
+            self.authenticate(first, KeyType::B, key_b)?;
+            for (block, data) in (first..).zip(mad2.iter()) {
+                self.write_block(block, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives `count` free sectors to an application in the card's directory,
+    /// returning them. The directory is read back afterwards to make sure
+    /// the card holds what was written.
+    pub fn allocate_sectors(&mut self, aid: Aid, count: usize, key_b: &Key) -> Result<Vec<u8>> {
+        let mut mad = self.read_mad()?;
+        let sectors = mad.allocate(aid, count)?;
+        self.write_mad(&mad, key_b)?;
+        if self.read_mad()? != mad {
+            return Err(Error::new("The MAD read back differs from the one written"));
+        }
+        Ok(sectors)
+    }
+}
+
+/// The CRC8 each part of the directory starts with, over the rest of it.
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(CRC_PRESET, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                crc << 1 ^ CRC_POLYNOMIAL
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Checks one part of the directory and appends its AIDs, returning its
+/// info byte.
+fn decode_section(bytes: &[u8], aids: &mut Vec<Aid>) -> Result<u8> {
+    if crc8(&bytes[1..]) != bytes[0] {
+        return Err(Error::new(&format!(
+            "MAD has CRC {:02x} where {:02x} is expected",
+            bytes[0],
+            crc8(&bytes[1..])
+        )));
+    }
+    aids.extend(
+        bytes[2..]
+            .chunks_exact(2)
+            .map(|aid| Aid(u16::from_le_bytes([aid[0], aid[1]]))),
+    );
+    Ok(bytes[1])
+}
+
+fn encode_section(info: u8, aids: &[Aid]) -> Vec<u8> {
+    let mut bytes = vec![0, info];
+    for aid in aids {
+        bytes.extend_from_slice(&aid.0.to_le_bytes());
+    }
+    bytes[0] = crc8(&bytes[1..]);
+    bytes
+}
+
+fn publisher_sector(info: u8) -> Option<u8> {
+    match info & INFO_PUBLISHER_SECTOR {
+        0 => None,
+        sector => Some(sector),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::decode_hex;
+
+    fn blocks(hex: &str) -> Vec<Block> {
+        decode_hex(hex)
+            .unwrap()
+            .chunks_exact(BLOCK_SIZE)
+            .map(|block| block.try_into().unwrap())
+            .collect()
+    }
+
+    fn mad1(hex: &str) -> [Block; 2] {
+        blocks(hex).as_slice().try_into().unwrap()
+    }
+
+    // the MAD1 example of AN10787
+    const EXAMPLE: &str = "8901010801080108000000000000040003100310021002100000000000001130";
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc8(&decode_hex(&EXAMPLE[2..]).unwrap()), 0x89);
+        // an NDEF card's MAD, every sector holding NDEF
+        let ndef = decode_hex(&format!("01{}", "03E1".repeat(15))).unwrap();
+        assert_eq!(crc8(&ndef), 0x14);
+        assert_eq!(crc8(&[]), CRC_PRESET);
+    }
+
+    #[test]
+    fn decode_example() {
+        let mad = Mad::decode(Layout::Classic1k, &mad1(EXAMPLE), None).unwrap();
+        assert_eq!(mad.version(), 1);
+        assert_eq!(mad.publisher_sector(), Some(1));
+        assert_eq!(mad.aid(0), None);
+        assert_eq!(mad.sectors_of(Aid(0x0801)), [1, 2, 3]);
+        assert_eq!(mad.aid(7), Some(Aid::CARDHOLDER_INFO));
+        assert_eq!(mad.sectors_of(Aid(0x1003)), [8, 9]);
+        assert_eq!(mad.sectors_of(Aid(0x1002)), [10, 11]);
+        assert_eq!(mad.aid(15), Some(Aid(0x3011)));
+        assert_eq!(mad.free_sectors(), [4, 5, 6, 12, 13, 14]);
+        assert_eq!(mad.aid(16), None);
+
+        assert_eq!(mad.encode(), (mad1(EXAMPLE), None));
+
+        let mut corrupted = mad1(EXAMPLE);
+        corrupted[1][15] ^= 0x01;
+        assert!(Mad::decode(Layout::Classic1k, &corrupted, None).is_err());
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut mad = Mad::new(Layout::Classic4k);
+        assert_eq!(mad.version(), 2);
+        mad.set_aid(1, Aid::NDEF).unwrap();
+        mad.set_aid(15, Aid::CARDHOLDER_INFO).unwrap();
+        mad.set_aid(17, Aid::NDEF).unwrap();
+        mad.set_aid(39, Aid(0x1234)).unwrap();
+        mad.set_publisher_sector(Some(15)).unwrap();
+        assert!(mad.set_aid(16, Aid::NDEF).is_err());
+        assert!(mad.set_aid(40, Aid::NDEF).is_err());
+
+        let (blocks1, blocks2) = mad.encode();
+        let blocks2 = blocks2.unwrap();
+        assert_eq!(
+            Mad::decode(Layout::Classic4k, &blocks1, Some(&blocks2)).unwrap(),
+            mad
+        );
+        assert!(Mad::decode(Layout::Classic1k, &blocks1, Some(&blocks2)).is_err());
+
+        let mini = Mad::new(Layout::Mini);
+        assert_eq!(mini.aid(4), Some(Aid::FREE));
+        assert_eq!(mini.aid(5), Some(Aid::NOT_APPLICABLE));
+        assert_eq!(mini.free_sectors(), [1, 2, 3, 4]);
+        let (blocks1, blocks2) = mini.encode();
+        assert_eq!(blocks2, None);
+        assert_eq!(Mad::decode(Layout::Mini, &blocks1, None).unwrap(), mini);
+    }
+
+    #[test]
+    fn v2_info_byte_wins() {
+        let mut mad = Mad::new(Layout::Classic4k);
+        mad.set_publisher_sector(Some(20)).unwrap();
+        let (blocks1, blocks2) = mad.encode();
+        // a publisher sector past 15 can't be written in MAD v1
+        assert_eq!(blocks1[0][1], 0);
+        assert_eq!(blocks2.unwrap()[0][1], 20);
+        let decoded = Mad::decode(Layout::Classic4k, &blocks1, blocks2.as_ref()).unwrap();
+        assert_eq!(decoded.publisher_sector(), Some(20));
+
+        // MAD v2 pointing nowhere leaves MAD v1's publisher sector
+        mad.set_publisher_sector(Some(3)).unwrap();
+        let (blocks1, _) = mad.encode();
+        let (_, blocks2) = Mad::new(Layout::Classic4k).encode();
+        let decoded = Mad::decode(Layout::Classic4k, &blocks1, blocks2.as_ref()).unwrap();
+        assert_eq!(decoded.publisher_sector(), Some(3));
+    }
+
+    #[test]
+    fn allocate_and_release() {
+        let mut mad = Mad::new(Layout::Classic1k);
+        mad.set_aid(1, Aid::CARDHOLDER_INFO).unwrap();
+        mad.set_aid(3, Aid(0x1002)).unwrap();
+
+        assert_eq!(mad.allocate(Aid::NDEF, 3).unwrap(), [2, 4, 5]);
+        assert_eq!(mad.sectors_of(Aid::NDEF), [2, 4, 5]);
+        assert_eq!(mad.aid(3), Some(Aid(0x1002)));
+        assert!(mad.allocate(Aid::DEFECT, 1).is_err());
+
+        let before = mad.clone();
+        assert!(mad.allocate(Aid(0x1003), 11).is_err());
+        assert_eq!(mad, before);
+        assert_eq!(mad.allocate(Aid(0x1003), 10).unwrap().len(), 10);
+        assert!(mad.free_sectors().is_empty());
+
+        assert_eq!(mad.release(Aid::NDEF), [2, 4, 5]);
+        assert_eq!(mad.free_sectors(), [2, 4, 5]);
+        assert_eq!(mad.aid(1), Some(Aid::CARDHOLDER_INFO));
+        assert!(mad.release(Aid::NDEF).is_empty());
+
+        // a Mini's missing sectors are never handed out
+        let mut mini = Mad::new(Layout::Mini);
+        assert!(mini.allocate(Aid::NDEF, 5).is_err());
+        assert_eq!(mini.allocate(Aid::NDEF, 4).unwrap(), [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,509 @@
+//! Sector trailers: the two keys of a sector and the access conditions that
+//! say what each key may do with each block.
+//!
+//! The access conditions are three bits, C1 C2 C3, per block group. The card
+//! stores them in bytes 6 to 8 of the trailer together with an inverted copy,
+//! and a sector whose copies disagree can never be authenticated to again.
+
+use super::{trailer_block, Block, Key, KeyType, MifareClassic, BLOCK_SIZE, KEY_SIZE};
+use crate::{Error, Result};
+
+use std::convert::TryInto;
+
+/// What the keys may do with a data block, named after the datasheet's
+/// description of each C1 C2 C3 combination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DataAccess {
+    /// `000`: everything with either key
+    Transport,
+    /// `010`: read with either key
+    ReadOnly,
+    /// `100`: read with either key, write with B
+    ReadWriteB,
+    /// `110`: value block, read with either key, write and increment with B,
+    /// decrement with either key
+    ValueIncrementB,
+    /// `001`: value block, read and decrement with either key
+    ValueDecrementOnly,
+    /// `011`: read and write with B
+    KeyBOnly,
+    /// `101`: read with B
+    ReadOnlyB,
+    /// `111`: nothing
+    Never,
+}
+
+/// What the keys may do with the trailer itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrailerAccess {
+    /// `000`: A writes both keys and reads key B, the access bits are frozen
+    KeyAWritesKeys,
+    /// `010`: A reads key B, nothing can be written
+    ReadOnlyKeyBReadable,
+    /// `100`: B writes both keys, the access bits are frozen
+    KeyBWritesKeys,
+    /// `110`: nothing can be written
+    Frozen,
+    /// `001`: A writes and reads everything but itself, as shipped
+    Transport,
+    /// `011`: B writes both keys and the access bits
+    KeyBWritesAll,
+    /// `101`: B writes the access bits, the keys are frozen
+    KeyBWritesAccess,
+    /// `111`: nothing can be written, same as `110`
+    FrozenAlternate,
+}
+
+impl DataAccess {
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0b000 => DataAccess::Transport,
+            0b010 => DataAccess::ReadOnly,
+            0b100 => DataAccess::ReadWriteB,
+            0b110 => DataAccess::ValueIncrementB,
+            0b001 => DataAccess::ValueDecrementOnly,
+            0b011 => DataAccess::KeyBOnly,
+            0b101 => DataAccess::ReadOnlyB,
+            _ => DataAccess::Never,
+        }
+    }
+
+    /// C1 C2 C3, C1 being the most significant bit.
+    pub fn bits(self) -> u8 {
+        match self {
+            DataAccess::Transport => 0b000,
+            DataAccess::ReadOnly => 0b010,
+            DataAccess::ReadWriteB => 0b100,
+            DataAccess::ValueIncrementB => 0b110,
+            DataAccess::ValueDecrementOnly => 0b001,
+            DataAccess::KeyBOnly => 0b011,
+            DataAccess::ReadOnlyB => 0b101,
+            DataAccess::Never => 0b111,
+        }
+    }
+
+    pub fn can_read(self, key: KeyType) -> bool {
+        match self {
+            DataAccess::Transport
+            | DataAccess::ReadOnly
+            | DataAccess::ReadWriteB
+            | DataAccess::ValueIncrementB
+            | DataAccess::ValueDecrementOnly => true,
+            DataAccess::KeyBOnly | DataAccess::ReadOnlyB => key == KeyType::B,
+            DataAccess::Never => false,
+        }
+    }
+
+    pub fn can_write(self, key: KeyType) -> bool {
+        match self {
+            DataAccess::Transport => true,
+            DataAccess::ReadWriteB | DataAccess::ValueIncrementB | DataAccess::KeyBOnly => {
+                key == KeyType::B
+            }
+            _ => false,
+        }
+    }
+
+    pub fn can_increment(self, key: KeyType) -> bool {
+        match self {
+            DataAccess::Transport => true,
+            DataAccess::ValueIncrementB => key == KeyType::B,
+            _ => false,
+        }
+    }
+
+    /// Whether the key may decrement, transfer and restore.
+    pub fn can_decrement(self, _key: KeyType) -> bool {
+        matches!(
+            self,
+            DataAccess::Transport | DataAccess::ValueIncrementB | DataAccess::ValueDecrementOnly
+        )
+    }
+}
+
+impl TrailerAccess {
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0b000 => TrailerAccess::KeyAWritesKeys,
+            0b010 => TrailerAccess::ReadOnlyKeyBReadable,
+            0b100 => TrailerAccess::KeyBWritesKeys,
+            0b110 => TrailerAccess::Frozen,
+            0b001 => TrailerAccess::Transport,
+            0b011 => TrailerAccess::KeyBWritesAll,
+            0b101 => TrailerAccess::KeyBWritesAccess,
+            _ => TrailerAccess::FrozenAlternate,
+        }
+    }
+
+    /// C1 C2 C3, C1 being the most significant bit.
+    pub fn bits(self) -> u8 {
+        match self {
+            TrailerAccess::KeyAWritesKeys => 0b000,
+            TrailerAccess::ReadOnlyKeyBReadable => 0b010,
+            TrailerAccess::KeyBWritesKeys => 0b100,
+            TrailerAccess::Frozen => 0b110,
+            TrailerAccess::Transport => 0b001,
+            TrailerAccess::KeyBWritesAll => 0b011,
+            TrailerAccess::KeyBWritesAccess => 0b101,
+            TrailerAccess::FrozenAlternate => 0b111,
+        }
+    }
+
+    /// Whether key B can be read back with key A. A readable key B is plain
+    /// data and the card refuses to authenticate with it.
+    pub fn key_b_readable(self) -> bool {
+        matches!(
+            self,
+            TrailerAccess::KeyAWritesKeys
+                | TrailerAccess::ReadOnlyKeyBReadable
+                | TrailerAccess::Transport
+        )
+    }
+
+    pub fn can_write_keys(self, key: KeyType) -> bool {
+        match self {
+            TrailerAccess::KeyAWritesKeys | TrailerAccess::Transport => key == KeyType::A,
+            TrailerAccess::KeyBWritesKeys | TrailerAccess::KeyBWritesAll => key == KeyType::B,
+            _ => false,
+        }
+    }
+
+    pub fn can_write_access(self, key: KeyType) -> bool {
+        match self {
+            TrailerAccess::Transport => key == KeyType::A,
+            TrailerAccess::KeyBWritesAll | TrailerAccess::KeyBWritesAccess => key == KeyType::B,
+            _ => false,
+        }
+    }
+
+    /// Whether a key the card will authenticate with may ever change the
+    /// access conditions again.
+    pub fn access_writable(self) -> bool {
+        self.usable_keys()
+            .iter()
+            .any(|&key| self.can_write_access(key))
+    }
+
+    fn usable_keys(self) -> &'static [KeyType] {
+        if self.key_b_readable() {
+            &[KeyType::A]
+        } else {
+            &[KeyType::A, KeyType::B]
+        }
+    }
+}
+
+/// The access conditions of a sector. On a 4K's big sectors each data group
+/// covers five blocks, otherwise one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AccessConditions {
+    pub data: [DataAccess; 3],
+    pub trailer: TrailerAccess,
+}
+
+impl Default for AccessConditions {
+    /// The conditions cards are shipped with.
+    fn default() -> Self {
+        AccessConditions {
+            data: [DataAccess::Transport; 3],
+            trailer: TrailerAccess::Transport,
+        }
+    }
+}
+
+impl AccessConditions {
+    /// Decodes bytes 6 to 8 of a trailer, failing if the inverted copies
+    /// disagree.
+    pub fn decode(bytes: [u8; 3]) -> Result<Self> {
+        let c1 = bytes[1] >> 4;
+        let c2 = bytes[2] & 0x0f;
+        let c3 = bytes[2] >> 4;
+
+        if bytes[0] & 0x0f != !c1 & 0x0f
+            || bytes[0] >> 4 != !c2 & 0x0f
+            || bytes[1] & 0x0f != !c3 & 0x0f
+        {
+            return Err(Error::new("Access bits don't match their inverted copy"));
+        }
+
+        let group = |i: u8| ((c1 >> i) & 1) << 2 | ((c2 >> i) & 1) << 1 | ((c3 >> i) & 1);
+        Ok(AccessConditions {
+            data: [
+                DataAccess::from_bits(group(0)),
+                DataAccess::from_bits(group(1)),
+                DataAccess::from_bits(group(2)),
+            ],
+            trailer: TrailerAccess::from_bits(group(3)),
+        })
+    }
+
+    pub fn encode(&self) -> [u8; 3] {
+        let groups = [
+            self.data[0].bits(),
+            self.data[1].bits(),
+            self.data[2].bits(),
+            self.trailer.bits(),
+        ];
+        let column = |shift: u8| {
+            groups
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (i, bits)| acc | ((bits >> shift) & 1) << i)
+        };
+        let (c1, c2, c3) = (column(2), column(1), column(0));
+
+        [
+            (!c2 & 0x0f) << 4 | (!c1 & 0x0f),
+            c1 << 4 | (!c3 & 0x0f),
+            c3 << 4 | c2,
+        ]
+    }
+
+    /// Refuses conditions that would leave part of the sector out of reach
+    /// for good: the access bits can't be changed anymore and a data group
+    /// can't be read by any key the card will authenticate with.
+    pub fn validate(&self) -> Result<()> {
+        if self.trailer.access_writable() {
+            return Ok(());
+        }
+
+        let keys = self.trailer.usable_keys();
+        for (group, access) in self.data.iter().enumerate() {
+            if !keys.iter().any(|&key| access.can_read(key)) {
+                return Err(Error::new(&format!(
+                    "Data group {} would be permanently inaccessible",
+                    group
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The last block of a sector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SectorTrailer {
+    /// Always reads back as zeros.
+    pub key_a: Key,
+    pub access: AccessConditions,
+    /// The general purpose byte, free for the application to use.
+    pub gpb: u8,
+    /// Reads back as zeros unless the access conditions make it readable.
+    pub key_b: Key,
+}
+
+impl SectorTrailer {
+    pub fn new(key_a: Key, access: AccessConditions, key_b: Key) -> Self {
+        SectorTrailer {
+            key_a,
+            access,
+            gpb: 0x69,
+            key_b,
+        }
+    }
+
+    pub fn decode(block: &Block) -> Result<Self> {
+        Ok(SectorTrailer {
+            key_a: block[0..KEY_SIZE].try_into().unwrap(),
+            access: AccessConditions::decode(block[6..9].try_into().unwrap())?,
+            gpb: block[9],
+            key_b: block[10..BLOCK_SIZE].try_into().unwrap(),
+        })
+    }
+
+    /// Encodes the trailer, refusing to if [`validate`](#method.validate)
+    /// fails.
+    pub fn encode(&self) -> Result<Block> {
+        self.validate()?;
+
+        let mut block = [0; BLOCK_SIZE];
+        block[0..KEY_SIZE].copy_from_slice(&self.key_a);
+        block[6..9].copy_from_slice(&self.access.encode());
+        block[9] = self.gpb;
+        block[10..BLOCK_SIZE].copy_from_slice(&self.key_b);
+        Ok(block)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.access.validate()
+    }
+}
+
+impl Default for SectorTrailer {
+    /// A trailer as shipped: transport access conditions and both keys
+    /// `FFFFFFFFFFFF`.
+    fn default() -> Self {
+        SectorTrailer::new(
+            [0xff; KEY_SIZE],
+            AccessConditions::default(),
+            [0xff; KEY_SIZE],
+        )
+    }
+}
+
+impl<'a, 'context> MifareClassic<'a, 'context> {
+    pub fn read_trailer(&mut self, sector: u8) -> Result<SectorTrailer> {
+        self.check_sector(sector)?;
+        SectorTrailer::decode(&self.read_block(trailer_block(sector))?)
+    }
+
+    /// Writes a sector's trailer once it passed validation. The sector must be
+    /// authenticated with a key allowed to write it.
+    pub fn write_trailer(&mut self, sector: u8, trailer: &SectorTrailer) -> Result<()> {
+        self.check_sector(sector)?;
+        let block = trailer.encode()?;
+        self.write_block(trailer_block(sector), &block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: [DataAccess; 8] = [
+        DataAccess::Transport,
+        DataAccess::ReadOnly,
+        DataAccess::ReadWriteB,
+        DataAccess::ValueIncrementB,
+        DataAccess::ValueDecrementOnly,
+        DataAccess::KeyBOnly,
+        DataAccess::ReadOnlyB,
+        DataAccess::Never,
+    ];
+    const TRAILER: [TrailerAccess; 8] = [
+        TrailerAccess::KeyAWritesKeys,
+        TrailerAccess::ReadOnlyKeyBReadable,
+        TrailerAccess::KeyBWritesKeys,
+        TrailerAccess::Frozen,
+        TrailerAccess::Transport,
+        TrailerAccess::KeyBWritesAll,
+        TrailerAccess::KeyBWritesAccess,
+        TrailerAccess::FrozenAlternate,
+    ];
+
+    #[test]
+    fn transport_configuration() {
+        let block = [
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x07, 0x80, 0x69, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff,
+        ];
+        let trailer = SectorTrailer::decode(&block).unwrap();
+        assert_eq!(trailer, SectorTrailer::default());
+        assert_eq!(trailer.access, AccessConditions::default());
+        assert_eq!(trailer.encode().unwrap(), block);
+        assert_eq!(AccessConditions::default().encode(), [0xff, 0x07, 0x80]);
+    }
+
+    #[test]
+    fn nfc_forum_conditions() {
+        // MAD sectors, writable with key B
+        let mad = AccessConditions::decode([0x78, 0x77, 0x88]).unwrap();
+        assert_eq!(mad.data, [DataAccess::ReadWriteB; 3]);
+        assert_eq!(mad.trailer, TrailerAccess::KeyBWritesAll);
+
+        // NDEF sectors, writable
+        let ndef = AccessConditions::decode([0x7f, 0x07, 0x88]).unwrap();
+        assert_eq!(ndef.data, [DataAccess::Transport; 3]);
+        assert_eq!(ndef.trailer, TrailerAccess::KeyBWritesAll);
+
+        // NDEF sectors, read-only
+        let read_only = AccessConditions::decode([0x07, 0x8f, 0x0f]).unwrap();
+        assert_eq!(read_only.data, [DataAccess::ReadOnly; 3]);
+        assert_eq!(read_only.trailer, TrailerAccess::Frozen);
+        assert!(read_only.validate().is_ok());
+
+        for conditions in &[mad, ndef, read_only] {
+            assert_eq!(
+                AccessConditions::decode(conditions.encode()).unwrap(),
+                *conditions
+            );
+        }
+    }
+
+    #[test]
+    fn every_combination_round_trips() {
+        for &trailer in &TRAILER {
+            for &first in &DATA {
+                for &second in &DATA {
+                    for &third in &DATA {
+                        let conditions = AccessConditions {
+                            data: [first, second, third],
+                            trailer,
+                        };
+                        assert_eq!(
+                            AccessConditions::decode(conditions.encode()).unwrap(),
+                            conditions
+                        );
+                    }
+                }
+            }
+        }
+        for (bits, access) in DATA.iter().enumerate() {
+            assert_eq!(DataAccess::from_bits(access.bits()), *access);
+            assert_eq!(
+                TrailerAccess::from_bits(TRAILER[bits].bits()),
+                TRAILER[bits]
+            );
+        }
+    }
+
+    #[test]
+    fn inverted_copy_mismatch() {
+        for &bytes in &[[0xff, 0x07, 0x81], [0xfe, 0x07, 0x80], [0xff, 0x17, 0x80]] {
+            assert!(AccessConditions::decode(bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn permissions() {
+        assert!(DataAccess::ReadOnlyB.can_read(KeyType::B));
+        assert!(!DataAccess::ReadOnlyB.can_read(KeyType::A));
+        assert!(!DataAccess::ReadOnly.can_write(KeyType::B));
+        assert!(DataAccess::ValueIncrementB.can_increment(KeyType::B));
+        assert!(!DataAccess::ValueIncrementB.can_increment(KeyType::A));
+        assert!(DataAccess::ValueDecrementOnly.can_decrement(KeyType::A));
+        assert!(!DataAccess::ValueDecrementOnly.can_write(KeyType::B));
+
+        assert!(TrailerAccess::Transport.key_b_readable());
+        assert!(TrailerAccess::Transport.can_write_access(KeyType::A));
+        assert!(!TrailerAccess::KeyBWritesAll.key_b_readable());
+        assert!(TrailerAccess::KeyBWritesAccess.can_write_access(KeyType::B));
+        assert!(!TrailerAccess::KeyBWritesAccess.can_write_keys(KeyType::B));
+        assert!(!TrailerAccess::Frozen.access_writable());
+    }
+
+    #[test]
+    fn unreachable_sectors_are_refused() {
+        let never = AccessConditions {
+            data: [
+                DataAccess::Transport,
+                DataAccess::Never,
+                DataAccess::Transport,
+            ],
+            trailer: TrailerAccess::Frozen,
+        };
+        assert!(never.validate().is_err());
+        let trailer = SectorTrailer::new([0xff; KEY_SIZE], never, [0xff; KEY_SIZE]);
+        assert!(trailer.encode().is_err());
+
+        // still fixable while the access bits can be written
+        let fixable = AccessConditions {
+            trailer: TrailerAccess::KeyBWritesAll,
+            ..never
+        };
+        assert!(fixable.validate().is_ok());
+
+        // key B is readable, so the card won't take it
+        let key_b_only = AccessConditions {
+            data: [DataAccess::KeyBOnly; 3],
+            trailer: TrailerAccess::ReadOnlyKeyBReadable,
+        };
+        assert!(key_b_only.validate().is_err());
+        let with_key_b = AccessConditions {
+            trailer: TrailerAccess::Frozen,
+            ..key_b_only
+        };
+        assert!(with_key_b.validate().is_ok());
+    }
+}
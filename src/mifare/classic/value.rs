@@ -0,0 +1,273 @@
+//! Value blocks: a signed 32 bit value stored three times (once inverted)
+//! together with an address byte stored four times (twice inverted), which
+//! the card can increment and decrement itself.
+
+use super::{is_trailer, sector_of, Block, MifareClassic, BLOCK_SIZE};
+use crate::ffi;
+use crate::{Error, Result};
+
+const DECREMENT: u8 = 0xC0;
+const INCREMENT: u8 = 0xC1;
+const RESTORE: u8 = 0xC2;
+const TRANSFER: u8 = 0xB0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ValueBlock {
+    pub value: i32,
+    /// Free for the application to use, usually the block number of a backup.
+    pub address: u8,
+}
+
+impl ValueBlock {
+    pub fn new(value: i32, address: u8) -> Self {
+        ValueBlock { value, address }
+    }
+
+    pub fn encode(&self) -> Block {
+        let value = self.value.to_le_bytes();
+        let inverted = (!self.value).to_le_bytes();
+
+        let mut block = [0; BLOCK_SIZE];
+        block[0..4].copy_from_slice(&value);
+        block[4..8].copy_from_slice(&inverted);
+        block[8..12].copy_from_slice(&value);
+        block[12] = self.address;
+        block[13] = !self.address;
+        block[14] = self.address;
+        block[15] = !self.address;
+        block
+    }
+
+    /// Decodes a value block, checking all the redundant copies agree.
+    pub fn decode(block: &Block) -> Result<Self> {
+        let word = |offset: usize| {
+            i32::from_le_bytes([
+                block[offset],
+                block[offset + 1],
+                block[offset + 2],
+                block[offset + 3],
+            ])
+        };
+        let value = word(0);
+
+        if word(4) != !value
+            || word(8) != value
+            || block[13] != !block[12]
+            || block[14] != block[12]
+            || block[15] != !block[12]
+        {
+            return Err(Error::new("Block isn't a valid value block"));
+        }
+
+        Ok(ValueBlock {
+            value,
+            address: block[12],
+        })
+    }
+}
+
+/// A value kept in two blocks of the same sector, so that an update torn by
+/// the card leaving the field can be detected and recovered.
+///
+/// Updates first write the new value to the backup, then copy the backup
+/// over the primary. Both blocks holding valid, equal values means the last
+/// update completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BackedValue {
+    primary: u8,
+    backup: u8,
+}
+
+impl BackedValue {
+    /// Fails with `NFC_EINVARG` unless the blocks are two different data
+    /// blocks of the same sector, which the restore and transfer copying the
+    /// backup over the primary need.
+    pub fn new(primary: u8, backup: u8) -> Result<Self> {
+        if primary == backup
+            || sector_of(primary) != sector_of(backup)
+            || is_trailer(primary)
+            || is_trailer(backup)
+        {
+            return Err(Error::from(ffi::NFC_EINVARG));
+        }
+        Ok(BackedValue { primary, backup })
+    }
+
+    pub fn primary(&self) -> u8 {
+        self.primary
+    }
+
+    pub fn backup(&self) -> u8 {
+        self.backup
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueState {
+    Consistent(ValueBlock),
+    /// An update was interrupted. If the backup is intact it holds the value
+    /// the update was writing.
+    Torn {
+        primary: Option<ValueBlock>,
+        backup: Option<ValueBlock>,
+    },
+}
+
+impl<'a, 'context> MifareClassic<'a, 'context> {
+    pub fn read_value(&mut self, block: u8) -> Result<ValueBlock> {
+        ValueBlock::decode(&self.read_block(block)?)
+    }
+
+    /// Formats a block as a value block.
+    pub fn write_value(&mut self, block: u8, value: &ValueBlock) -> Result<()> {
+        self.write_block(block, &value.encode())
+    }
+
+    /// Loads the value of `block` plus `amount` into the card's transfer
+    /// buffer. Nothing is stored until [`transfer`](#method.transfer).
+    pub fn increment(&mut self, block: u8, amount: u32) -> Result<()> {
+        self.value_operation(INCREMENT, block, amount)
+    }
+
+    /// Loads the value of `block` minus `amount` into the card's transfer
+    /// buffer. Nothing is stored until [`transfer`](#method.transfer).
+    pub fn decrement(&mut self, block: u8, amount: u32) -> Result<()> {
+        self.value_operation(DECREMENT, block, amount)
+    }
+
+    /// Loads the value of `block` into the card's transfer buffer, to copy it
+    /// to another block with [`transfer`](#method.transfer).
+    pub fn restore(&mut self, block: u8) -> Result<()> {
+        self.value_operation(RESTORE, block, 0)
+    }
+
+    /// Writes the transfer buffer to `block`.
+    pub fn transfer(&mut self, block: u8) -> Result<()> {
+        self.check_block(block)?;
+        self.initiator
+            .transceive_bytes(&[TRANSFER, block], BLOCK_SIZE, -1)
+            .map(|_| ())
+    }
+
+    fn value_operation(&mut self, command: u8, block: u8, operand: u32) -> Result<()> {
+        self.check_block(block)?;
+
+        let mut frame = vec![command, block];
+        frame.extend_from_slice(&operand.to_le_bytes());
+        self.initiator
+            .transceive_bytes(&frame, BLOCK_SIZE, -1)
+            .map(|_| ())
+    }
+
+    /// Reads both copies of a backed value and checks whether the last update
+    /// completed.
+    pub fn check_backed_value(&mut self, value: BackedValue) -> Result<ValueState> {
+        let primary = ValueBlock::decode(&self.read_block(value.primary)?).ok();
+        let backup = ValueBlock::decode(&self.read_block(value.backup)?).ok();
+
+        Ok(match (primary, backup) {
+            (Some(primary), Some(backup)) if primary.value == backup.value => {
+                ValueState::Consistent(primary)
+            }
+            (primary, backup) => ValueState::Torn { primary, backup },
+        })
+    }
+
+    /// Finishes an interrupted update by copying the backup over the primary.
+    pub fn recover_backed_value(&mut self, value: BackedValue) -> Result<ValueBlock> {
+        self.restore(value.backup)?;
+        self.transfer(value.primary)?;
+        self.read_value(value.primary)
+    }
+
+    /// Adds `amount` to a backed value, returning the new value.
+    pub fn increment_backed(&mut self, value: BackedValue, amount: u32) -> Result<ValueBlock> {
+        self.update_backed(value, INCREMENT, amount)
+    }
+
+    /// Subtracts `amount` from a backed value, returning the new value.
+    pub fn decrement_backed(&mut self, value: BackedValue, amount: u32) -> Result<ValueBlock> {
+        self.update_backed(value, DECREMENT, amount)
+    }
+
+    fn update_backed(
+        &mut self,
+        value: BackedValue,
+        command: u8,
+        amount: u32,
+    ) -> Result<ValueBlock> {
+        if let ValueState::Torn { .. } = self.check_backed_value(value)? {
+            return Err(Error::new(
+                "Backed value is torn, recover it before updating it",
+            ));
+        }
+
+        // phase one: the new value only lands in the backup
+        self.value_operation(command, value.primary, amount)?;
+        self.transfer(value.backup)?;
+        // phase two: copy it over the primary
+        self.restore(value.backup)?;
+        self.transfer(value.primary)?;
+
+        self.read_value(value.primary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+
+    #[test]
+    fn encoding() {
+        let block = ValueBlock::new(100, 5).encode();
+        assert_eq!(
+            block,
+            [
+                0x64, 0x00, 0x00, 0x00, 0x9b, 0xff, 0xff, 0xff, 0x64, 0x00, 0x00, 0x00, 0x05, 0xfa,
+                0x05, 0xfa,
+            ]
+        );
+
+        let negative = ValueBlock::new(-2, 0).encode();
+        assert_eq!(
+            negative[..8],
+            [0xfe, 0xff, 0xff, 0xff, 0x01, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(negative[12..], [0x00, 0xff, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn round_trips() {
+        for &value in &[0, 1, -1, 100, i32::MIN, i32::MAX] {
+            for &address in &[0x00, 0x05, 0xff] {
+                let block = ValueBlock::new(value, address);
+                assert_eq!(ValueBlock::decode(&block.encode()).unwrap(), block);
+            }
+        }
+    }
+
+    #[test]
+    fn complement_checks() {
+        let block = ValueBlock::new(0x1234_5678, 0x3c).encode();
+        for byte in 0..BLOCK_SIZE {
+            let mut corrupted = block;
+            corrupted[byte] ^= 0x01;
+            assert!(ValueBlock::decode(&corrupted).is_err(), "byte {}", byte);
+        }
+        assert!(ValueBlock::decode(&[0; BLOCK_SIZE]).is_err());
+        assert!(ValueBlock::decode(&[0xff; BLOCK_SIZE]).is_err());
+    }
+
+    #[test]
+    fn backed_value_blocks() {
+        let value = BackedValue::new(4, 5).unwrap();
+        assert_eq!((value.primary(), value.backup()), (4, 5));
+        assert!(BackedValue::new(130, 142).is_ok());
+
+        for &(primary, backup) in &[(4, 8), (6, 7), (7, 6), (5, 5), (142, 143), (126, 128)] {
+            let error = BackedValue::new(primary, backup).unwrap_err();
+            assert_eq!(error.kind(), Some(ErrorKind::InvalidArguments));
+        }
+    }
+}
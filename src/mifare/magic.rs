@@ -0,0 +1,469 @@
+//! "Magic" MIFARE Classic clones, whose block 0 can be rewritten to change
+//! their UID.
+//!
+//! * Gen1a cards open a backdoor after the 7 bit command `0x40` and `0x43`,
+//!   after which every block can be read and written without
+//!   authenticating.
+//! * Gen2 (CUID) cards accept a regular write to block 0.
+//! * Gen3 cards take block 0 through the `90 F0 CC CC 10` APDU, sent as a
+//!   plain ISO14443-3 frame.
+//! * Gen4 (GTU) cards take commands prefixed by `CF` and a 4 byte password.
+//!
+//! Block 0 of a card with a 4 byte UID holds the UID, its BCC (the XOR of
+//! its bytes), the SAK, the ATQA and manufacturer data. A block 0 with the
+//! wrong BCC can make the card impossible to select, so it's never written.
+
+use super::classic::{Block, Key, KeyType, Layout, MifareClassic, BLOCK_SIZE};
+use crate::device::{Initiator, TargetResultEnum};
+use crate::ffi;
+use crate::util::crc_a;
+use crate::{Error, Modulation, Property, Result, SelectCriteria, Target};
+
+use bit_vec::BitVec;
+
+use std::convert::TryInto;
+
+const ACK: u8 = 0x0A;
+const HALT: [u8; 2] = [0x50, 0x00];
+const READ: u8 = 0x30;
+const WRITE: u8 = 0xA0;
+const GEN1A_UNLOCK_1: u8 = 0x40;
+const GEN1A_UNLOCK_2: u8 = 0x43;
+const GEN3_WRITE_BLOCK_0: [u8; 5] = [0x90, 0xF0, 0xCC, 0xCC, 0x10];
+const GEN3_SUCCESS: [u8; 2] = [0x90, 0x00];
+const GEN4_PREFIX: u8 = 0xCF;
+const GEN4_GET_CONFIG: u8 = 0xC6;
+const GEN4_READ: u8 = 0xCE;
+const GEN4_WRITE: u8 = 0xCD;
+/// The password Gen4 cards ship with.
+pub const GEN4_DEFAULT_PASSWORD: [u8; 4] = [0x00; 4];
+/// Responses are at most a Gen4 configuration and its CRC.
+const MAX_FRAME: usize = 34;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MagicKind {
+    Gen1a,
+    Gen2,
+    Gen3,
+    Gen4 { password: [u8; 4] },
+}
+
+/// The XOR of the UID's bytes, which follows it in block 0 and during
+/// anticollision.
+pub fn bcc(uid: &[u8]) -> u8 {
+    uid.iter().fold(0, |bcc, byte| bcc ^ byte)
+}
+
+/// Block 0 of a card with a 4 byte UID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ManufacturerBlock {
+    pub uid: [u8; 4],
+    pub sak: u8,
+    /// In the order libnfc reports it.
+    pub atqa: [u8; 2],
+    pub manufacturer: [u8; 8],
+}
+
+impl ManufacturerBlock {
+    /// Decodes block 0, failing if its BCC is wrong.
+    pub fn decode(block: &Block) -> Result<Self> {
+        let uid: [u8; 4] = block[0..4].try_into().unwrap();
+        if bcc(&uid) != block[4] {
+            return Err(Error::new(&format!(
+                "Block 0 has BCC {:02x} where {:02x} is expected",
+                block[4],
+                bcc(&uid)
+            )));
+        }
+        Ok(ManufacturerBlock {
+            uid,
+            sak: block[5],
+            atqa: [block[7], block[6]],
+            manufacturer: block[8..].try_into().unwrap(),
+        })
+    }
+
+    pub fn encode(&self) -> Block {
+        let mut block = [0; BLOCK_SIZE];
+        block[0..4].copy_from_slice(&self.uid);
+        block[4] = bcc(&self.uid);
+        block[5] = self.sak;
+        block[6] = self.atqa[1];
+        block[7] = self.atqa[0];
+        block[8..].copy_from_slice(&self.manufacturer);
+        block
+    }
+}
+
+/// A magic card selected on an initiator.
+pub struct MagicCard<'a, 'context> {
+    initiator: &'a mut Initiator<'context>,
+    target: Target,
+    kind: MagicKind,
+    key: Key,
+}
+
+impl<'a, 'context> MagicCard<'a, 'context> {
+    /// Works out which kind of magic card a selected target is, if any.
+    ///
+    /// Telling Gen2 and Gen3 cards from genuine ones means reading block 0
+    /// and writing it back unchanged, which takes `key`, key A of sector 0.
+    pub fn detect(
+        initiator: &'a mut Initiator<'context>,
+        target: Target,
+        key: &Key,
+    ) -> Result<Option<Self>> {
+        let kind = match probe(initiator, &target, key)? {
+            Some(kind) => kind,
+            None => return Ok(None),
+        };
+        reselect(initiator, &target)?;
+        Ok(Some(MagicCard {
+            initiator,
+            target,
+            kind,
+            key: *key,
+        }))
+    }
+
+    /// Wraps a target known to be a magic card of the given kind. `key` is
+    /// only used by Gen2 and Gen3 cards, which need authenticating, as key A
+    /// of whichever sector a block is in.
+    pub fn new(
+        initiator: &'a mut Initiator<'context>,
+        target: Target,
+        kind: MagicKind,
+        key: &Key,
+    ) -> Self {
+        MagicCard {
+            initiator,
+            target,
+            kind,
+            key: *key,
+        }
+    }
+
+    pub fn kind(&self) -> MagicKind {
+        self.kind
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    pub fn read_block(&mut self, block: u8) -> Result<Block> {
+        let data = match self.kind {
+            MagicKind::Gen1a => raw_framing(self.initiator, |initiator| {
+                gen1a_unlock(initiator)?;
+                command(initiator, &[READ, block])
+            })?,
+            MagicKind::Gen4 { password } => raw_framing(self.initiator, |initiator| {
+                command(initiator, &gen4_command(password, GEN4_READ, &[block]))
+            })?,
+            MagicKind::Gen2 | MagicKind::Gen3 => return self.classic(block)?.read_block(block),
+        };
+        reselect(self.initiator, &self.target)?;
+        data.as_slice()
+            .try_into()
+            .map_err(|_| Error::from(ffi::NFC_ERFTRANS))
+    }
+
+    /// Writes a block. Block 0 is only written if its BCC is right.
+    pub fn write_block(&mut self, block: u8, data: &Block) -> Result<()> {
+        if block == 0 {
+            ManufacturerBlock::decode(data)?;
+        }
+
+        match self.kind {
+            MagicKind::Gen1a => raw_framing(self.initiator, |initiator| {
+                gen1a_unlock(initiator)?;
+                expect_ack(&command(initiator, &[WRITE, block])?)?;
+                expect_ack(&command(initiator, data)?)
+            })?,
+            MagicKind::Gen4 { password } => raw_framing(self.initiator, |initiator| {
+                let mut arguments = vec![block];
+                arguments.extend_from_slice(data);
+                expect_ack(&command(
+                    initiator,
+                    &gen4_command(password, GEN4_WRITE, &arguments),
+                )?)
+            })?,
+            MagicKind::Gen3 if block == 0 => raw_framing(self.initiator, |initiator| {
+                gen3_write_block_0(initiator, data)
+            })?,
+            MagicKind::Gen2 | MagicKind::Gen3 => {
+                return self.classic(block)?.write_block(block, data)
+            }
+        }
+        reselect(self.initiator, &self.target)
+    }
+
+    pub fn write_manufacturer_block(&mut self, block: &ManufacturerBlock) -> Result<()> {
+        self.write_block(0, &block.encode())
+    }
+
+    /// Changes the UID, keeping the rest of block 0 and fixing its BCC.
+    pub fn set_uid(&mut self, uid: [u8; 4]) -> Result<()> {
+        let mut block = self.read_block(0)?;
+        block[0..4].copy_from_slice(&uid);
+        block[4] = bcc(&uid);
+        self.write_block(0, &block)
+    }
+
+    /// Authenticates for the sector containing `block` the regular way, for
+    /// Gen2 and Gen3 cards.
+    fn classic(&mut self, block: u8) -> Result<MifareClassic<'_, 'context>> {
+        let mut classic =
+            MifareClassic::with_layout(self.initiator, self.target.clone(), Layout::Classic1k)?;
+        classic.authenticate(block, KeyType::A, &self.key)?;
+        Ok(classic)
+    }
+}
+
+fn probe(initiator: &mut Initiator, target: &Target, key: &Key) -> Result<Option<MagicKind>> {
+    if raw_framing(initiator, gen1a_unlock).is_ok() {
+        return Ok(Some(MagicKind::Gen1a));
+    }
+
+    reselect(initiator, target)?;
+    let config = raw_framing(initiator, |initiator| {
+        command(
+            initiator,
+            &gen4_command(GEN4_DEFAULT_PASSWORD, GEN4_GET_CONFIG, &[]),
+        )
+    });
+    if let Ok(config) = config {
+        if config.len() >= 30 {
+            return Ok(Some(MagicKind::Gen4 {
+                password: GEN4_DEFAULT_PASSWORD,
+            }));
+        }
+    }
+
+    // the rest accept block 0 written back as it is
+    reselect(initiator, target)?;
+    let block_0 = {
+        let mut classic =
+            MifareClassic::with_layout(&mut *initiator, target.clone(), Layout::Classic1k)?;
+        match classic
+            .authenticate(0, KeyType::A, key)
+            .and_then(|_| classic.read_block(0))
+        {
+            Ok(block) => block,
+            Err(_) => return Ok(None),
+        }
+    };
+    if ManufacturerBlock::decode(&block_0).is_err() {
+        return Ok(None);
+    }
+
+    reselect(initiator, target)?;
+    if raw_framing(initiator, |initiator| {
+        gen3_write_block_0(initiator, &block_0)
+    })
+    .is_ok()
+    {
+        return Ok(Some(MagicKind::Gen3));
+    }
+
+    reselect(initiator, target)?;
+    let mut classic =
+        MifareClassic::with_layout(&mut *initiator, target.clone(), Layout::Classic1k)?;
+    let written = classic
+        .authenticate(0, KeyType::A, key)
+        .and_then(|_| classic.write_block(0, &block_0));
+    Ok(written.ok().map(|_| MagicKind::Gen2))
+}
+
+fn gen1a_unlock(initiator: &mut Initiator) -> Result<()> {
+    // the card doesn't answer a halt
+    let _ = command(initiator, &HALT);
+    expect_ack(&send_bits(initiator, &[GEN1A_UNLOCK_1], 7)?)?;
+    expect_ack(&send_bits(initiator, &[GEN1A_UNLOCK_2], 8)?)
+}
+
+fn gen3_write_block_0(initiator: &mut Initiator, data: &Block) -> Result<()> {
+    let mut apdu = GEN3_WRITE_BLOCK_0.to_vec();
+    apdu.extend_from_slice(data);
+    if command(initiator, &apdu)? == GEN3_SUCCESS {
+        Ok(())
+    } else {
+        Err(Error::from(ffi::NFC_EDEVNOTSUPP))
+    }
+}
+
+fn gen4_command(password: [u8; 4], code: u8, arguments: &[u8]) -> Vec<u8> {
+    let mut frame = vec![GEN4_PREFIX];
+    frame.extend_from_slice(&password);
+    frame.push(code);
+    frame.extend_from_slice(arguments);
+    frame
+}
+
+/// Runs `f` with the reader leaving the CRC and framing to us, as the
+/// backdoor commands need. Both properties are turned back on whatever
+/// happens, before any error is returned.
+fn raw_framing<T, F>(initiator: &mut Initiator, f: F) -> Result<T>
+where
+    F: FnOnce(&mut Initiator) -> Result<T>,
+{
+    let result = initiator
+        .set_bool_property(Property::NP_HANDLE_CRC, false)
+        .and_then(|_| initiator.set_bool_property(Property::NP_EASY_FRAMING, false))
+        .and_then(|_| f(initiator));
+    let crc = initiator.set_bool_property(Property::NP_HANDLE_CRC, true);
+    let framing = initiator.set_bool_property(Property::NP_EASY_FRAMING, true);
+    let result = result?;
+    crc.and(framing)?;
+    Ok(result)
+}
+
+fn send_bits(initiator: &mut Initiator, data: &[u8], bits: usize) -> Result<Vec<u8>> {
+    let mut frame = BitVec::from_bytes(data);
+    frame.truncate(bits);
+    let (received, _) = initiator.transceive_bits(&frame, &BitVec::new(), MAX_FRAME)?;
+    Ok(received.to_bytes())
+}
+
+/// Sends a frame with its CRC and returns the response.
+fn command(initiator: &mut Initiator, data: &[u8]) -> Result<Vec<u8>> {
+    let frame = with_crc(data);
+    let response = send_bits(initiator, &frame, frame.len() * 8)?;
+    strip_response(&response)
+}
+
+fn with_crc(data: &[u8]) -> Vec<u8> {
+    let mut frame = data.to_vec();
+    frame.extend_from_slice(&crc_a(data));
+    frame
+}
+
+/// The response without its CRC, or the 4 bit acknowledgement as a single
+/// byte.
+fn strip_response(response: &[u8]) -> Result<Vec<u8>> {
+    match response.len() {
+        1 => Ok(vec![response[0] & 0x0f]),
+        0 | 2 => Err(Error::from(ffi::NFC_ERFTRANS)),
+        _ => {
+            let (data, crc) = response.split_at(response.len() - 2);
+            if crc == crc_a(data) {
+                Ok(data.to_vec())
+            } else {
+                Err(Error::from(ffi::NFC_ERFTRANS))
+            }
+        }
+    }
+}
+
+fn expect_ack(response: &[u8]) -> Result<()> {
+    match response {
+        [ACK] => Ok(()),
+        _ => Err(Error::from(ffi::NFC_ERFTRANS)),
+    }
+}
+
+fn reselect(initiator: &mut Initiator, target: &Target) -> Result<()> {
+    let criteria = SelectCriteria::for_target(target);
+    match initiator.select_passive_target(Modulation::iso14443a(), &criteria)? {
+        TargetResultEnum::Found(_) => Ok(()),
+        TargetResultEnum::Empty => Err(Error::from(ffi::NFC_ETGRELEASED)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::decode_hex;
+
+    // block 0 of a Gen1a card, "bcdefghi" being the usual manufacturer data
+    // of those
+    const BLOCK_0: &str = "B4F4E9C26B0804006263646566676869";
+
+    fn block(hex: &str) -> Block {
+        decode_hex(hex).unwrap().as_slice().try_into().unwrap()
+    }
+
+    #[test]
+    fn check_byte() {
+        assert_eq!(bcc(&[0xb4, 0xf4, 0xe9, 0xc2]), 0x6b);
+        assert_eq!(bcc(&[0x01, 0x02, 0x03, 0x04]), 0x04);
+        assert_eq!(bcc(&[0x00; 4]), 0x00);
+        // the cascade tag counts for the first part of a 7 byte UID
+        assert_eq!(bcc(&[0x88, 0x04, 0x44, 0x8b]), 0x43);
+    }
+
+    #[test]
+    fn manufacturer_block() {
+        let decoded = ManufacturerBlock::decode(&block(BLOCK_0)).unwrap();
+        assert_eq!(
+            decoded,
+            ManufacturerBlock {
+                uid: [0xb4, 0xf4, 0xe9, 0xc2],
+                sak: 0x08,
+                // stored least significant byte first
+                atqa: [0x00, 0x04],
+                manufacturer: *b"bcdefghi",
+            }
+        );
+        assert_eq!(decoded.encode(), block(BLOCK_0));
+
+        let changed = ManufacturerBlock {
+            uid: [0x01, 0x02, 0x03, 0x04],
+            ..decoded
+        };
+        assert_eq!(changed.encode(), block("01020304040804006263646566676869"));
+    }
+
+    #[test]
+    fn wrong_check_byte() {
+        let mut data = block(BLOCK_0);
+        data[4] ^= 0x01;
+        assert!(ManufacturerBlock::decode(&data).is_err());
+        let mut data = block(BLOCK_0);
+        data[0] ^= 0x01;
+        assert!(ManufacturerBlock::decode(&data).is_err());
+    }
+
+    #[test]
+    fn gen4_commands() {
+        assert_eq!(
+            gen4_command(GEN4_DEFAULT_PASSWORD, GEN4_GET_CONFIG, &[]),
+            [0xcf, 0x00, 0x00, 0x00, 0x00, 0xc6]
+        );
+        assert_eq!(
+            gen4_command([0x12, 0x34, 0x56, 0x78], GEN4_READ, &[0x04]),
+            [0xcf, 0x12, 0x34, 0x56, 0x78, 0xce, 0x04]
+        );
+        let mut write = vec![0xcf, 0x00, 0x00, 0x00, 0x00, 0xcd, 0x00];
+        write.extend_from_slice(&block(BLOCK_0));
+        let mut arguments = vec![0x00];
+        arguments.extend_from_slice(&block(BLOCK_0));
+        assert_eq!(
+            gen4_command(GEN4_DEFAULT_PASSWORD, GEN4_WRITE, &arguments),
+            write
+        );
+    }
+
+    #[test]
+    fn frames() {
+        assert_eq!(with_crc(&HALT), [0x50, 0x00, 0x57, 0xcd]);
+        assert_eq!(with_crc(&[READ, 0x00]), [0x30, 0x00, 0x02, 0xa8]);
+
+        // the acknowledgement is 4 bits
+        assert_eq!(strip_response(&[0x0a]).unwrap(), [ACK]);
+        assert_eq!(strip_response(&[0xfa]).unwrap(), [ACK]);
+        assert!(expect_ack(&strip_response(&[0x04]).unwrap()).is_err());
+
+        let answer = with_crc(&block(BLOCK_0));
+        assert_eq!(strip_response(&answer).unwrap(), block(BLOCK_0));
+        assert_eq!(
+            strip_response(&with_crc(&GEN3_SUCCESS)).unwrap(),
+            GEN3_SUCCESS
+        );
+        let mut corrupted = answer;
+        corrupted[3] ^= 0x01;
+        assert!(strip_response(&corrupted).is_err());
+        assert!(strip_response(&[]).is_err());
+        assert!(strip_response(&[0x90, 0x00]).is_err());
+    }
+}
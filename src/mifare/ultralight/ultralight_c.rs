@@ -0,0 +1,234 @@
+//! The Ultralight C's 3DES mutual authentication, which libnfc doesn't do.
+//!
+//! The card sends RndB encrypted with the shared key, the reader answers
+//! with its own RndA followed by RndB rotated by a byte, and the card proves
+//! it knows the key by sending back RndA rotated. Everything is 2 key 3DES
+//! in CBC mode, each message chained on the last block of the one before.
+
+use super::{MemoryMap, Ultralight, PAGE_SIZE};
+use crate::device::Initiator;
+use crate::ffi;
+use crate::{Error, Result, Target};
+
+use des::cipher::generic_array::GenericArray;
+use des::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use des::TdesEde2;
+
+use std::convert::TryInto;
+
+pub const TDES_KEY_SIZE: usize = 16;
+
+pub type TdesKey = [u8; TDES_KEY_SIZE];
+
+/// The key cards ship with, "BREAKMEIFYOUCAN!" with each half reversed.
+pub const DEFAULT_TDES_KEY: TdesKey = *b"IEMKAERB!NACUOYF";
+
+const AUTHENTICATE: u8 = 0x1A;
+const ADDITIONAL_FRAME: u8 = 0xAF;
+const DONE: u8 = 0x00;
+const AUTH0_PAGE: u8 = 0x2A;
+const AUTH1_PAGE: u8 = 0x2B;
+const KEY_PAGE: u8 = 0x2C;
+/// AUTH0 past the last page turns authentication off.
+pub const AUTH0_DISABLED: u8 = 0x30;
+const NONCE_SIZE: usize = 8;
+
+/// An Ultralight C target selected on an initiator.
+pub struct UltralightC<'a, 'context> {
+    ultralight: Ultralight<'a, 'context>,
+}
+
+impl<'a, 'context> UltralightC<'a, 'context> {
+    pub fn new(initiator: &'a mut Initiator<'context>, target: Target) -> Result<Self> {
+        Ok(UltralightC {
+            ultralight: Ultralight::with_map(initiator, target, MemoryMap::ULTRALIGHT_C)?,
+        })
+    }
+
+    /// The plain Ultralight commands, to read and write pages once
+    /// authenticated.
+    pub fn ultralight(&mut self) -> &mut Ultralight<'a, 'context> {
+        &mut self.ultralight
+    }
+
+    /// Authenticates with the card, and the card with us. A wrong key, which
+    /// the card NAKs, fails with `ErrorKind::MifareClassicAuth`, after which
+    /// the card has to be selected again. Other errors are returned as they
+    /// are.
+    pub fn authenticate(&mut self, key: &TdesKey) -> Result<()> {
+        let cipher = TdesEde2::new(GenericArray::from_slice(key));
+        let initiator = self.ultralight.initiator();
+
+        let answer = initiator.transceive_bytes(&[AUTHENTICATE, 0x00], 1 + NONCE_SIZE, -1)?;
+        let encrypted_b = match answer.split_first() {
+            Some((&ADDITIONAL_FRAME, rest)) if rest.len() == NONCE_SIZE => rest.to_vec(),
+            _ => return Err(Error::from(ffi::NFC_EMFCAUTHFAIL)),
+        };
+
+        let mut rnd_a = [0; NONCE_SIZE];
+        getrandom::getrandom(&mut rnd_a)
+            .map_err(|e| Error::new(&format!("Couldn't generate RndA: {}", e)))?;
+        let encrypted = answer_challenge(&cipher, &encrypted_b, &rnd_a);
+
+        let mut frame = vec![ADDITIONAL_FRAME];
+        frame.extend_from_slice(&encrypted);
+        let answer = initiator.transceive_bytes(&frame, 1 + NONCE_SIZE, -1)?;
+        let encrypted_a = match answer.split_first() {
+            Some((&DONE, rest)) if rest.len() == NONCE_SIZE => rest,
+            _ => return Err(Error::from(ffi::NFC_EMFCAUTHFAIL)),
+        };
+
+        if knows_key(&cipher, &encrypted, encrypted_a, &rnd_a) {
+            Ok(())
+        } else {
+            Err(Error::new("The card doesn't know the key"))
+        }
+    }
+
+    /// Writes a new key. The card has to be authenticated first if the key
+    /// pages are protected, which they are unless AUTH0 was disabled.
+    pub fn change_key(&mut self, key: &TdesKey) -> Result<()> {
+        let stored = key_pages(key);
+        for (page, data) in (KEY_PAGE..).zip(stored.chunks_exact(PAGE_SIZE)) {
+            self.ultralight.write(page, data.try_into().unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// Sets the first page that needs authentication, from 3 up to
+    /// [`AUTH0_DISABLED`](constant.AUTH0_DISABLED.html).
+    pub fn set_auth0(&mut self, page: u8) -> Result<()> {
+        if !(3..=AUTH0_DISABLED).contains(&page) {
+            return Err(Error::from(ffi::NFC_EINVARG));
+        }
+        self.ultralight.write(AUTH0_PAGE, &[page, 0, 0, 0])
+    }
+
+    /// Sets whether the pages from AUTH0 on need authentication to be read
+    /// (AUTH1 cleared), or only to be written (AUTH1 set).
+    pub fn set_auth1(&mut self, read_protected: bool) -> Result<()> {
+        let auth1 = if read_protected { 0x00 } else { 0x01 };
+        self.ultralight.write(AUTH1_PAGE, &[auth1, 0, 0, 0])
+    }
+
+    /// Reads AUTH0 and AUTH1, returning the first protected page and
+    /// whether reading is protected too.
+    pub fn read_auth_config(&mut self) -> Result<(u8, bool)> {
+        let pages = self.ultralight.read(AUTH0_PAGE)?;
+        Ok((pages[0], pages[PAGE_SIZE] & 0x01 == 0))
+    }
+}
+
+/// The reader's answer to the card's encrypted RndB: RndA followed by RndB
+/// rotated, chained on the card's message.
+fn answer_challenge(cipher: &TdesEde2, encrypted_b: &[u8], rnd_a: &[u8; NONCE_SIZE]) -> Vec<u8> {
+    let mut rnd_b = decrypt(cipher, &[0; NONCE_SIZE], encrypted_b);
+    rnd_b.rotate_left(1);
+    let mut plain = rnd_a.to_vec();
+    plain.extend_from_slice(&rnd_b);
+    encrypt(cipher, encrypted_b, &plain)
+}
+
+/// Whether the card's final message, chained on the reader's answer, is
+/// RndA rotated.
+fn knows_key(
+    cipher: &TdesEde2,
+    answer: &[u8],
+    encrypted_a: &[u8],
+    rnd_a: &[u8; NONCE_SIZE],
+) -> bool {
+    let mut rnd_a_rotated = decrypt(cipher, &answer[NONCE_SIZE..], encrypted_a);
+    rnd_a_rotated.rotate_right(1);
+    rnd_a_rotated == rnd_a
+}
+
+/// The key as written to its pages, each half stored backwards.
+fn key_pages(key: &TdesKey) -> TdesKey {
+    let mut stored = *key;
+    stored[..8].reverse();
+    stored[8..].reverse();
+    stored
+}
+
+fn encrypt(cipher: &TdesEde2, iv: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut previous = iv.to_vec();
+    let mut encrypted = Vec::with_capacity(data.len());
+    for chunk in data.chunks_exact(NONCE_SIZE) {
+        let mut block = GenericArray::clone_from_slice(chunk);
+        for (byte, chained) in block.iter_mut().zip(&previous) {
+            *byte ^= chained;
+        }
+        cipher.encrypt_block(&mut block);
+        previous = block.to_vec();
+        encrypted.extend_from_slice(&block);
+    }
+    encrypted
+}
+
+fn decrypt(cipher: &TdesEde2, iv: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut previous = iv;
+    let mut decrypted = Vec::with_capacity(data.len());
+    for chunk in data.chunks_exact(NONCE_SIZE) {
+        let mut block = GenericArray::clone_from_slice(chunk);
+        cipher.decrypt_block(&mut block);
+        for (byte, chained) in block.iter_mut().zip(previous) {
+            *byte ^= chained;
+        }
+        previous = chunk;
+        decrypted.extend_from_slice(&block);
+    }
+    decrypted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::decode_hex;
+
+    // an authentication with the default key, RndB 4D9D6F4A7F5B2C11 and RndA
+    // A1B2C3D4E5F60718
+    const RND_A: [u8; NONCE_SIZE] = [0xa1, 0xb2, 0xc3, 0xd4, 0xe5, 0xf6, 0x07, 0x18];
+    const RND_B: &str = "4D9D6F4A7F5B2C11";
+    const EK_RND_B: &str = "3E89CE3AD6B68E69";
+    const READER: &str = "9B63B8C4C1F38C22DF5224EE05CF312E";
+    const CARD: &str = "105BD6BEBBAF48C1";
+
+    fn tdes(key: &TdesKey) -> TdesEde2 {
+        TdesEde2::new(GenericArray::from_slice(key))
+    }
+
+    #[test]
+    fn cbc() {
+        let cipher = tdes(&DEFAULT_TDES_KEY);
+        let rnd_b = decode_hex(RND_B).unwrap();
+        let ek_rnd_b = decode_hex(EK_RND_B).unwrap();
+        assert_eq!(encrypt(&cipher, &[0; NONCE_SIZE], &rnd_b), ek_rnd_b);
+        assert_eq!(decrypt(&cipher, &[0; NONCE_SIZE], &ek_rnd_b), rnd_b);
+
+        let reader = decode_hex(READER).unwrap();
+        let mut plain = RND_A.to_vec();
+        plain.extend_from_slice(&decode_hex("9D6F4A7F5B2C114D").unwrap());
+        assert_eq!(decrypt(&cipher, &ek_rnd_b, &reader), plain);
+    }
+
+    #[test]
+    fn authentication() {
+        let cipher = tdes(&DEFAULT_TDES_KEY);
+        let reader = answer_challenge(&cipher, &decode_hex(EK_RND_B).unwrap(), &RND_A);
+        assert_eq!(reader, decode_hex(READER).unwrap());
+        let card = decode_hex(CARD).unwrap();
+        assert!(knows_key(&cipher, &reader, &card, &RND_A));
+
+        let mut wrong_a = RND_A;
+        wrong_a[0] ^= 1;
+        assert!(!knows_key(&cipher, &reader, &card, &wrong_a));
+        let other = tdes(&[0; TDES_KEY_SIZE]);
+        assert!(!knows_key(&other, &reader, &card, &RND_A));
+    }
+
+    #[test]
+    fn factory_key_pages() {
+        assert_eq!(&key_pages(&DEFAULT_TDES_KEY), b"BREAKMEIFYOUCAN!");
+        assert_eq!(key_pages(&key_pages(&DEFAULT_TDES_KEY)), DEFAULT_TDES_KEY);
+    }
+}
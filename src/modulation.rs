@@ -0,0 +1,255 @@
+use crate::ffi;
+use crate::{BaudRate, Error, ModulationType, Result, Target};
+
+use std::convert::TryFrom;
+
+/// Baud rates for modulations that can run at any of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Baud {
+    B106,
+    B212,
+    B424,
+    B847,
+}
+
+/// Baud rates FeliCa is defined for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FelicaBaud {
+    B212,
+    B424,
+}
+
+/// Baud rates NFCIP-1 DEP is defined for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DepBaud {
+    B106,
+    B212,
+    B424,
+}
+
+impl From<Baud> for BaudRate {
+    fn from(baud: Baud) -> BaudRate {
+        match baud {
+            Baud::B106 => BaudRate::NBR_106,
+            Baud::B212 => BaudRate::NBR_212,
+            Baud::B424 => BaudRate::NBR_424,
+            Baud::B847 => BaudRate::NBR_847,
+        }
+    }
+}
+
+impl From<FelicaBaud> for BaudRate {
+    fn from(baud: FelicaBaud) -> BaudRate {
+        match baud {
+            FelicaBaud::B212 => BaudRate::NBR_212,
+            FelicaBaud::B424 => BaudRate::NBR_424,
+        }
+    }
+}
+
+impl From<DepBaud> for BaudRate {
+    fn from(baud: DepBaud) -> BaudRate {
+        match baud {
+            DepBaud::B106 => BaudRate::NBR_106,
+            DepBaud::B212 => BaudRate::NBR_212,
+            DepBaud::B424 => BaudRate::NBR_424,
+        }
+    }
+}
+
+/// A modulation type together with a baud rate it's actually defined for.
+///
+/// The constructors only accept legal pairings, use `TryFrom` to check a
+/// pairing from elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Modulation {
+    nmt: ModulationType,
+    nbr: BaudRate,
+}
+
+impl Modulation {
+    /// ISO14443A at 106 kbps, the rate anticollision always happens at.
+    pub fn iso14443a() -> Self {
+        Modulation::new(ModulationType::NMT_ISO14443A, BaudRate::NBR_106)
+    }
+
+    pub fn iso14443b(baud: Baud) -> Self {
+        Modulation::new(ModulationType::NMT_ISO14443B, baud.into())
+    }
+
+    pub fn felica(baud: FelicaBaud) -> Self {
+        Modulation::new(ModulationType::NMT_FELICA, baud.into())
+    }
+
+    pub fn jewel() -> Self {
+        Modulation::new(ModulationType::NMT_JEWEL, BaudRate::NBR_106)
+    }
+
+    /// ISO14443B' (Innovatron)
+    pub fn iso14443bi() -> Self {
+        Modulation::new(ModulationType::NMT_ISO14443BI, BaudRate::NBR_106)
+    }
+
+    /// ST SRx
+    pub fn iso14443b2sr() -> Self {
+        Modulation::new(ModulationType::NMT_ISO14443B2SR, BaudRate::NBR_106)
+    }
+
+    /// ASK CTx
+    pub fn iso14443b2ct() -> Self {
+        Modulation::new(ModulationType::NMT_ISO14443B2CT, BaudRate::NBR_106)
+    }
+
+    /// HID iClass (Picopass)
+    pub fn iso14443biclass() -> Self {
+        Modulation::new(ModulationType::NMT_ISO14443BICLASS, BaudRate::NBR_106)
+    }
+
+    /// Thinfilm NFC Barcode
+    pub fn barcode() -> Self {
+        Modulation::new(ModulationType::NMT_BARCODE, BaudRate::NBR_106)
+    }
+
+    pub fn dep(baud: DepBaud) -> Self {
+        Modulation::new(ModulationType::NMT_DEP, baud.into())
+    }
+
+    fn new(nmt: ModulationType, nbr: BaudRate) -> Self {
+        Modulation { nmt, nbr }
+    }
+
+    /// The modulation a target was found with.
+    pub fn of_target(target: &Target) -> Result<Self> {
+        Modulation::try_from(ffi::nfc_modulation {
+            nmt: target.info.modulation_type(),
+            nbr: target.baud_rate,
+        })
+    }
+
+    pub fn modulation_type(&self) -> ModulationType {
+        self.nmt
+    }
+
+    pub fn baud_rate(&self) -> BaudRate {
+        self.nbr
+    }
+
+    /// Whether the modulation type is defined at the baud rate.
+    pub fn is_legal(nmt: ModulationType, nbr: BaudRate) -> bool {
+        use ffi::nfc_modulation_type::*;
+
+        match nmt {
+            NMT_ISO14443A | NMT_ISO14443B => nbr != BaudRate::NBR_UNDEFINED,
+            NMT_FELICA => nbr == BaudRate::NBR_212 || nbr == BaudRate::NBR_424,
+            NMT_DEP => nbr != BaudRate::NBR_UNDEFINED && nbr != BaudRate::NBR_847,
+            _ => nbr == BaudRate::NBR_106,
+        }
+    }
+}
+
+impl TryFrom<ffi::nfc_modulation> for Modulation {
+    type Error = Error;
+
+    fn try_from(raw: ffi::nfc_modulation) -> Result<Self> {
+        if Modulation::is_legal(raw.nmt, raw.nbr) {
+            Ok(Modulation::new(raw.nmt, raw.nbr))
+        } else {
+            Err(Error::new(&format!(
+                "{} is not defined at {}",
+                raw.nmt, raw.nbr
+            )))
+        }
+    }
+}
+
+impl From<Modulation> for ffi::nfc_modulation {
+    fn from(modulation: Modulation) -> ffi::nfc_modulation {
+        ffi::nfc_modulation {
+            nmt: modulation.nmt,
+            nbr: modulation.nbr,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ffi::nfc_modulation_type::*;
+
+    const ALL_RATES: [BaudRate; 5] = [
+        BaudRate::NBR_UNDEFINED,
+        BaudRate::NBR_106,
+        BaudRate::NBR_212,
+        BaudRate::NBR_424,
+        BaudRate::NBR_847,
+    ];
+
+    #[test]
+    fn legal_pairings() {
+        use BaudRate::*;
+
+        let cases = [
+            (NMT_ISO14443A, &[NBR_106, NBR_212, NBR_424, NBR_847][..]),
+            (NMT_ISO14443B, &[NBR_106, NBR_212, NBR_424, NBR_847][..]),
+            (NMT_FELICA, &[NBR_212, NBR_424][..]),
+            (NMT_DEP, &[NBR_106, NBR_212, NBR_424][..]),
+            (NMT_JEWEL, &[NBR_106][..]),
+            (NMT_ISO14443BI, &[NBR_106][..]),
+            (NMT_ISO14443B2SR, &[NBR_106][..]),
+            (NMT_ISO14443B2CT, &[NBR_106][..]),
+            (NMT_ISO14443BICLASS, &[NBR_106][..]),
+            (NMT_BARCODE, &[NBR_106][..]),
+        ];
+        for &(nmt, legal) in cases.iter() {
+            for &nbr in ALL_RATES.iter() {
+                let expected = legal.contains(&nbr);
+                assert_eq!(
+                    Modulation::is_legal(nmt, nbr),
+                    expected,
+                    "{} at {}",
+                    nmt,
+                    nbr
+                );
+
+                let modulation = Modulation::try_from(ffi::nfc_modulation { nmt, nbr });
+                assert_eq!(modulation.is_ok(), expected, "{} at {}", nmt, nbr);
+                if let Ok(modulation) = modulation {
+                    assert_eq!(modulation.modulation_type(), nmt);
+                    assert_eq!(modulation.baud_rate(), nbr);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn constructors_are_legal() {
+        let mut modulations = vec![
+            Modulation::iso14443a(),
+            Modulation::jewel(),
+            Modulation::iso14443bi(),
+            Modulation::iso14443b2sr(),
+            Modulation::iso14443b2ct(),
+            Modulation::iso14443biclass(),
+            Modulation::barcode(),
+        ];
+        modulations.extend(
+            [Baud::B106, Baud::B212, Baud::B424, Baud::B847]
+                .iter()
+                .map(|&baud| Modulation::iso14443b(baud)),
+        );
+        modulations.extend(
+            [FelicaBaud::B212, FelicaBaud::B424]
+                .iter()
+                .map(|&baud| Modulation::felica(baud)),
+        );
+        modulations.extend(
+            [DepBaud::B106, DepBaud::B212, DepBaud::B424]
+                .iter()
+                .map(|&baud| Modulation::dep(baud)),
+        );
+        for modulation in modulations {
+            let raw = ffi::nfc_modulation::from(modulation);
+            assert_eq!(Modulation::try_from(raw).unwrap(), modulation);
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! NTAG 424 DNA tags, over ISO-DEP.
+//!
+//! Commands are native ones wrapped in ISO 7816 APDUs of class 0x90, the
+//! status coming back as `91 xx`. AuthenticateEV2First derives session keys
+//! from one of the five AES application keys, after which commands are sent
+//! either with a CMAC or encrypted as well, depending on what the command or
+//! the file needs. Each command increments a counter both sides fold into the
+//! MACs and IVs, so a replayed or reordered command is refused.
+
+use crate::device::Initiator;
+use crate::ffi;
+use crate::{Error, Result};
+
+use subtle::ConstantTimeEq;
+
+mod crypto;
+mod sdm;
+
+pub use sdm::{
+    decrypt_file_data, sdm_mac, verify_sun, AccessRights, CommMode, FileSettings, PiccData,
+    SdmSettings, ACCESS_FREE, ACCESS_NEVER, UID_SIZE,
+};
+
+use crypto::{
+    cmac, crc32, decrypt, encrypt, encrypt_block, pad, session_keys, to_block, truncate, unpad,
+};
+
+pub const AES_KEY_SIZE: usize = 16;
+pub const AES_BLOCK_SIZE: usize = 16;
+pub const KEY_COUNT: u8 = 5;
+/// The file holding the NDEF message, the one SDM is usually enabled on.
+pub const NDEF_FILE: u8 = 0x02;
+
+pub type AesKey = [u8; AES_KEY_SIZE];
+
+const SELECT_APPLICATION: [u8; 13] = [
+    0x00, 0xa4, 0x04, 0x0c, 0x07, 0xd2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01, 0x00,
+];
+const NATIVE_CLASS: u8 = 0x90;
+const NATIVE_STATUS: u8 = 0x91;
+const ISO_OK: [u8; 2] = [0x90, 0x00];
+const OK: u8 = 0x00;
+const ADDITIONAL_FRAME: u8 = 0xAF;
+const AUTHENTICATE_EV2_FIRST: u8 = 0x71;
+const GET_FILE_SETTINGS: u8 = 0xF5;
+const CHANGE_FILE_SETTINGS: u8 = 0x5F;
+const CHANGE_KEY: u8 = 0xC4;
+const COMMAND_IV: [u8; 2] = [0xa5, 0x5a];
+const RESPONSE_IV: [u8; 2] = [0x5a, 0xa5];
+const MAC_SIZE: usize = 8;
+const TI_SIZE: usize = 4;
+const MAX_RESPONSE: usize = 256 + 2;
+
+/// The keys and state of an authenticated session.
+struct SecureChannel {
+    enc_key: AesKey,
+    mac_key: AesKey,
+    ti: [u8; TI_SIZE],
+    counter: u16,
+    key_no: u8,
+}
+
+impl SecureChannel {
+    fn iv(&self, label: [u8; 2], counter: u16) -> [u8; AES_BLOCK_SIZE] {
+        let mut iv = [0; AES_BLOCK_SIZE];
+        iv[..2].copy_from_slice(&label);
+        iv[2..6].copy_from_slice(&self.ti);
+        iv[6..8].copy_from_slice(&counter.to_le_bytes());
+        encrypt_block(&self.enc_key, &iv)
+    }
+
+    fn mac(&self, code: u8, counter: u16, parts: &[&[u8]]) -> [u8; MAC_SIZE] {
+        let mut input = vec![code];
+        input.extend_from_slice(&counter.to_le_bytes());
+        input.extend_from_slice(&self.ti);
+        for part in parts {
+            input.extend_from_slice(part);
+        }
+        truncate(&cmac(&self.mac_key, &input))
+    }
+}
+
+/// An NTAG 424 DNA target selected on an initiator.
+pub struct Ntag424<'a, 'context> {
+    initiator: &'a mut Initiator<'context>,
+    channel: Option<SecureChannel>,
+}
+
+impl<'a, 'context> Ntag424<'a, 'context> {
+    /// Wraps a selected ISO 14443-4 target, selecting the NDEF application.
+    pub fn new(initiator: &'a mut Initiator<'context>) -> Result<Self> {
+        let mut ntag = Ntag424 {
+            initiator,
+            channel: None,
+        };
+        ntag.select_application()?;
+        Ok(ntag)
+    }
+
+    /// Selects the NDEF application, which ends any session.
+    pub fn select_application(&mut self) -> Result<()> {
+        self.channel = None;
+        let answer = self
+            .initiator
+            .transceive_bytes(&SELECT_APPLICATION, MAX_RESPONSE, -1)?;
+        if answer.ends_with(&ISO_OK) {
+            Ok(())
+        } else {
+            Err(Error::new("The tag has no NTAG 424 DNA application"))
+        }
+    }
+
+    /// Whether a session is established, and with which key.
+    pub fn authenticated_key(&self) -> Option<u8> {
+        self.channel.as_ref().map(|channel| channel.key_no)
+    }
+
+    /// Authenticates with the key numbered `key_no`, and the tag with us,
+    /// starting a session. A wrong key fails with
+    /// `ErrorKind::MifareClassicAuth`.
+    pub fn authenticate(&mut self, key_no: u8, key: &AesKey) -> Result<()> {
+        if key_no >= KEY_COUNT {
+            return Err(Error::from(ffi::NFC_EINVARG));
+        }
+        self.channel = None;
+
+        let (status, encrypted_b) = self.transceive(AUTHENTICATE_EV2_FIRST, &[key_no, 0x00])?;
+        if status != ADDITIONAL_FRAME || encrypted_b.len() != AES_BLOCK_SIZE {
+            return Err(Error::from(ffi::NFC_EMFCAUTHFAIL));
+        }
+        let rnd_b = to_block(&decrypt(key, &[0; AES_BLOCK_SIZE], &encrypted_b));
+
+        let mut rnd_a = [0; AES_BLOCK_SIZE];
+        getrandom::getrandom(&mut rnd_a)
+            .map_err(|e| Error::new(&format!("Couldn't generate RndA: {}", e)))?;
+        let mut plain = rnd_a.to_vec();
+        plain.extend_from_slice(&rnd_b[1..]);
+        plain.push(rnd_b[0]);
+        let (status, answer) = self.transceive(
+            ADDITIONAL_FRAME,
+            &encrypt(key, &[0; AES_BLOCK_SIZE], &plain),
+        )?;
+        if status != OK || answer.len() != 2 * AES_BLOCK_SIZE {
+            return Err(Error::from(ffi::NFC_EMFCAUTHFAIL));
+        }
+
+        // TI, RndA rotated, then the capabilities we don't need
+        let answer = decrypt(key, &[0; AES_BLOCK_SIZE], &answer);
+        let mut rnd_a_rotated = to_block(&answer[TI_SIZE..TI_SIZE + AES_BLOCK_SIZE]);
+        rnd_a_rotated.rotate_right(1);
+        if rnd_a_rotated != rnd_a {
+            return Err(Error::new("The tag doesn't know the key"));
+        }
+
+        let (enc_key, mac_key) = session_keys(key, &rnd_a, &rnd_b);
+        let mut ti = [0; TI_SIZE];
+        ti.copy_from_slice(&answer[..TI_SIZE]);
+        self.channel = Some(SecureChannel {
+            enc_key,
+            mac_key,
+            ti,
+            counter: 0,
+            key_no,
+        });
+        Ok(())
+    }
+
+    /// Reads a file's settings, with a MAC if authenticated.
+    pub fn get_file_settings(&mut self, file_no: u8) -> Result<FileSettings> {
+        let mode = if self.channel.is_some() {
+            CommMode::Mac
+        } else {
+            CommMode::Plain
+        };
+        let data = self.command(GET_FILE_SETTINGS, &[file_no], &[], mode)?;
+        FileSettings::decode(&data)
+    }
+
+    /// Changes a file's access rights, communication mode and SDM settings.
+    /// Needs a session with the file's change key.
+    pub fn change_file_settings(&mut self, file_no: u8, settings: &FileSettings) -> Result<()> {
+        let data = settings.encode()?;
+        self.command(CHANGE_FILE_SETTINGS, &[file_no], &data, CommMode::Full)?;
+        Ok(())
+    }
+
+    /// Changes the key numbered `key_no` and its version. Needs a session
+    /// with key 0, and for any other key its current value as `old`.
+    /// Changing key 0 ends the session, `old` being ignored.
+    pub fn change_key(
+        &mut self,
+        key_no: u8,
+        new: &AesKey,
+        version: u8,
+        old: &AesKey,
+    ) -> Result<()> {
+        if key_no >= KEY_COUNT {
+            return Err(Error::from(ffi::NFC_EINVARG));
+        }
+        let own_key = self.authenticated_key() == Some(key_no);
+
+        let mut data = Vec::with_capacity(AES_KEY_SIZE + 5);
+        if own_key {
+            data.extend_from_slice(new);
+            data.push(version);
+        } else {
+            data.extend(new.iter().zip(old).map(|(new, old)| new ^ old));
+            data.push(version);
+            data.extend_from_slice(&crc32(new));
+        }
+
+        if own_key {
+            // the tag answers without a MAC, the session keys being gone
+            let frame = self.protect(CHANGE_KEY, &[key_no], &data, CommMode::Full)?;
+            self.channel = None;
+            let (status, _) = self.transceive(CHANGE_KEY, &frame)?;
+            return self.expect_ok(status);
+        }
+        self.command(CHANGE_KEY, &[key_no], &data, CommMode::Full)?;
+        Ok(())
+    }
+
+    /// Sends a command in `mode`, checking and decrypting the answer as
+    /// needed.
+    fn command(
+        &mut self,
+        command: u8,
+        header: &[u8],
+        data: &[u8],
+        mode: CommMode,
+    ) -> Result<Vec<u8>> {
+        let frame = self.protect(command, header, data, mode)?;
+        let (status, answer) = match self.transceive(command, &frame) {
+            Ok((status, answer)) => (status, answer),
+            Err(e) => {
+                // errors end the session on the tag's side too
+                self.channel = None;
+                return Err(e);
+            }
+        };
+        self.expect_ok(status)?;
+
+        let channel = match self.channel.as_mut() {
+            Some(channel) => channel,
+            None => return Ok(answer),
+        };
+        channel.counter = channel.counter.wrapping_add(1);
+        if mode == CommMode::Plain {
+            return Ok(answer);
+        }
+
+        if answer.len() < MAC_SIZE {
+            self.channel = None;
+            return Err(Error::new("The answer is missing its MAC"));
+        }
+        let (answer, mac) = answer.split_at(answer.len() - MAC_SIZE);
+        if !bool::from(channel.mac(OK, channel.counter, &[answer])[..].ct_eq(mac)) {
+            self.channel = None;
+            return Err(Error::new("The answer's MAC doesn't match"));
+        }
+
+        if mode == CommMode::Full && !answer.is_empty() {
+            let iv = channel.iv(RESPONSE_IV, channel.counter);
+            unpad(&decrypt(&channel.enc_key, &iv, answer))
+        } else {
+            Ok(answer.to_vec())
+        }
+    }
+
+    /// Builds a command's data in `mode`, encrypting and adding the MAC.
+    fn protect(&self, command: u8, header: &[u8], data: &[u8], mode: CommMode) -> Result<Vec<u8>> {
+        let mut frame = header.to_vec();
+        if mode == CommMode::Plain {
+            frame.extend_from_slice(data);
+            return Ok(frame);
+        }
+
+        let channel = self
+            .channel
+            .as_ref()
+            .ok_or_else(|| Error::new("The command needs authenticating first"))?;
+        if mode == CommMode::Full && !data.is_empty() {
+            let iv = channel.iv(COMMAND_IV, channel.counter);
+            frame.extend_from_slice(&encrypt(&channel.enc_key, &iv, &pad(data)));
+        } else {
+            frame.extend_from_slice(data);
+        }
+        let mac = channel.mac(command, channel.counter, &[&frame]);
+        frame.extend_from_slice(&mac);
+        Ok(frame)
+    }
+
+    /// Sends a native command, returning the status and the data.
+    fn transceive(&mut self, command: u8, data: &[u8]) -> Result<(u8, Vec<u8>)> {
+        let mut apdu = vec![NATIVE_CLASS, command, 0x00, 0x00];
+        if !data.is_empty() {
+            apdu.push(data.len() as u8);
+            apdu.extend_from_slice(data);
+        }
+        apdu.push(0x00);
+
+        let mut answer = self.initiator.transceive_bytes(&apdu, MAX_RESPONSE, -1)?;
+        if answer.len() < 2 || answer[answer.len() - 2] != NATIVE_STATUS {
+            return Err(Error::new("The tag didn't answer with a native status"));
+        }
+        let status = answer[answer.len() - 1];
+        answer.truncate(answer.len() - 2);
+        match status {
+            OK | ADDITIONAL_FRAME => Ok((status, answer)),
+            _ => Err(status_error(status)),
+        }
+    }
+
+    fn expect_ok(&mut self, status: u8) -> Result<()> {
+        if status == OK {
+            Ok(())
+        } else {
+            self.channel = None;
+            Err(Error::new("The tag asked for more frames than expected"))
+        }
+    }
+}
+
+fn status_error(status: u8) -> Error {
+    match status {
+        0xAE => Error::from(ffi::NFC_EMFCAUTHFAIL),
+        0x1E => Error::new("The tag found the command's MAC or CRC wrong"),
+        0x40 => Error::new("The tag has no such key"),
+        0x7E => Error::new("The command's length is wrong"),
+        0x9D => Error::new("The command isn't allowed with the current access rights"),
+        0x9E => Error::new("The command's parameters are invalid"),
+        0xEE => Error::new("The tag failed to write to its memory"),
+        0xF0 => Error::new("The tag has no such file"),
+        _ => Error::new(&format!("The tag answered with status 91{:02X}", status)),
+    }
+}
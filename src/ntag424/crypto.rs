@@ -0,0 +1,195 @@
+//! The AES primitives of EV2 secure messaging and SDM.
+
+use super::{AesKey, AES_BLOCK_SIZE};
+use crate::{Error, Result};
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes128;
+use cmac::{Cmac, Mac};
+
+use std::convert::TryInto;
+
+const PADDING: u8 = 0x80;
+
+pub(crate) fn cmac(key: &AesKey, data: &[u8]) -> [u8; AES_BLOCK_SIZE] {
+    let mut mac = <Cmac<Aes128> as Mac>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// The 8 bytes of a CMAC that are actually sent: every other one, starting
+/// with the second.
+pub(crate) fn truncate(mac: &[u8; AES_BLOCK_SIZE]) -> [u8; 8] {
+    let mut truncated = [0; 8];
+    for (byte, chunk) in truncated.iter_mut().zip(mac.chunks_exact(2)) {
+        *byte = chunk[1];
+    }
+    truncated
+}
+
+pub(crate) fn encrypt_block(key: &AesKey, block: &[u8; AES_BLOCK_SIZE]) -> [u8; AES_BLOCK_SIZE] {
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    let mut block = GenericArray::clone_from_slice(block);
+    cipher.encrypt_block(&mut block);
+    block.into()
+}
+
+/// Encrypts in CBC mode, `data` being a whole number of blocks.
+pub(crate) fn encrypt(key: &AesKey, iv: &[u8; AES_BLOCK_SIZE], data: &[u8]) -> Vec<u8> {
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    let mut previous = *iv;
+    let mut encrypted = Vec::with_capacity(data.len());
+    for chunk in data.chunks_exact(AES_BLOCK_SIZE) {
+        let mut block = GenericArray::clone_from_slice(chunk);
+        for (byte, chained) in block.iter_mut().zip(&previous) {
+            *byte ^= chained;
+        }
+        cipher.encrypt_block(&mut block);
+        previous = block.into();
+        encrypted.extend_from_slice(&previous);
+    }
+    encrypted
+}
+
+/// Decrypts in CBC mode, `data` being a whole number of blocks.
+pub(crate) fn decrypt(key: &AesKey, iv: &[u8; AES_BLOCK_SIZE], data: &[u8]) -> Vec<u8> {
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    let mut previous = &iv[..];
+    let mut decrypted = Vec::with_capacity(data.len());
+    for chunk in data.chunks_exact(AES_BLOCK_SIZE) {
+        let mut block = GenericArray::clone_from_slice(chunk);
+        cipher.decrypt_block(&mut block);
+        for (byte, chained) in block.iter_mut().zip(previous) {
+            *byte ^= chained;
+        }
+        previous = chunk;
+        decrypted.extend_from_slice(&block);
+    }
+    decrypted
+}
+
+/// Pads to a whole number of blocks with `80 00 ...`, adding a block if
+/// `data` already is one.
+pub(crate) fn pad(data: &[u8]) -> Vec<u8> {
+    let mut padded = data.to_vec();
+    padded.push(PADDING);
+    padded.resize((data.len() / AES_BLOCK_SIZE + 1) * AES_BLOCK_SIZE, 0);
+    padded
+}
+
+pub(crate) fn unpad(data: &[u8]) -> Result<Vec<u8>> {
+    match data.iter().rposition(|&byte| byte != 0) {
+        Some(end) if data[end] == PADDING => Ok(data[..end].to_vec()),
+        _ => Err(Error::new("Decrypted data isn't padded")),
+    }
+}
+
+/// The encryption and MAC session keys of an AuthenticateEV2First.
+pub(crate) fn session_keys(
+    key: &AesKey,
+    rnd_a: &[u8; AES_BLOCK_SIZE],
+    rnd_b: &[u8; AES_BLOCK_SIZE],
+) -> (AesKey, AesKey) {
+    let mut context = Vec::with_capacity(26);
+    context.extend_from_slice(&rnd_a[0..2]);
+    context.extend(rnd_a[2..8].iter().zip(&rnd_b[0..6]).map(|(a, b)| a ^ b));
+    context.extend_from_slice(&rnd_b[6..]);
+    context.extend_from_slice(&rnd_a[8..]);
+
+    let derive = |label: [u8; 2]| {
+        let mut vector = vec![label[0], label[1], 0x00, 0x01, 0x00, 0x80];
+        vector.extend_from_slice(&context);
+        cmac(key, &vector)
+    };
+    (derive([0xa5, 0x5a]), derive([0x5a, 0xa5]))
+}
+
+/// The CRC32 ChangeKey sends with a new key: IEEE 802.3 without the final
+/// inversion, least significant byte first.
+pub(crate) fn crc32(data: &[u8]) -> [u8; 4] {
+    let crc = data.iter().fold(0xffff_ffffu32, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    });
+    crc.to_le_bytes()
+}
+
+pub(crate) fn to_block(data: &[u8]) -> [u8; AES_BLOCK_SIZE] {
+    data.try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ntag424::AES_KEY_SIZE;
+    use crate::util::decode_hex;
+
+    fn block(hex: &str) -> [u8; AES_BLOCK_SIZE] {
+        to_block(&decode_hex(hex).unwrap())
+    }
+
+    // the AuthenticateEV2First example of AN12196, with key 0 all zeros
+    #[test]
+    fn authentication_session_keys() {
+        let rnd_a = block("13C5DB8A5930439FC3DEF9A4C675360F");
+        let rnd_b = block("B9E2FC789B64BF237CCCAA20EC7E6E48");
+        let (enc_key, mac_key) = session_keys(&[0; AES_KEY_SIZE], &rnd_a, &rnd_b);
+        assert_eq!(enc_key, block("1309C877509E5A215007FF0ED19CA564"));
+        assert_eq!(mac_key, block("4C6626F5E72EA694202139295C7A7FC7"));
+    }
+
+    #[test]
+    fn truncated_mac() {
+        let mac = cmac(&[0; AES_KEY_SIZE], &[]);
+        assert_eq!(mac, block("4387C14B46EF7E176DCEEFA862D72FF9"));
+        assert_eq!(
+            truncate(&mac),
+            [0x87, 0x4b, 0xef, 0x17, 0xce, 0xa8, 0xd7, 0xf9]
+        );
+    }
+
+    #[test]
+    fn padding() {
+        assert_eq!(pad(&[]), block("80000000000000000000000000000000"));
+        assert_eq!(pad(&[1, 2, 3]), block("01020380000000000000000000000000"));
+        let full = [0x11; AES_BLOCK_SIZE];
+        let padded = pad(&full);
+        assert_eq!(padded.len(), 2 * AES_BLOCK_SIZE);
+        assert_eq!(padded[AES_BLOCK_SIZE], PADDING);
+
+        for data in &[&[][..], &[0, 0, 0x80, 0][..], &full[..]] {
+            assert_eq!(unpad(&pad(data)).unwrap(), data.to_vec());
+        }
+        assert!(unpad(&[0; AES_BLOCK_SIZE]).is_err());
+        assert!(unpad(&[0x01; AES_BLOCK_SIZE]).is_err());
+    }
+
+    #[test]
+    fn cbc_round_trip() {
+        let key = block("00112233445566778899AABBCCDDEEFF");
+        let iv = block("0F0E0D0C0B0A09080706050403020100");
+        let data = pad(b"two blocks of plain text");
+        let encrypted = encrypt(&key, &iv, &data);
+        assert_ne!(encrypted, data);
+        assert_eq!(decrypt(&key, &iv, &encrypted), data);
+
+        let mut first = iv;
+        for (byte, plain) in first.iter_mut().zip(&data) {
+            *byte ^= plain;
+        }
+        assert_eq!(encrypted[..AES_BLOCK_SIZE], encrypt_block(&key, &first));
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        // CRC-32's check value is CBF43926, inverted at the end
+        assert_eq!(crc32(b"123456789"), (!0xcbf4_3926u32).to_le_bytes());
+        assert_eq!(crc32(&[]), [0xff; 4]);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::device::{Device, PollType};
+use crate::{Baud, Error, FelicaBaud, Mode, Modulation, Result};
+
+use std::time::Duration;
+
+// libnfc counts the poll period in units of 150 ms, from 1 to 15
+const PERIOD_UNIT: Duration = Duration::from_millis(150);
+const MAX_PERIOD_UNITS: u32 = 15;
+// 0xFF means polling forever
+const MAX_POLL_NUMBER: u32 = 0xFE;
+
+/// What to poll for and for how long, for
+/// [`Initiator::poll`](struct.Initiator.html#method.poll).
+///
+/// Each round polls every modulation for `period`, and rounds repeat until a
+/// target shows up or `timeout` runs out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PollProfile {
+    pub(crate) modulations: Vec<Modulation>,
+    period: Duration,
+    timeout: Option<Duration>,
+}
+
+impl Default for PollProfile {
+    fn default() -> Self {
+        PollProfile {
+            modulations: Vec::new(),
+            period: PERIOD_UNIT * 2,
+            timeout: None,
+        }
+    }
+}
+
+impl PollProfile {
+    /// A profile without any modulations, polling forever with a 300 ms
+    /// period.
+    pub fn new() -> Self {
+        PollProfile::default()
+    }
+
+    /// Mifare, NTAG, DESFire and other ISO14443A targets.
+    pub fn all_iso14443a() -> Self {
+        PollProfile::new().modulation(Modulation::iso14443a())
+    }
+
+    /// The technologies transit cards use: Mifare and DESFire, FeliCa, and
+    /// Calypso on ISO14443B and B'.
+    pub fn transit() -> Self {
+        PollProfile::new().modulations(vec![
+            Modulation::iso14443a(),
+            Modulation::felica(FelicaBaud::B212),
+            Modulation::felica(FelicaBaud::B424),
+            Modulation::iso14443b(Baud::B106),
+            Modulation::iso14443bi(),
+        ])
+    }
+
+    /// The technologies access control badges use: Mifare and DESFire,
+    /// ISO14443B and HID iClass.
+    pub fn access_control() -> Self {
+        PollProfile::new().modulations(vec![
+            Modulation::iso14443a(),
+            Modulation::iso14443b(Baud::B106),
+            Modulation::iso14443biclass(),
+        ])
+    }
+
+    /// Every modulation and baud rate the device supports as an initiator.
+    pub fn supported_by(device: &mut Device) -> Result<Self> {
+        let modulations = device.supported_modulations_with_baud_rates(Mode::N_INITIATOR)?;
+        Ok(PollProfile::new().modulations(modulations))
+    }
+
+    pub fn modulation(mut self, modulation: Modulation) -> Self {
+        self.modulations.push(modulation);
+        self
+    }
+
+    pub fn modulations<I: IntoIterator<Item = Modulation>>(mut self, modulations: I) -> Self {
+        self.modulations.extend(modulations);
+        self
+    }
+
+    /// How long each modulation is polled for in a round, rounded to the
+    /// nearest 150 ms between 150 ms and 2.25 s.
+    pub fn period(mut self, period: Duration) -> Self {
+        self.period = period;
+        self
+    }
+
+    /// How long to poll for in total before giving up.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Polls until a target shows up.
+    pub fn forever(mut self) -> Self {
+        self.timeout = None;
+        self
+    }
+
+    /// The period in libnfc's units of 150 ms.
+    pub fn poll_period(&self) -> u8 {
+        let units =
+            (self.period.as_millis() + PERIOD_UNIT.as_millis() / 2) / PERIOD_UNIT.as_millis();
+        // clamped, so the cast can't truncate
+        units.max(1).min(u128::from(MAX_PERIOD_UNITS)) as u8
+    }
+
+    /// The number of rounds needed to cover the timeout.
+    pub fn poll_number(&self) -> PollType {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return PollType::Forever,
+        };
+
+        let round =
+            PERIOD_UNIT * u32::from(self.poll_period()) * self.modulations.len().max(1) as u32;
+        let rounds = timeout.as_millis().div_ceil(round.as_millis());
+        PollType::Limited(rounds.max(1).min(u128::from(MAX_POLL_NUMBER)) as u8)
+    }
+
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.modulations.is_empty() {
+            Err(Error::new("Poll profile doesn't have any modulations"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn period(millis: u64) -> u8 {
+        PollProfile::all_iso14443a()
+            .period(Duration::from_millis(millis))
+            .poll_period()
+    }
+
+    #[test]
+    fn poll_period() {
+        assert_eq!(PollProfile::new().poll_period(), 2);
+        // rounded to the nearest unit
+        assert_eq!(period(0), 1);
+        assert_eq!(period(74), 1);
+        assert_eq!(period(150), 1);
+        assert_eq!(period(224), 1);
+        assert_eq!(period(225), 2);
+        assert_eq!(period(1000), 7);
+        // clamped to 15 units
+        assert_eq!(period(15 * 150), 15);
+        assert_eq!(period(16 * 150), 15);
+        assert_eq!(period(3_600_000), 15);
+    }
+
+    #[test]
+    fn poll_number() {
+        assert_eq!(
+            PollProfile::all_iso14443a().poll_number(),
+            PollType::Forever
+        );
+        assert_eq!(
+            PollProfile::all_iso14443a()
+                .timeout(Duration::from_secs(1))
+                .forever()
+                .poll_number(),
+            PollType::Forever
+        );
+
+        // 300 ms rounds of one modulation, rounded up
+        let number = |millis| {
+            PollProfile::all_iso14443a()
+                .timeout(Duration::from_millis(millis))
+                .poll_number()
+        };
+        assert_eq!(number(0), PollType::Limited(1));
+        assert_eq!(number(300), PollType::Limited(1));
+        assert_eq!(number(301), PollType::Limited(2));
+        assert_eq!(number(3000), PollType::Limited(10));
+        // clamped below the 0xFF that means forever
+        assert_eq!(number(254 * 300), PollType::Limited(254));
+        assert_eq!(number(3_600_000), PollType::Limited(254));
+
+        // a round polls each of the five transit modulations for 150 ms
+        let transit = PollProfile::transit()
+            .period(Duration::from_millis(150))
+            .timeout(Duration::from_secs(3));
+        assert_eq!(transit.poll_number(), PollType::Limited(4));
+        let long_period = PollProfile::transit()
+            .period(Duration::from_secs(10))
+            .timeout(Duration::from_secs(60));
+        assert_eq!(long_period.poll_number(), PollType::Limited(6));
+    }
+
+    #[test]
+    fn presets() {
+        assert_eq!(
+            PollProfile::all_iso14443a().modulations,
+            [Modulation::iso14443a()]
+        );
+        assert_eq!(
+            PollProfile::transit().modulations,
+            [
+                Modulation::iso14443a(),
+                Modulation::felica(FelicaBaud::B212),
+                Modulation::felica(FelicaBaud::B424),
+                Modulation::iso14443b(Baud::B106),
+                Modulation::iso14443bi(),
+            ]
+        );
+        assert_eq!(
+            PollProfile::access_control().modulations,
+            [
+                Modulation::iso14443a(),
+                Modulation::iso14443b(Baud::B106),
+                Modulation::iso14443biclass(),
+            ]
+        );
+        for profile in &[
+            PollProfile::all_iso14443a(),
+            PollProfile::transit(),
+            PollProfile::access_control(),
+        ] {
+            assert_eq!(profile.poll_period(), 2);
+            assert_eq!(profile.poll_number(), PollType::Forever);
+            assert!(profile.check().is_ok());
+        }
+        assert!(PollProfile::new().check().is_err());
+    }
+}
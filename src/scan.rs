@@ -0,0 +1,40 @@
+use crate::{Error, Modulation, ModulationType, Target};
+
+use std::collections::HashSet;
+
+/// Everything found by [`Initiator::scan_all`](struct.Initiator.html#method.scan_all).
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    /// Each target in the field once, in the order they were found.
+    pub targets: Vec<Target>,
+    /// The modulations that were scanned successfully.
+    pub scanned: Vec<Modulation>,
+    /// The modulations that couldn't be scanned, and why.
+    pub failures: Vec<(Modulation, Error)>,
+    seen: HashSet<(ModulationType, Vec<u8>)>,
+}
+
+impl ScanReport {
+    pub(crate) fn new() -> Self {
+        ScanReport::default()
+    }
+
+    /// Adds the targets found with a modulation, skipping any seen before,
+    /// e.g. a FeliCa target answering at both 212 and 424 kbps.
+    pub(crate) fn add(&mut self, modulation: Modulation, targets: Vec<Target>) {
+        self.scanned.push(modulation);
+        for target in targets {
+            let key = (
+                target.info.modulation_type(),
+                target.info.identifier().to_vec(),
+            );
+            if self.seen.insert(key) {
+                self.targets.push(target);
+            }
+        }
+    }
+
+    pub(crate) fn fail(&mut self, modulation: Modulation, error: Error) {
+        self.failures.push((modulation, error));
+    }
+}
@@ -0,0 +1,244 @@
+use crate::{Error, Modulation, ModulationType, Result, Target, TargetInfo};
+
+/// FeliCa polling request code, which asks the target to append extra
+/// information to its response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FelicaRequestCode {
+    None = 0x00,
+    SystemCode = 0x01,
+    CommunicationPerformance = 0x02,
+}
+
+/// Number of time slots targets may answer a FeliCa polling request in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FelicaTimeSlots {
+    One = 0x00,
+    Two = 0x01,
+    Four = 0x03,
+    Eight = 0x07,
+    Sixteen = 0x0f,
+}
+
+/// Which target `select_passive_target` should select, encoded into the
+/// initiator data libnfc expects for the modulation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum SelectCriteria {
+    /// The first target to answer, using libnfc's defaults.
+    #[default]
+    Any,
+    /// The ISO14443A target with this (4, 7 or 10 byte) UID. libnfc adds the
+    /// cascade tags.
+    Iso14443a { uid: Vec<u8> },
+    /// FeliCa polling for a system code, `0xFFFF` being the wildcard.
+    Felica {
+        system_code: u16,
+        request_code: FelicaRequestCode,
+        time_slots: FelicaTimeSlots,
+    },
+    /// ISO14443B targets in an Application Family, `0x00` being all of them.
+    /// With `timeslot_approach` the initiator uses time slots instead of the
+    /// probabilistic approach during anticollision.
+    Iso14443b { afi: u8, timeslot_approach: bool },
+    /// Initiator data passed to libnfc as is.
+    Raw(Vec<u8>),
+}
+
+impl SelectCriteria {
+    /// Any FeliCa target, without asking for the system code.
+    pub fn any_felica() -> Self {
+        SelectCriteria::Felica {
+            system_code: 0xffff,
+            request_code: FelicaRequestCode::None,
+            time_slots: FelicaTimeSlots::One,
+        }
+    }
+
+    /// Criteria that find the target again. Only ISO14443A targets can be
+    /// selected by identifier, for the others any target of the same kind is
+    /// selected and it's up to the caller to compare identifiers.
+    pub fn for_target(target: &Target) -> Self {
+        match &target.info {
+            TargetInfo::ISO14443A { info } => SelectCriteria::Iso14443a {
+                uid: info.uid.clone(),
+            },
+            TargetInfo::FELICA { .. } => SelectCriteria::any_felica(),
+            TargetInfo::ISO14443B { .. } => SelectCriteria::Iso14443b {
+                afi: 0x00,
+                timeslot_approach: false,
+            },
+            _ => SelectCriteria::Any,
+        }
+    }
+
+    /// The initiator data to pass to libnfc.
+    pub fn init_data(&self) -> Vec<u8> {
+        match self {
+            SelectCriteria::Any => Vec::new(),
+            SelectCriteria::Iso14443a { uid } => uid.clone(),
+            SelectCriteria::Felica {
+                system_code,
+                request_code,
+                time_slots,
+            } => {
+                let [code_high, code_low] = system_code.to_be_bytes();
+                // polling command code, system code, request code, slot count
+                vec![
+                    0x00,
+                    code_high,
+                    code_low,
+                    *request_code as u8,
+                    *time_slots as u8,
+                ]
+            }
+            SelectCriteria::Iso14443b {
+                afi,
+                timeslot_approach,
+            } => {
+                if *timeslot_approach {
+                    vec![*afi, 0x01]
+                } else {
+                    vec![*afi]
+                }
+            }
+            SelectCriteria::Raw(data) => data.clone(),
+        }
+    }
+
+    /// Checks the criteria make sense for the modulation.
+    pub fn check(&self, modulation: Modulation) -> Result<()> {
+        let expected = match self {
+            SelectCriteria::Any | SelectCriteria::Raw(_) => return Ok(()),
+            SelectCriteria::Iso14443a { uid } => {
+                if ![4, 7, 10].contains(&uid.len()) {
+                    return Err(Error::new(&format!(
+                        "ISO14443A UIDs are 4, 7 or 10 bytes, not {}",
+                        uid.len()
+                    )));
+                }
+                ModulationType::NMT_ISO14443A
+            }
+            SelectCriteria::Felica { .. } => ModulationType::NMT_FELICA,
+            SelectCriteria::Iso14443b { .. } => ModulationType::NMT_ISO14443B,
+        };
+
+        if modulation.modulation_type() == expected {
+            Ok(())
+        } else {
+            Err(Error::new(&format!(
+                "{} criteria can't select a {} target",
+                expected,
+                modulation.modulation_type()
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Baud, FelicaBaud};
+
+    #[test]
+    fn felica_init_data() {
+        // the polling payload libnfc sends by default: any system, asking
+        // for the system code, one time slot
+        let default = SelectCriteria::Felica {
+            system_code: 0xffff,
+            request_code: FelicaRequestCode::SystemCode,
+            time_slots: FelicaTimeSlots::One,
+        };
+        assert_eq!(default.init_data(), [0x00, 0xff, 0xff, 0x01, 0x00]);
+
+        assert_eq!(
+            SelectCriteria::any_felica().init_data(),
+            [0x00, 0xff, 0xff, 0x00, 0x00]
+        );
+        // the NFC Forum Type 3 Tag system code, 16 slots
+        let type3 = SelectCriteria::Felica {
+            system_code: 0x12fc,
+            request_code: FelicaRequestCode::CommunicationPerformance,
+            time_slots: FelicaTimeSlots::Sixteen,
+        };
+        assert_eq!(type3.init_data(), [0x00, 0x12, 0xfc, 0x02, 0x0f]);
+
+        let slots = [
+            (FelicaTimeSlots::One, 0x00),
+            (FelicaTimeSlots::Two, 0x01),
+            (FelicaTimeSlots::Four, 0x03),
+            (FelicaTimeSlots::Eight, 0x07),
+            (FelicaTimeSlots::Sixteen, 0x0f),
+        ];
+        for &(time_slots, byte) in slots.iter() {
+            let criteria = SelectCriteria::Felica {
+                system_code: 0x88b4,
+                request_code: FelicaRequestCode::None,
+                time_slots,
+            };
+            assert_eq!(criteria.init_data(), [0x00, 0x88, 0xb4, 0x00, byte]);
+        }
+    }
+
+    #[test]
+    fn iso14443b_init_data() {
+        let all = SelectCriteria::Iso14443b {
+            afi: 0x00,
+            timeslot_approach: false,
+        };
+        assert_eq!(all.init_data(), [0x00]);
+        // transport, then the polling method byte asking for time slots
+        let transport = SelectCriteria::Iso14443b {
+            afi: 0x10,
+            timeslot_approach: true,
+        };
+        assert_eq!(transport.init_data(), [0x10, 0x01]);
+    }
+
+    #[test]
+    fn other_init_data() {
+        assert!(SelectCriteria::Any.init_data().is_empty());
+        let uid = vec![0x04, 0x44, 0x8b, 0xd2, 0xdb, 0x6b, 0x80];
+        assert_eq!(
+            SelectCriteria::Iso14443a { uid: uid.clone() }.init_data(),
+            uid
+        );
+        assert_eq!(
+            SelectCriteria::Raw(vec![0x01, 0x02]).init_data(),
+            [0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn check() {
+        let iso14443a = Modulation::iso14443a();
+        let felica = Modulation::felica(FelicaBaud::B212);
+        let iso14443b = Modulation::iso14443b(Baud::B106);
+
+        for &size in &[4, 7, 10] {
+            let criteria = SelectCriteria::Iso14443a {
+                uid: vec![0x04; size],
+            };
+            assert!(criteria.check(iso14443a).is_ok());
+            assert!(criteria.check(felica).is_err());
+        }
+        for &size in &[0, 5, 11] {
+            let criteria = SelectCriteria::Iso14443a {
+                uid: vec![0x04; size],
+            };
+            assert!(criteria.check(iso14443a).is_err());
+        }
+
+        assert!(SelectCriteria::any_felica().check(felica).is_ok());
+        assert!(SelectCriteria::any_felica().check(iso14443a).is_err());
+        let b = SelectCriteria::Iso14443b {
+            afi: 0x00,
+            timeslot_approach: false,
+        };
+        assert!(b.check(iso14443b).is_ok());
+        assert!(b.check(Modulation::iso14443bi()).is_err());
+
+        for &modulation in &[iso14443a, felica, iso14443b, Modulation::jewel()] {
+            assert!(SelectCriteria::Any.check(modulation).is_ok());
+            assert!(SelectCriteria::Raw(vec![0xff]).check(modulation).is_ok());
+        }
+    }
+}
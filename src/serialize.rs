@@ -0,0 +1,335 @@
+//! Serde support, enabled by the `serde` feature.
+//!
+//! The bindgen types get hand written impls with stable names, so the
+//! representation doesn't change if libnfc renumbers its enums. Byte strings
+//! such as UIDs are written as lowercase hex.
+
+use crate::ffi::{nfc_baud_rate, nfc_dep_mode, nfc_modulation, nfc_modulation_type};
+use crate::target_info::Ats;
+use crate::util::{decode_hex, encode_hex};
+use crate::Modulation;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use std::convert::TryFrom;
+
+/// `#[serde(with = "hex")]` for byte arrays and vectors.
+pub(crate) mod hex {
+    use serde::de::{Deserialize, Deserializer, Error};
+    use serde::ser::Serializer;
+    use std::convert::TryFrom;
+
+    pub fn serialize<S, T>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: AsRef<[u8]>,
+    {
+        serializer.serialize_str(&super::encode_hex(bytes.as_ref()))
+    }
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: TryFrom<Vec<u8>>,
+    {
+        let hex = String::deserialize(deserializer)?;
+        let bytes = super::decode_hex(&hex).map_err(D::Error::custom)?;
+        let len = bytes.len();
+        T::try_from(bytes).map_err(|_| D::Error::invalid_length(len, &"a fixed number of bytes"))
+    }
+}
+
+/// `#[serde(with = "hex_option")]` for optional byte arrays.
+pub(crate) mod hex_option {
+    use serde::de::{Deserialize, Deserializer, Error};
+    use serde::ser::Serializer;
+    use std::convert::TryFrom;
+
+    pub fn serialize<S, T>(bytes: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: AsRef<[u8]>,
+    {
+        match bytes {
+            Some(bytes) => serializer.serialize_some(&super::encode_hex(bytes.as_ref())),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: TryFrom<Vec<u8>>,
+    {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(hex) => {
+                let bytes = super::decode_hex(&hex).map_err(D::Error::custom)?;
+                let len = bytes.len();
+                T::try_from(bytes)
+                    .map(Some)
+                    .map_err(|_| D::Error::invalid_length(len, &"a fixed number of bytes"))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Implements `Serialize` and `Deserialize` for a fieldless enum as a fixed
+/// set of strings.
+macro_rules! string_enum {
+    ($type:ty, { $($variant:path => $name:expr,)* }) => {
+        impl Serialize for $type {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(match self {
+                    $($variant => $name,)*
+                })
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $type {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                const NAMES: &[&str] = &[$($name),*];
+                let name = String::deserialize(deserializer)?;
+                match name.as_str() {
+                    $($name => Ok($variant),)*
+                    _ => Err(de::Error::unknown_variant(&name, NAMES)),
+                }
+            }
+        }
+    };
+}
+
+string_enum!(nfc_modulation_type, {
+    nfc_modulation_type::NMT_ISO14443A => "ISO14443A",
+    nfc_modulation_type::NMT_JEWEL => "JEWEL",
+    nfc_modulation_type::NMT_ISO14443B => "ISO14443B",
+    nfc_modulation_type::NMT_ISO14443BI => "ISO14443BI",
+    nfc_modulation_type::NMT_ISO14443B2SR => "ISO14443B2SR",
+    nfc_modulation_type::NMT_ISO14443B2CT => "ISO14443B2CT",
+    nfc_modulation_type::NMT_FELICA => "FELICA",
+    nfc_modulation_type::NMT_DEP => "DEP",
+    nfc_modulation_type::NMT_BARCODE => "BARCODE",
+    nfc_modulation_type::NMT_ISO14443BICLASS => "ISO14443BICLASS",
+});
+
+string_enum!(nfc_baud_rate, {
+    nfc_baud_rate::NBR_UNDEFINED => "undefined",
+    nfc_baud_rate::NBR_106 => "106kbps",
+    nfc_baud_rate::NBR_212 => "212kbps",
+    nfc_baud_rate::NBR_424 => "424kbps",
+    nfc_baud_rate::NBR_847 => "847kbps",
+});
+
+string_enum!(nfc_dep_mode, {
+    nfc_dep_mode::NDM_UNDEFINED => "undefined",
+    nfc_dep_mode::NDM_PASSIVE => "passive",
+    nfc_dep_mode::NDM_ACTIVE => "active",
+});
+
+impl Serialize for Modulation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Modulation", 2)?;
+        state.serialize_field("type", &self.modulation_type())?;
+        state.serialize_field("baud_rate", &self.baud_rate())?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Modulation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct RawModulation {
+            #[serde(rename = "type")]
+            nmt: nfc_modulation_type,
+            baud_rate: nfc_baud_rate,
+        }
+
+        let modulation = RawModulation::deserialize(deserializer)?;
+        Modulation::try_from(nfc_modulation {
+            nmt: modulation.nmt,
+            nbr: modulation.baud_rate,
+        })
+        .map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Ats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        hex::serialize(&self.as_bytes(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ats {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        hex::deserialize(deserializer).map(Ats::new)
+    }
+}
+
+#[cfg(all(test, feature = "serde_json"))]
+mod tests {
+    use super::*;
+    use crate::target_info::{FelicaInfo, Iso14443aInfo};
+    use crate::{BaudRate, FelicaBaud, Target, TargetInfo};
+
+    use serde_json::Value;
+
+    fn json(text: &str) -> Value {
+        serde_json::from_str(text).unwrap()
+    }
+
+    fn round_trip<T>(value: &T, text: &str)
+    where
+        T: Serialize + for<'de> Deserialize<'de> + PartialEq + std::fmt::Debug,
+    {
+        assert_eq!(serde_json::to_value(value).unwrap(), json(text));
+        assert_eq!(&serde_json::from_str::<T>(text).unwrap(), value);
+    }
+
+    #[test]
+    fn iso14443a_target() {
+        let target = Target {
+            baud_rate: BaudRate::NBR_106,
+            info: TargetInfo::ISO14443A {
+                info: Iso14443aInfo {
+                    atqa: [0x03, 0x44],
+                    sak: 0x20,
+                    uid: vec![0x04, 0x44, 0x8b, 0xd2, 0xdb, 0x6b, 0x80],
+                    ats: Some(Ats::new(vec![0x75, 0x77, 0x81, 0x02, 0x80])),
+                },
+            },
+        };
+        round_trip(
+            &target,
+            r#"{
+                "baud_rate": "106kbps",
+                "info": {
+                    "type": "ISO14443A",
+                    "info": {
+                        "atqa": "0344",
+                        "sak": 32,
+                        "uid": "04448bd2db6b80",
+                        "ats": "7577810280"
+                    }
+                }
+            }"#,
+        );
+
+        // hex is read in either case
+        let upper = r#"{
+            "baud_rate": "106kbps",
+            "info": {
+                "type": "ISO14443A",
+                "info": { "atqa": "0004", "sak": 8, "uid": "B4F4E9C2", "ats": null }
+            }
+        }"#;
+        let target: Target = serde_json::from_str(upper).unwrap();
+        assert_eq!(target.info.identifier(), [0xb4, 0xf4, 0xe9, 0xc2]);
+    }
+
+    #[test]
+    fn felica_target() {
+        let target = Target {
+            baud_rate: BaudRate::NBR_212,
+            info: TargetInfo::FELICA {
+                info: FelicaInfo {
+                    response_code: 0x01,
+                    idm: [0x01, 0x2e, 0x4c, 0xcd, 0x7e, 0x83, 0x26, 0x07],
+                    pmm: [0x03, 0x01, 0x4b, 0x02, 0x4f, 0x49, 0x93, 0xff],
+                    system_code: Some([0x88, 0xb4]),
+                },
+            },
+        };
+        round_trip(
+            &target,
+            r#"{
+                "baud_rate": "212kbps",
+                "info": {
+                    "type": "FELICA",
+                    "info": {
+                        "response_code": 1,
+                        "idm": "012e4ccd7e832607",
+                        "pmm": "03014b024f4993ff",
+                        "system_code": "88b4"
+                    }
+                }
+            }"#,
+        );
+    }
+
+    #[test]
+    fn invalid_targets() {
+        for text in &[
+            // unknown type tag
+            r#"{"baud_rate": "106kbps", "info": {"type": "NFC-V", "info": {}}}"#,
+            // ATQA of the wrong size
+            r#"{"baud_rate": "106kbps", "info": {"type": "ISO14443A",
+                "info": {"atqa": "000400", "sak": 8, "uid": "b4f4e9c2", "ats": null}}}"#,
+            // odd number of hex digits
+            r#"{"baud_rate": "106kbps", "info": {"type": "ISO14443A",
+                "info": {"atqa": "0004", "sak": 8, "uid": "b4f4e9c", "ats": null}}}"#,
+            // not hex
+            r#"{"baud_rate": "106kbps", "info": {"type": "ISO14443A",
+                "info": {"atqa": "0004", "sak": 8, "uid": "b4f4e9cg", "ats": null}}}"#,
+            r#"{"baud_rate": "100kbps", "info": {"type": "ISO14443A",
+                "info": {"atqa": "0004", "sak": 8, "uid": "b4f4e9c2", "ats": null}}}"#,
+        ] {
+            assert!(serde_json::from_str::<Target>(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn modulation() {
+        round_trip(
+            &Modulation::iso14443a(),
+            r#"{"type": "ISO14443A", "baud_rate": "106kbps"}"#,
+        );
+        round_trip(
+            &Modulation::felica(FelicaBaud::B424),
+            r#"{"type": "FELICA", "baud_rate": "424kbps"}"#,
+        );
+        round_trip(
+            &Modulation::iso14443biclass(),
+            r#"{"type": "ISO14443BICLASS", "baud_rate": "106kbps"}"#,
+        );
+
+        for text in &[
+            // FeliCa isn't defined at 106 kbps
+            r#"{"type": "FELICA", "baud_rate": "106kbps"}"#,
+            r#"{"type": "ISO14443A", "baud_rate": "undefined"}"#,
+            r#"{"type": "ISO14443A"}"#,
+            r#"{"type": "ISO14443A", "baud_rate": "106kbps", "extra": 1}"#,
+            r#"{"type": "iso14443a", "baud_rate": "106kbps"}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<Modulation>(text).is_err(),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn baud_rate() {
+        for &(baud_rate, name) in &[
+            (BaudRate::NBR_UNDEFINED, "\"undefined\""),
+            (BaudRate::NBR_106, "\"106kbps\""),
+            (BaudRate::NBR_212, "\"212kbps\""),
+            (BaudRate::NBR_424, "\"424kbps\""),
+            (BaudRate::NBR_847, "\"847kbps\""),
+        ] {
+            round_trip(&baud_rate, name);
+        }
+        assert!(serde_json::from_str::<BaudRate>("\"1Mbps\"").is_err());
+        assert!(serde_json::from_str::<BaudRate>("1").is_err());
+    }
+
+    #[test]
+    fn ats() {
+        round_trip(&Ats::new(vec![0x78, 0x77, 0x80, 0x02]), "\"78778002\"");
+        round_trip(&Ats::new(Vec::new()), "\"\"");
+        assert!(serde_json::from_str::<Ats>("\"7877800\"").is_err());
+        assert!(serde_json::from_str::<Ats>("[120, 119]").is_err());
+    }
+}
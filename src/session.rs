@@ -0,0 +1,172 @@
+use crate::device::{Initiator, TargetResultEnum};
+use crate::ffi;
+use crate::{Error, ErrorKind, Modulation, Result, SelectCriteria, Target};
+
+use std::time::Duration;
+
+/// How hard a [`Session`](struct.Session.html) tries to get its target back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Reselection attempts per command before giving up, and how many
+    /// times a command is run again after its target was lost.
+    pub max_attempts: u32,
+    /// How long to wait before each attempt, giving the card time to come
+    /// back into the field.
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            delay: Duration::from_millis(50),
+        }
+    }
+}
+
+/// Passed to the resync callback every time a session got its target back.
+#[derive(Debug)]
+pub struct ResyncEvent<'a> {
+    /// Which attempt succeeded, starting at 1.
+    pub attempt: u32,
+    /// The error that made the session reselect.
+    pub cause: ErrorKind,
+    /// The target as it answered the reselection.
+    pub target: &'a Target,
+}
+
+type ResyncCallback<'a> = Box<dyn FnMut(&ResyncEvent<'_>) + 'a>;
+
+/// A selected target which is reselected automatically when it briefly
+/// leaves the field.
+///
+/// Commands sent through [`transceive_idempotent`](#method.transceive_idempotent)
+/// or [`retry`](#method.retry) are sent again after reselecting, so only use
+/// them for commands that are safe to repeat. Anything else should go
+/// through [`initiator`](#method.initiator) directly.
+pub struct Session<'a, 'context> {
+    initiator: &'a mut Initiator<'context>,
+    target: Target,
+    modulation: Modulation,
+    criteria: SelectCriteria,
+    policy: RetryPolicy,
+    on_resync: Option<ResyncCallback<'a>>,
+}
+
+impl<'a, 'context> Session<'a, 'context> {
+    /// Starts a session for a target which was just selected on the initiator.
+    pub fn new(initiator: &'a mut Initiator<'context>, target: Target) -> Result<Self> {
+        Ok(Session {
+            modulation: Modulation::of_target(&target)?,
+            criteria: SelectCriteria::for_target(&target),
+            initiator,
+            target,
+            policy: RetryPolicy::default(),
+            on_resync: None,
+        })
+    }
+
+    pub fn with_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Calls `callback` every time the target had to be reselected.
+    pub fn on_resync<F: FnMut(&ResyncEvent<'_>) + 'a>(mut self, callback: F) -> Self {
+        self.on_resync = Some(Box::new(callback));
+        self
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    pub fn initiator(&mut self) -> &mut Initiator<'context> {
+        self.initiator
+    }
+
+    /// Sends a command which is safe to repeat, reselecting the target and
+    /// sending it again if the target went away.
+    pub fn transceive_idempotent(
+        &mut self,
+        send: &[u8],
+        receive_size: ffi::size_t,
+        timeout: ffi::c_int,
+    ) -> Result<Vec<u8>> {
+        self.retry(|initiator| initiator.transceive_bytes(send, receive_size, timeout))
+    }
+
+    /// Runs an operation which is safe to repeat, reselecting the target and
+    /// running it again if the target went away. Once it has been run again
+    /// `max_attempts` times, its last error is returned, so a card that keeps
+    /// reselecting but never answers doesn't hang the caller.
+    pub fn retry<T, F>(&mut self, mut operation: F) -> Result<T>
+    where
+        F: FnMut(&mut Initiator<'context>) -> Result<T>,
+    {
+        let mut reruns = 0;
+        loop {
+            let error = match operation(self.initiator) {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            match error.kind() {
+                Some(kind) if is_target_lost(kind) && reruns < self.policy.max_attempts => {
+                    reruns += 1;
+                    self.resync(kind, error)?
+                }
+                _ => return Err(error),
+            }
+        }
+    }
+
+    fn resync(&mut self, cause: ErrorKind, error: Error) -> Result<()> {
+        for attempt in 1..=self.policy.max_attempts {
+            std::thread::sleep(self.policy.delay);
+            match self.reselect() {
+                Ok(true) => {
+                    if let Some(callback) = self.on_resync.as_mut() {
+                        callback(&ResyncEvent {
+                            attempt,
+                            cause,
+                            target: &self.target,
+                        });
+                    }
+                    return Ok(());
+                }
+                Ok(false) => continue,
+                Err(reselect_error) => match reselect_error.kind() {
+                    Some(kind) if is_target_lost(kind) => continue,
+                    _ => return Err(reselect_error),
+                },
+            }
+        }
+        Err(error)
+    }
+
+    /// Tries to select the target again, returning whether it's back.
+    fn reselect(&mut self) -> Result<bool> {
+        let found = match self
+            .initiator
+            .select_passive_target(self.modulation, &self.criteria)?
+        {
+            TargetResultEnum::Found(found) => found.target,
+            TargetResultEnum::Empty => return Ok(false),
+        };
+
+        if found.info.identifier() != self.target.info.identifier() {
+            return Err(Error::new(
+                "A different target was selected while reselecting",
+            ));
+        }
+        self.target = found;
+        Ok(true)
+    }
+}
+
+fn is_target_lost(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::TargetReleased | ErrorKind::RFTransmission | ErrorKind::Timeout
+    )
+}
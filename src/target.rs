@@ -1,22 +1,23 @@
+use crate::display::VerboseTarget;
 use crate::ffi;
+use crate::target_info;
+use crate::{Error, Result};
 
-pub mod target_info {
-    pub use crate::ffi::{
-        nfc_barcode_info as BarcodeInfo, nfc_dep_info as DepInfo, nfc_felica_info as FelicaInfo,
-        nfc_iso14443a_info as Iso14443aInfo, nfc_iso14443b2ct_info as Iso14443b2ctInfo,
-        nfc_iso14443b2sr_info as Iso14443b2srInfo, nfc_iso14443b_info as Iso14443bInfo,
-        nfc_iso14443biclass_info as Iso14443biclassInfo,
-        nfc_iso14443bi_info as Iso14443biInfo, nfc_jewel_info as JewelInfo,
-    };
-}
+use std::convert::{TryFrom, TryInto};
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Target {
     pub baud_rate: crate::BaudRate,
     pub info: TargetInfo,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type")
+)]
 pub enum TargetInfo {
     ISO14443A { info: target_info::Iso14443aInfo },
     FELICA { info: target_info::FelicaInfo },
@@ -30,39 +31,81 @@ pub enum TargetInfo {
     DEP { info: target_info::DepInfo },
 }
 
+impl Target {
+    /// Formats the target like `nfc-list -v`, decoding ATQA, SAK, ATS and
+    /// protocol info rather than only dumping them.
+    pub fn verbose(&self) -> VerboseTarget<'_> {
+        VerboseTarget(self)
+    }
+}
+
+impl TargetInfo {
+    pub fn modulation_type(&self) -> crate::ModulationType {
+        match self {
+            TargetInfo::ISO14443A { .. } => ffi::nfc_modulation_type::NMT_ISO14443A,
+            TargetInfo::FELICA { .. } => ffi::nfc_modulation_type::NMT_FELICA,
+            TargetInfo::ISO14443B { .. } => ffi::nfc_modulation_type::NMT_ISO14443B,
+            TargetInfo::ISO14443BI { .. } => ffi::nfc_modulation_type::NMT_ISO14443BI,
+            TargetInfo::ISO14443BICLASS { .. } => ffi::nfc_modulation_type::NMT_ISO14443BICLASS,
+            TargetInfo::ISO14443B2SR { .. } => ffi::nfc_modulation_type::NMT_ISO14443B2SR,
+            TargetInfo::ISO14443B2CT { .. } => ffi::nfc_modulation_type::NMT_ISO14443B2CT,
+            TargetInfo::JEWEL { .. } => ffi::nfc_modulation_type::NMT_JEWEL,
+            TargetInfo::BARCODE { .. } => ffi::nfc_modulation_type::NMT_BARCODE,
+            TargetInfo::DEP { .. } => ffi::nfc_modulation_type::NMT_DEP,
+        }
+    }
+
+    /// The bytes identifying this target among others of its modulation: the
+    /// UID, IDm, PUPI and so on.
+    pub fn identifier(&self) -> &[u8] {
+        match self {
+            TargetInfo::ISO14443A { info } => &info.uid,
+            TargetInfo::FELICA { info } => &info.idm,
+            TargetInfo::ISO14443B { info } => &info.pupi,
+            TargetInfo::ISO14443BI { info } => &info.div,
+            TargetInfo::ISO14443BICLASS { info } => &info.uid,
+            TargetInfo::ISO14443B2SR { info } => &info.uid,
+            TargetInfo::ISO14443B2CT { info } => &info.uid,
+            TargetInfo::JEWEL { info } => &info.id,
+            TargetInfo::BARCODE { info } => &info.data,
+            TargetInfo::DEP { info } => &info.nfcid3,
+        }
+    }
+}
+
 impl From<ffi::nfc_target> for Target {
     fn from(target: ffi::nfc_target) -> Target {
         let inner = unsafe {
             match target.nm.nmt {
                 ffi::nfc_modulation_type::NMT_ISO14443A => TargetInfo::ISO14443A {
-                    info: target.nti.nai,
+                    info: target.nti.nai.into(),
                 },
                 ffi::nfc_modulation_type::NMT_ISO14443B => TargetInfo::ISO14443B {
-                    info: target.nti.nbi,
+                    info: target.nti.nbi.into(),
                 },
                 ffi::nfc_modulation_type::NMT_ISO14443BI => TargetInfo::ISO14443BI {
-                    info: target.nti.nii,
+                    info: target.nti.nii.into(),
                 },
                 ffi::nfc_modulation_type::NMT_ISO14443BICLASS => TargetInfo::ISO14443BICLASS {
-                    info: target.nti.nhi,
+                    info: target.nti.nhi.into(),
                 },
                 ffi::nfc_modulation_type::NMT_ISO14443B2SR => TargetInfo::ISO14443B2SR {
-                    info: target.nti.nsi,
+                    info: target.nti.nsi.into(),
                 },
                 ffi::nfc_modulation_type::NMT_ISO14443B2CT => TargetInfo::ISO14443B2CT {
-                    info: target.nti.nci,
+                    info: target.nti.nci.into(),
                 },
                 ffi::nfc_modulation_type::NMT_FELICA => TargetInfo::FELICA {
-                    info: target.nti.nfi,
+                    info: target.nti.nfi.into(),
                 },
                 ffi::nfc_modulation_type::NMT_BARCODE => TargetInfo::BARCODE {
-                    info: target.nti.nti,
+                    info: target.nti.nti.into(),
                 },
                 ffi::nfc_modulation_type::NMT_JEWEL => TargetInfo::JEWEL {
-                    info: target.nti.nji,
+                    info: target.nti.nji.into(),
                 },
                 ffi::nfc_modulation_type::NMT_DEP => TargetInfo::DEP {
-                    info: target.nti.ndi,
+                    info: target.nti.ndi.into(),
                 },
             }
         };
@@ -80,57 +123,57 @@ impl From<&ffi::nfc_target> for Target {
     }
 }
 
-impl Into<ffi::nfc_target> for Target {
-    fn into(self) -> ffi::nfc_target {
-        let specifics = match self.info {
-            TargetInfo::ISO14443A { info } => (
-                ffi::nfc_target_info { nai: info },
-                ffi::nfc_modulation_type::NMT_ISO14443A,
-            ),
-            TargetInfo::FELICA { info } => (
-                ffi::nfc_target_info { nfi: info },
-                ffi::nfc_modulation_type::NMT_FELICA,
-            ),
-            TargetInfo::ISO14443B { info } => (
-                ffi::nfc_target_info { nbi: info },
-                ffi::nfc_modulation_type::NMT_ISO14443B,
-            ),
-            TargetInfo::ISO14443BI { info } => (
-                ffi::nfc_target_info { nii: info },
-                ffi::nfc_modulation_type::NMT_ISO14443BI,
-            ),
-            TargetInfo::ISO14443B2SR { info } => (
-                ffi::nfc_target_info { nsi: info },
-                ffi::nfc_modulation_type::NMT_ISO14443B2SR,
-            ),
-            TargetInfo::ISO14443B2CT { info } => (
-                ffi::nfc_target_info { nci: info },
-                ffi::nfc_modulation_type::NMT_ISO14443B2CT,
-            ),
-            TargetInfo::JEWEL { info } => (
-                ffi::nfc_target_info { nji: info },
-                ffi::nfc_modulation_type::NMT_JEWEL,
-            ),
-            TargetInfo::BARCODE { info } => (
-                ffi::nfc_target_info { nti: info },
-                ffi::nfc_modulation_type::NMT_BARCODE,
-            ),
-            TargetInfo::DEP { info } => (
-                ffi::nfc_target_info { ndi: info },
-                ffi::nfc_modulation_type::NMT_DEP,
-            ),
-            TargetInfo::ISO14443BICLASS { info } => (
-                ffi::nfc_target_info { nhi: info },
-                ffi::nfc_modulation_type::NMT_ISO14443BICLASS,
-            ),
+impl TryFrom<&Target> for ffi::nfc_target {
+    type Error = Error;
+
+    fn try_from(target: &Target) -> Result<ffi::nfc_target> {
+        let nti = match &target.info {
+            TargetInfo::ISO14443A { info } => ffi::nfc_target_info {
+                nai: info.try_into()?,
+            },
+            TargetInfo::FELICA { info } => ffi::nfc_target_info {
+                nfi: info.try_into()?,
+            },
+            TargetInfo::ISO14443B { info } => ffi::nfc_target_info {
+                nbi: info.try_into()?,
+            },
+            TargetInfo::ISO14443BI { info } => ffi::nfc_target_info {
+                nii: info.try_into()?,
+            },
+            TargetInfo::ISO14443B2SR { info } => ffi::nfc_target_info {
+                nsi: info.try_into()?,
+            },
+            TargetInfo::ISO14443B2CT { info } => ffi::nfc_target_info {
+                nci: info.try_into()?,
+            },
+            TargetInfo::JEWEL { info } => ffi::nfc_target_info {
+                nji: info.try_into()?,
+            },
+            TargetInfo::BARCODE { info } => ffi::nfc_target_info {
+                nti: info.try_into()?,
+            },
+            TargetInfo::DEP { info } => ffi::nfc_target_info {
+                ndi: info.try_into()?,
+            },
+            TargetInfo::ISO14443BICLASS { info } => ffi::nfc_target_info {
+                nhi: info.try_into()?,
+            },
         };
 
-        ffi::nfc_target {
-            nti: specifics.0,
+        Ok(ffi::nfc_target {
+            nti,
             nm: ffi::nfc_modulation {
-                nmt: specifics.1,
-                nbr: self.baud_rate,
+                nmt: target.info.modulation_type(),
+                nbr: target.baud_rate,
             },
-        }
+        })
+    }
+}
+
+impl TryFrom<Target> for ffi::nfc_target {
+    type Error = Error;
+
+    fn try_from(target: Target) -> Result<ffi::nfc_target> {
+        ffi::nfc_target::try_from(&target)
     }
 }
@@ -0,0 +1,716 @@
+//! Owned, safe equivalents of the libnfc `nfc_*_info` structs.
+//!
+//! Each type converts from its raw counterpart with `From`, and back with
+//! `TryFrom`, which fails if a variable-length field doesn't fit in the fixed
+//! size buffer libnfc uses.
+
+use crate::ffi;
+use crate::{BaudRate, Error, Result};
+
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// Copies `src` into the front of `dst`, failing if it doesn't fit.
+fn copy_into(dst: &mut [u8], src: &[u8], what: &str) -> Result<usize> {
+    if src.len() > dst.len() {
+        return Err(Error::new(&format!(
+            "{} is {} bytes, but at most {} are supported",
+            what,
+            src.len(),
+            dst.len()
+        )));
+    }
+    dst[..src.len()].copy_from_slice(src);
+    Ok(src.len())
+}
+
+/// Reads the first `len` bytes of `src`, clamping to its size.
+fn read_from(src: &[u8], len: usize) -> Vec<u8> {
+    src[..std::cmp::min(len, src.len())].to_vec()
+}
+
+/// Maximum frame size for a FSCI / FSDI, ISO14443-3 table 7. Values above 8
+/// are RFU and must be treated as 256 bytes.
+pub(crate) fn frame_size(fsci: u8) -> usize {
+    const FRAME_SIZES: [usize; 9] = [16, 24, 32, 40, 48, 64, 96, 128, 256];
+    FRAME_SIZES[std::cmp::min(usize::from(fsci), FRAME_SIZES.len() - 1)]
+}
+
+/// Frame waiting time for a FWI (or SFGI), ISO14443-4 section 7.2:
+/// `(256 * 16 / fc) * 2^FWI`.
+pub(crate) fn frame_waiting_time(integer: u8) -> Duration {
+    const CARRIER_HZ: u64 = 13_560_000;
+    Duration::from_nanos(((256 * 16) << u64::from(integer & 0x0f)) * 1_000_000_000 / CARRIER_HZ)
+}
+
+/// The bit rates supported by a PICC, as advertised by TA(1) in the ATS or
+/// the first protocol info byte of the ATQB. Both use the same layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BitRates(pub u8);
+
+impl BitRates {
+    /// Whether the PICC can only do 106 kbps in both directions.
+    pub fn only_106(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether the PICC requires the same bit rate in both directions.
+    pub fn same_in_both_directions(self) -> bool {
+        self.0 & 0x80 != 0
+    }
+
+    /// Bit rates, other than 106 kbps, supported from the PICC to the PCD.
+    pub fn picc_to_pcd(self) -> Vec<BaudRate> {
+        Self::rates(self.0 >> 4)
+    }
+
+    /// Bit rates, other than 106 kbps, supported from the PCD to the PICC.
+    pub fn pcd_to_picc(self) -> Vec<BaudRate> {
+        Self::rates(self.0)
+    }
+
+    fn rates(bits: u8) -> Vec<BaudRate> {
+        [BaudRate::NBR_212, BaudRate::NBR_424, BaudRate::NBR_847]
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| bits & (1 << bit) != 0)
+            .map(|(_, rate)| *rate)
+            .collect()
+    }
+}
+
+/// The Answer To Select of an ISO14443-4 compliant ISO14443A target, without
+/// the leading length byte (TL).
+///
+/// Accessors for the interface bytes fall back to the defaults from
+/// ISO14443-4 section 5.2 when the PICC omits them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Ats {
+    bytes: Vec<u8>,
+}
+
+impl Ats {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Ats { bytes }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The format byte, T0.
+    pub fn t0(&self) -> Option<u8> {
+        self.bytes.first().copied()
+    }
+
+    fn interface_byte(&self, presence_bit: u8) -> Option<u8> {
+        let t0 = self.t0()?;
+        if t0 & presence_bit == 0 {
+            return None;
+        }
+        // interface bytes are in TA, TB, TC order, each only if present
+        let offset = 1 + (t0 & 0x70 & (presence_bit - 1)).count_ones() as usize;
+        self.bytes.get(offset).copied()
+    }
+
+    /// The raw TA(1) interface byte, if present.
+    pub fn ta(&self) -> Option<u8> {
+        self.interface_byte(0x10)
+    }
+
+    /// The raw TB(1) interface byte, if present.
+    pub fn tb(&self) -> Option<u8> {
+        self.interface_byte(0x20)
+    }
+
+    /// The raw TC(1) interface byte, if present.
+    pub fn tc(&self) -> Option<u8> {
+        self.interface_byte(0x40)
+    }
+
+    /// Frame Size for proximity Card Integer.
+    pub fn fsci(&self) -> u8 {
+        self.t0().map_or(2, |t0| t0 & 0x0f)
+    }
+
+    /// Largest frame, in bytes including CRC, the PICC is able to receive.
+    pub fn max_frame_size(&self) -> usize {
+        frame_size(self.fsci())
+    }
+
+    pub fn bit_rates(&self) -> BitRates {
+        BitRates(self.ta().unwrap_or(0x00))
+    }
+
+    /// Frame Waiting time Integer.
+    pub fn fwi(&self) -> u8 {
+        // 15 is RFU and has to be treated as the default
+        match self.tb().map_or(4, |tb| tb >> 4) {
+            15 => 4,
+            fwi => fwi,
+        }
+    }
+
+    /// Start-up Frame Guard time Integer.
+    pub fn sfgi(&self) -> u8 {
+        match self.tb().map_or(0, |tb| tb & 0x0f) {
+            15 => 0,
+            sfgi => sfgi,
+        }
+    }
+
+    /// How long the PICC may take to start responding to a frame.
+    pub fn frame_waiting_time(&self) -> Duration {
+        frame_waiting_time(self.fwi())
+    }
+
+    /// How long the PICC needs after the ATS before it's ready to receive the
+    /// next frame, if it needs any time at all.
+    pub fn startup_frame_guard_time(&self) -> Option<Duration> {
+        match self.sfgi() {
+            0 => None,
+            sfgi => Some(frame_waiting_time(sfgi)),
+        }
+    }
+
+    pub fn nad_supported(&self) -> bool {
+        self.tc().unwrap_or(0x02) & 0x01 != 0
+    }
+
+    pub fn cid_supported(&self) -> bool {
+        self.tc().unwrap_or(0x02) & 0x02 != 0
+    }
+
+    pub fn historical_bytes(&self) -> &[u8] {
+        let start = match self.t0() {
+            Some(t0) => 1 + (t0 & 0x70).count_ones() as usize,
+            None => 0,
+        };
+        self.bytes.get(start..).unwrap_or(&[])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Iso14443aInfo {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub atqa: [u8; 2],
+    pub sak: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub uid: Vec<u8>,
+    pub ats: Option<Ats>,
+}
+
+impl From<ffi::nfc_iso14443a_info> for Iso14443aInfo {
+    fn from(info: ffi::nfc_iso14443a_info) -> Self {
+        let ats = if info.szAtsLen > 0 {
+            Some(Ats::new(read_from(&info.abtAts, info.szAtsLen)))
+        } else {
+            None
+        };
+
+        Iso14443aInfo {
+            atqa: info.abtAtqa,
+            sak: info.btSak,
+            uid: read_from(&info.abtUid, info.szUidLen),
+            ats,
+        }
+    }
+}
+
+impl TryFrom<&Iso14443aInfo> for ffi::nfc_iso14443a_info {
+    type Error = Error;
+
+    fn try_from(info: &Iso14443aInfo) -> Result<Self> {
+        // Safety: this is a plain C struct of integers and byte arrays
+        let mut raw: ffi::nfc_iso14443a_info = unsafe { std::mem::zeroed() };
+        raw.abtAtqa = info.atqa;
+        raw.btSak = info.sak;
+        raw.szUidLen = copy_into(&mut raw.abtUid, &info.uid, "UID")?;
+        if let Some(ats) = &info.ats {
+            raw.szAtsLen = copy_into(&mut raw.abtAts, ats.as_bytes(), "ATS")?;
+        }
+        Ok(raw)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FelicaInfo {
+    pub response_code: u8,
+    /// Manufacture ID (IDm)
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub idm: [u8; 8],
+    /// Manufacture parameter (PMm)
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub pmm: [u8; 8],
+    /// Only present if the polling request asked for it.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex_option"))]
+    pub system_code: Option<[u8; 2]>,
+}
+
+// length byte + response code + IDm + PMm
+const FELICA_RESPONSE_LEN: usize = 18;
+const FELICA_RESPONSE_WITH_SYSCODE_LEN: usize = FELICA_RESPONSE_LEN + 2;
+
+impl From<ffi::nfc_felica_info> for FelicaInfo {
+    fn from(info: ffi::nfc_felica_info) -> Self {
+        FelicaInfo {
+            response_code: info.btResCode,
+            idm: info.abtId,
+            pmm: info.abtPad,
+            system_code: if info.szLen > FELICA_RESPONSE_LEN {
+                Some(info.abtSysCode)
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl TryFrom<&FelicaInfo> for ffi::nfc_felica_info {
+    type Error = Error;
+
+    fn try_from(info: &FelicaInfo) -> Result<Self> {
+        Ok(ffi::nfc_felica_info {
+            szLen: if info.system_code.is_some() {
+                FELICA_RESPONSE_WITH_SYSCODE_LEN
+            } else {
+                FELICA_RESPONSE_LEN
+            },
+            btResCode: info.response_code,
+            abtId: info.idm,
+            abtPad: info.pmm,
+            abtSysCode: info.system_code.unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Iso14443bInfo {
+    /// Pseudo-Unique PICC Identifier
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub pupi: [u8; 4],
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub application_data: [u8; 4],
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub protocol_info: [u8; 3],
+    pub card_identifier: u8,
+}
+
+impl From<ffi::nfc_iso14443b_info> for Iso14443bInfo {
+    fn from(info: ffi::nfc_iso14443b_info) -> Self {
+        Iso14443bInfo {
+            pupi: info.abtPupi,
+            application_data: info.abtApplicationData,
+            protocol_info: info.abtProtocolInfo,
+            card_identifier: info.ui8CardIdentifier,
+        }
+    }
+}
+
+impl TryFrom<&Iso14443bInfo> for ffi::nfc_iso14443b_info {
+    type Error = Error;
+
+    fn try_from(info: &Iso14443bInfo) -> Result<Self> {
+        Ok(ffi::nfc_iso14443b_info {
+            abtPupi: info.pupi,
+            abtApplicationData: info.application_data,
+            abtProtocolInfo: info.protocol_info,
+            ui8CardIdentifier: info.card_identifier,
+        })
+    }
+}
+
+impl Iso14443bInfo {
+    pub fn protocol(&self) -> ProtocolInfo {
+        ProtocolInfo(self.protocol_info)
+    }
+}
+
+/// The protocol info field of an ATQB, ISO14443-3 section 7.9.4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProtocolInfo(pub [u8; 3]);
+
+impl ProtocolInfo {
+    pub fn bit_rates(self) -> BitRates {
+        BitRates(self.0[0])
+    }
+
+    /// Frame Size for proximity Card Integer.
+    pub fn fsci(self) -> u8 {
+        self.0[1] >> 4
+    }
+
+    /// Largest frame, in bytes including CRC, the PICC is able to receive.
+    pub fn max_frame_size(self) -> usize {
+        frame_size(self.fsci())
+    }
+
+    /// The raw protocol type nibble.
+    pub fn protocol_type(self) -> u8 {
+        self.0[1] & 0x0f
+    }
+
+    pub fn iso14443_4_compliant(self) -> bool {
+        self.protocol_type() & 0x01 != 0
+    }
+
+    /// Frame Waiting time Integer.
+    pub fn fwi(self) -> u8 {
+        match self.0[2] >> 4 {
+            15 => 4,
+            fwi => fwi,
+        }
+    }
+
+    /// How long the PICC may take to start responding to a frame.
+    pub fn frame_waiting_time(self) -> Duration {
+        frame_waiting_time(self.fwi())
+    }
+
+    /// Application Data Coding, whether the application data holds a
+    /// proprietary value or AFI, CRC and application count.
+    pub fn adc(self) -> u8 {
+        (self.0[2] >> 2) & 0x03
+    }
+
+    pub fn nad_supported(self) -> bool {
+        self.0[2] & 0x02 != 0
+    }
+
+    pub fn cid_supported(self) -> bool {
+        self.0[2] & 0x01 != 0
+    }
+}
+
+/// ISO14443B' (Innovatron) target information.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Iso14443biInfo {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub div: [u8; 4],
+    pub ver_log: u8,
+    pub config: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub atr: Vec<u8>,
+}
+
+impl From<ffi::nfc_iso14443bi_info> for Iso14443biInfo {
+    fn from(info: ffi::nfc_iso14443bi_info) -> Self {
+        Iso14443biInfo {
+            div: info.abtDIV,
+            ver_log: info.btVerLog,
+            config: info.btConfig,
+            atr: read_from(&info.abtAtr, info.szAtrLen),
+        }
+    }
+}
+
+impl TryFrom<&Iso14443biInfo> for ffi::nfc_iso14443bi_info {
+    type Error = Error;
+
+    fn try_from(info: &Iso14443biInfo) -> Result<Self> {
+        let mut raw: ffi::nfc_iso14443bi_info = unsafe { std::mem::zeroed() };
+        raw.abtDIV = info.div;
+        raw.btVerLog = info.ver_log;
+        raw.btConfig = info.config;
+        raw.szAtrLen = copy_into(&mut raw.abtAtr, &info.atr, "ATR")?;
+        Ok(raw)
+    }
+}
+
+/// HID iClass (ISO14443B' variant) target information.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Iso14443biclassInfo {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub uid: [u8; 8],
+}
+
+impl From<ffi::nfc_iso14443biclass_info> for Iso14443biclassInfo {
+    fn from(info: ffi::nfc_iso14443biclass_info) -> Self {
+        Iso14443biclassInfo { uid: info.abtUID }
+    }
+}
+
+impl TryFrom<&Iso14443biclassInfo> for ffi::nfc_iso14443biclass_info {
+    type Error = Error;
+
+    fn try_from(info: &Iso14443biclassInfo) -> Result<Self> {
+        Ok(ffi::nfc_iso14443biclass_info { abtUID: info.uid })
+    }
+}
+
+/// ST SRx target information.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Iso14443b2srInfo {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub uid: [u8; 8],
+}
+
+impl From<ffi::nfc_iso14443b2sr_info> for Iso14443b2srInfo {
+    fn from(info: ffi::nfc_iso14443b2sr_info) -> Self {
+        Iso14443b2srInfo { uid: info.abtUID }
+    }
+}
+
+impl TryFrom<&Iso14443b2srInfo> for ffi::nfc_iso14443b2sr_info {
+    type Error = Error;
+
+    fn try_from(info: &Iso14443b2srInfo) -> Result<Self> {
+        Ok(ffi::nfc_iso14443b2sr_info { abtUID: info.uid })
+    }
+}
+
+/// ASK CTx target information.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Iso14443b2ctInfo {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub uid: [u8; 4],
+    pub product_code: u8,
+    pub fab_code: u8,
+}
+
+impl From<ffi::nfc_iso14443b2ct_info> for Iso14443b2ctInfo {
+    fn from(info: ffi::nfc_iso14443b2ct_info) -> Self {
+        Iso14443b2ctInfo {
+            uid: info.abtUID,
+            product_code: info.btProdCode,
+            fab_code: info.btFabCode,
+        }
+    }
+}
+
+impl TryFrom<&Iso14443b2ctInfo> for ffi::nfc_iso14443b2ct_info {
+    type Error = Error;
+
+    fn try_from(info: &Iso14443b2ctInfo) -> Result<Self> {
+        Ok(ffi::nfc_iso14443b2ct_info {
+            abtUID: info.uid,
+            btProdCode: info.product_code,
+            btFabCode: info.fab_code,
+        })
+    }
+}
+
+/// Innovision Jewel / Topaz target information.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JewelInfo {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub sens_res: [u8; 2],
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub id: [u8; 4],
+}
+
+impl From<ffi::nfc_jewel_info> for JewelInfo {
+    fn from(info: ffi::nfc_jewel_info) -> Self {
+        JewelInfo {
+            sens_res: info.btSensRes,
+            id: info.btId,
+        }
+    }
+}
+
+impl TryFrom<&JewelInfo> for ffi::nfc_jewel_info {
+    type Error = Error;
+
+    fn try_from(info: &JewelInfo) -> Result<Self> {
+        Ok(ffi::nfc_jewel_info {
+            btSensRes: info.sens_res,
+            btId: info.id,
+        })
+    }
+}
+
+/// Thinfilm NFC Barcode target information.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BarcodeInfo {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub data: Vec<u8>,
+}
+
+impl From<ffi::nfc_barcode_info> for BarcodeInfo {
+    fn from(info: ffi::nfc_barcode_info) -> Self {
+        BarcodeInfo {
+            data: read_from(&info.abtData, info.szDataLen),
+        }
+    }
+}
+
+impl TryFrom<&BarcodeInfo> for ffi::nfc_barcode_info {
+    type Error = Error;
+
+    fn try_from(info: &BarcodeInfo) -> Result<Self> {
+        let mut raw: ffi::nfc_barcode_info = unsafe { std::mem::zeroed() };
+        raw.szDataLen = copy_into(&mut raw.abtData, &info.data, "Barcode data")?;
+        Ok(raw)
+    }
+}
+
+/// NFCIP-1 Data Exchange Protocol information, used both to describe DEP
+/// targets and to configure the initiator in `select_dep_target`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DepInfo {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub nfcid3: [u8; 10],
+    pub did: u8,
+    /// Supported send bit rate
+    pub bs: u8,
+    /// Supported receive bit rate
+    pub br: u8,
+    pub timeout: u8,
+    pub pp: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::hex"))]
+    pub general_bytes: Vec<u8>,
+    pub mode: crate::DepMode,
+}
+
+impl From<ffi::nfc_dep_info> for DepInfo {
+    fn from(info: ffi::nfc_dep_info) -> Self {
+        DepInfo {
+            nfcid3: info.abtNFCID3,
+            did: info.btDID,
+            bs: info.btBS,
+            br: info.btBR,
+            timeout: info.btTO,
+            pp: info.btPP,
+            general_bytes: read_from(&info.abtGB, info.szGB),
+            mode: info.ndm,
+        }
+    }
+}
+
+impl TryFrom<&DepInfo> for ffi::nfc_dep_info {
+    type Error = Error;
+
+    fn try_from(info: &DepInfo) -> Result<Self> {
+        let mut raw: ffi::nfc_dep_info = unsafe { std::mem::zeroed() };
+        raw.abtNFCID3 = info.nfcid3;
+        raw.btDID = info.did;
+        raw.btBS = info.bs;
+        raw.btBR = info.br;
+        raw.btTO = info.timeout;
+        raw.btPP = info.pp;
+        raw.szGB = copy_into(&mut raw.abtGB, &info.general_bytes, "General bytes")?;
+        raw.ndm = info.mode;
+        Ok(raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_sizes_and_times() {
+        assert_eq!(frame_size(0), 16);
+        assert_eq!(frame_size(8), 256);
+        // RFU
+        assert_eq!(frame_size(0x0c), 256);
+        assert_eq!(frame_waiting_time(0), Duration::from_nanos(302_064));
+        assert_eq!(frame_waiting_time(4), Duration::from_nanos(4_833_038));
+        assert_eq!(frame_waiting_time(8), Duration::from_nanos(77_328_613));
+    }
+
+    #[test]
+    fn desfire_ats() {
+        // a DESFire EV1's ATS, TL left out
+        let ats = Ats::new(vec![0x75, 0x77, 0x81, 0x02, 0x80]);
+        assert_eq!(ats.ta(), Some(0x77));
+        assert_eq!(ats.tb(), Some(0x81));
+        assert_eq!(ats.tc(), Some(0x02));
+        assert_eq!(ats.fsci(), 5);
+        assert_eq!(ats.max_frame_size(), 64);
+
+        let rates = ats.bit_rates();
+        assert!(!rates.only_106());
+        assert!(!rates.same_in_both_directions());
+        let all = vec![BaudRate::NBR_212, BaudRate::NBR_424, BaudRate::NBR_847];
+        assert_eq!(rates.picc_to_pcd(), all);
+        assert_eq!(rates.pcd_to_picc(), all);
+
+        assert_eq!(ats.fwi(), 8);
+        assert_eq!(ats.sfgi(), 1);
+        assert_eq!(ats.frame_waiting_time(), frame_waiting_time(8));
+        assert_eq!(ats.startup_frame_guard_time(), Some(frame_waiting_time(1)));
+        assert!(!ats.nad_supported());
+        assert!(ats.cid_supported());
+        assert_eq!(ats.historical_bytes(), [0x80]);
+    }
+
+    #[test]
+    fn ats_without_interface_bytes() {
+        let ats = Ats::new(vec![0x08, 0xc1, 0x05]);
+        assert_eq!((ats.ta(), ats.tb(), ats.tc()), (None, None, None));
+        assert_eq!(ats.max_frame_size(), 256);
+        assert!(ats.bit_rates().only_106());
+        assert_eq!(ats.fwi(), 4);
+        assert_eq!(ats.startup_frame_guard_time(), None);
+        assert!(!ats.nad_supported());
+        assert!(ats.cid_supported());
+        assert_eq!(ats.historical_bytes(), [0xc1, 0x05]);
+
+        // only TC, which comes right after T0
+        let ats = Ats::new(vec![0x48, 0x01]);
+        assert_eq!(ats.tc(), Some(0x01));
+        assert!(ats.nad_supported());
+        assert!(!ats.cid_supported());
+        assert!(ats.historical_bytes().is_empty());
+
+        // TB present but cut short
+        let ats = Ats::new(vec![0x38, 0x80]);
+        assert_eq!(ats.ta(), Some(0x80));
+        assert_eq!(ats.tb(), None);
+        assert!(ats.bit_rates().same_in_both_directions());
+        assert!(ats.historical_bytes().is_empty());
+
+        let empty = Ats::new(Vec::new());
+        assert_eq!(empty.t0(), None);
+        assert_eq!(empty.max_frame_size(), 32);
+        assert_eq!(empty.fwi(), 4);
+        assert!(empty.historical_bytes().is_empty());
+    }
+
+    #[test]
+    fn ats_rfu_timings() {
+        let ats = Ats::new(vec![0x20, 0xff]);
+        assert_eq!(ats.fwi(), 4);
+        assert_eq!(ats.sfgi(), 0);
+        assert_eq!(ats.startup_frame_guard_time(), None);
+    }
+
+    #[test]
+    fn atqb_protocol_info() {
+        let info = ProtocolInfo([0x00, 0x81, 0x71]);
+        assert!(info.bit_rates().only_106());
+        assert_eq!(info.fsci(), 8);
+        assert_eq!(info.max_frame_size(), 256);
+        assert_eq!(info.protocol_type(), 1);
+        assert!(info.iso14443_4_compliant());
+        assert_eq!(info.fwi(), 7);
+        assert_eq!(info.frame_waiting_time(), frame_waiting_time(7));
+        assert_eq!(info.adc(), 0);
+        assert!(!info.nad_supported());
+        assert!(info.cid_supported());
+
+        let info = ProtocolInfo([0xa3, 0x50, 0xf6]);
+        let rates = info.bit_rates();
+        assert!(rates.same_in_both_directions());
+        assert_eq!(rates.picc_to_pcd(), [BaudRate::NBR_424]);
+        assert_eq!(rates.pcd_to_picc(), [BaudRate::NBR_212, BaudRate::NBR_424]);
+        assert_eq!(info.max_frame_size(), 64);
+        assert!(!info.iso14443_4_compliant());
+        // 15 is RFU
+        assert_eq!(info.fwi(), 4);
+        assert_eq!(info.adc(), 1);
+        assert!(info.nad_supported());
+        assert!(!info.cid_supported());
+    }
+}
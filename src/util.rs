@@ -1,3 +1,6 @@
+use crate::ffi;
+use crate::{BaudRate, ModulationType};
+
 #[cfg(target_arch = "arm")]
 pub fn str_to_connarr(connstring: &str) -> [u8; 1024] {
     let end = std::cmp::min(1024, connstring.len());
@@ -14,3 +17,79 @@ pub fn str_to_connarr(connstring: &str) -> [i8; 1024] {
         .copy_from_slice(&unsafe { &*(connstring.as_bytes() as *const _ as *const [i8]) }[0..end]);
     connarr
 }
+
+/// Reads a libnfc list of enum values terminated by 0.
+///
+/// Safety: `list` must point to such a list.
+pub unsafe fn read_terminated<T>(list: *const u32, convert: fn(u32) -> Option<T>) -> Vec<T> {
+    let mut values = Vec::new();
+    let mut cursor = list;
+    while *cursor != 0 {
+        values.extend(convert(*cursor));
+        cursor = cursor.add(1);
+    }
+    values
+}
+
+pub fn modulation_type_from_raw(raw: u32) -> Option<ModulationType> {
+    use ffi::nfc_modulation_type::*;
+
+    [
+        NMT_ISO14443A,
+        NMT_JEWEL,
+        NMT_ISO14443B,
+        NMT_ISO14443BI,
+        NMT_ISO14443B2SR,
+        NMT_ISO14443B2CT,
+        NMT_FELICA,
+        NMT_DEP,
+        NMT_BARCODE,
+        NMT_ISO14443BICLASS,
+    ]
+    .iter()
+    .copied()
+    .find(|modulation_type| *modulation_type as u32 == raw)
+}
+
+pub fn baud_rate_from_raw(raw: u32) -> Option<BaudRate> {
+    [
+        BaudRate::NBR_106,
+        BaudRate::NBR_212,
+        BaudRate::NBR_424,
+        BaudRate::NBR_847,
+    ]
+    .iter()
+    .copied()
+    .find(|baud_rate| *baud_rate as u32 == raw)
+}
+
+#[cfg(any(feature = "serde", feature = "serde_json"))]
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| format!("invalid hex string {:?}", hex))
+        })
+        .collect()
+}
+
+/// The ISO14443A CRC, low byte first, for frames sent with `NP_HANDLE_CRC`
+/// disabled.
+pub(crate) fn crc_a(data: &[u8]) -> [u8; 2] {
+    let mut crc: u16 = 0x6363;
+    for &byte in data {
+        let mut byte = byte ^ crc as u8;
+        byte ^= byte << 4;
+        let byte = u16::from(byte);
+        crc = (crc >> 8) ^ (byte << 8) ^ (byte << 3) ^ (byte >> 4);
+    }
+    crc.to_le_bytes()
+}